@@ -1,12 +1,15 @@
 use std::time::Duration;
 
-use iot_system::config::Mqtt;
+use iot_system::{config::Mqtt, domain::AgentId};
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize)]
 pub struct Configuration {
     mqtt: Mqtt,
     delay: f64,
+    /// Sent with each sample, so that the vehicles publishing to one topic are told apart
+    #[serde(default)]
+    agent_id: Option<AgentId>,
 }
 
 impl Configuration {
@@ -17,6 +20,10 @@ impl Configuration {
     pub fn delay(&self) -> Duration {
         Duration::from_secs_f64(self.delay)
     }
+
+    pub fn agent_id(&self) -> Option<&AgentId> {
+        self.agent_id.as_ref()
+    }
 }
 
 impl iot_system::config::TryRead<'_> for Configuration {}
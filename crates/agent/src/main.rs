@@ -1,9 +1,15 @@
 use std::time::Duration;
 
 use color_eyre::Result;
-use iot_system::{config::TryRead, domain::Agent, setup_tracing};
+use iot_system::{
+    codec::Encoding,
+    config::TryRead,
+    domain::{Agent, AgentId, IdempotencyKey},
+    setup_tracing,
+};
 use mqtt::AsyncClient;
 use tracing::instrument;
+use uuid::Uuid;
 
 use crate::{
     config::Configuration,
@@ -22,9 +28,15 @@ async fn main() -> Result<()> {
     let client = iot_system::mqtt::connect(config.mqtt().to_owned()).await?;
 
     let datasource = FileDatasource::new("./data/accelerometer.csv", "./data/gps.csv");
-    let result = publish(client, &config.mqtt().topic(), datasource, config.delay()).await;
-
-    result.map_err(Into::into)
+    publish(
+        client,
+        &config.mqtt().topic(),
+        config.mqtt().encoding,
+        config.agent_id(),
+        datasource,
+        config.delay(),
+    )
+    .await
 }
 
 #[cfg(all(feature = "async-read", not(feature = "sync-read")))]
@@ -32,6 +44,8 @@ async fn main() -> Result<()> {
 async fn publish(
     client: AsyncClient,
     topic: &str,
+    encoding: Encoding,
+    agent_id: Option<&AgentId>,
     datasource: FileDatasource<state::New>,
     delay: Duration,
 ) -> Result<()> {
@@ -43,8 +57,11 @@ async fn publish(
     tokio::spawn(read_data(datasource, data_reader_sender));
 
     while let Some(data) = data_reader_receiver.recv().await {
+        let data = data
+            .with_idempotency_key(idempotency_key())
+            .with_agent_id(agent_id.cloned());
         tracing::debug!("Data received from the channel. Sending to the broker: {data:#?}");
-        let message = mqtt::Message::new(topic, serde_json::to_vec(&data)?, 0);
+        let message = encoding.message(topic, &data)?;
         if let Err(err) = client.publish(message).await {
             tracing::error!("Failed to send message to topic {topic}: {err}")
         } else {
@@ -62,6 +79,8 @@ async fn publish(
 async fn publish(
     client: AsyncClient,
     topic: &str,
+    encoding: Encoding,
+    agent_id: Option<&AgentId>,
     datasource: FileDatasource<state::New>,
     delay: Duration,
 ) -> Result<()> {
@@ -72,14 +91,16 @@ async fn publish(
     loop {
         interval.tick().await;
         let data: Agent = match datasource.read() {
-            Ok(data) => data,
+            Ok(data) => data
+                .with_idempotency_key(idempotency_key())
+                .with_agent_id(agent_id.cloned()),
             Err(err) => {
                 tracing::error!("Failed to read data from the datasource: {}", err);
                 continue;
             }
         };
         tracing::debug!("Sending data to the broker: {data:#?}");
-        let message = mqtt::Message::new(topic, serde_json::to_vec(&data)?, 0);
+        let message = encoding.message(topic, &data)?;
         if let Err(err) = client.publish(message).await {
             tracing::error!("Failed to send message to topic {topic}: {err}")
         };
@@ -91,6 +112,8 @@ async fn publish(
 async fn publish(
     _client: AsyncClient,
     _topic: &str,
+    _encoding: Encoding,
+    _agent_id: Option<&AgentId>,
     _datasource: FileDatasource<state::New>,
     _delay: Duration,
 ) -> Result<()> {
@@ -101,12 +124,20 @@ async fn publish(
 async fn publish(
     _client: AsyncClient,
     _topic: &str,
+    _encoding: Encoding,
+    _agent_id: Option<&AgentId>,
     _datasource: FileDatasource<state::New>,
     _delay: Duration,
 ) -> Result<()> {
     panic!("You must enable only one of the `async-read` or `sync-read` features to use the `publish` function.")
 }
 
+/// Random key identifying the sample, so that the store doesn't duplicate it
+/// if the upstream services retry the submission
+fn idempotency_key() -> IdempotencyKey {
+    IdempotencyKey::try_from(Uuid::new_v4().to_string()).expect("UUID is a valid idempotency key")
+}
+
 #[instrument(skip(datasource, data_reader_sender))]
 async fn read_data(
     mut datasource: FileDatasource<state::Reading>,
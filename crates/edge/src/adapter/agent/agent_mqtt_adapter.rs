@@ -1,33 +1,43 @@
 use std::sync::Arc;
 
-use iot_system::{config::Mqtt, domain::Agent};
+use iot_system::{
+    codec::{DecodeError, Encoding},
+    config::Mqtt,
+    domain::Agent,
+};
 
 pub struct AgentMqttAdapter {
     client: mqtt::AsyncClient,
     topic: Arc<str>,
-    sender: tokio::sync::mpsc::UnboundedSender<Agent>,
+    encoding: Encoding,
+    sender: tokio::sync::mpsc::UnboundedSender<(String, Agent)>,
 }
 
 impl AgentMqttAdapter {
     pub async fn new(
         config: Mqtt,
-        sender: tokio::sync::mpsc::UnboundedSender<Agent>,
+        sender: tokio::sync::mpsc::UnboundedSender<(String, Agent)>,
     ) -> mqtt::Result<Self> {
         let topic = config.topic();
+        let encoding = config.encoding;
         iot_system::mqtt::connect(config).await.map(|client| Self {
             client,
             topic,
+            encoding,
             sender,
         })
     }
 
+    /// Sends the data received from the agents along with the topics it was published to.
+    /// The topic identifies the agent if the configured topic is a wildcard,
+    /// like `agents/+/data`
     pub async fn listen_for_data(mut self) -> Result<Self, SendError> {
         self.client.subscribe(self.topic.to_string(), 0).await?;
         let messages = self.client.get_stream(None);
         while let Ok(Some(message)) = messages.recv().await {
             if self
                 .sender
-                .send(serde_json::from_slice(message.payload())?)
+                .send((message.topic().to_owned(), self.encoding.decode(&message)?))
                 .is_err()
             {
                 break;
@@ -46,10 +56,10 @@ pub enum SendError {
         #[source]
         mqtt::Error,
     ),
-    #[error("Failed to deserialize the message: {0}")]
-    Serde(
+    #[error("Failed to decode the message: {0}")]
+    Decode(
         #[from]
         #[source]
-        serde_json::Error,
+        DecodeError,
     ),
 }
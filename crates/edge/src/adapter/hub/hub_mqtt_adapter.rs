@@ -1,8 +1,11 @@
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use iot_system::{config::Mqtt, domain::ProcessedAgent};
-use mqtt::Message;
+use iot_system::{
+    codec::{EncodeError, Encoding},
+    config::Mqtt,
+    domain::ProcessedAgent,
+};
 use tracing::instrument;
 
 use crate::adapter::hub::HubGateway;
@@ -10,15 +13,19 @@ use crate::adapter::hub::HubGateway;
 pub struct HubMqttAdapter {
     client: mqtt::AsyncClient,
     topic: Arc<str>,
+    encoding: Encoding,
 }
 
 impl HubMqttAdapter {
     #[instrument]
     pub async fn new(config: Mqtt) -> mqtt::Result<Self> {
         let topic = config.topic();
-        iot_system::mqtt::connect(config)
-            .await
-            .map(|client| Self { client, topic })
+        let encoding = config.encoding;
+        iot_system::mqtt::connect(config).await.map(|client| Self {
+            client,
+            topic,
+            encoding,
+        })
     }
 }
 
@@ -29,11 +36,7 @@ impl HubGateway for HubMqttAdapter {
     #[instrument(skip(self))]
     async fn save_data(&mut self, processed_data: ProcessedAgent) -> Result<(), Self::Error> {
         self.client
-            .publish(Message::new(
-                self.topic.to_string(),
-                serde_json::to_vec(&processed_data)?,
-                0,
-            ))
+            .publish(self.encoding.message(&self.topic, &processed_data)?)
             .await
             .map_err(Into::into)
     }
@@ -47,10 +50,10 @@ pub enum SendError {
         #[source]
         mqtt::Error,
     ),
-    #[error("Failed to serialize the message: {0}")]
-    Serde(
+    #[error("Failed to encode the message: {0}")]
+    Encode(
         #[from]
         #[source]
-        serde_json::Error,
+        EncodeError,
     ),
 }
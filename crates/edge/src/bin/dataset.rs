@@ -0,0 +1,216 @@
+use std::{fs::File, io, path::PathBuf};
+
+use clap::{Parser, Subcommand};
+use color_eyre::{eyre::OptionExt, Result};
+use edge::{
+    config::Configuration,
+    dataset::{self, Labelled, LabelledWindow},
+    features::Feature,
+    Filters,
+};
+use iot_system::{
+    config::TryRead,
+    domain::{ProcessedAgent, RoadState},
+    proto,
+};
+use serde::Deserialize;
+
+/// Exports the features of the windows of the labelled samples as a CSV dataset for training
+/// the classifiers. The windows and the features are the same as of the edge, as configured
+#[derive(Debug, Parser)]
+struct Args {
+    /// File to write the dataset to. The standard output if absent
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    #[command(subcommand)]
+    source: Source,
+}
+
+#[derive(Debug, Subcommand)]
+enum Source {
+    /// The trips in the store, labelled by the road states corrected by hand, as in the audit
+    /// trail. The samples never corrected are labelled by the classifier, so they are skipped.
+    /// The samples are stored filtered by the edge, so they are not filtered again
+    Store {
+        /// Base URL of the HTTP API of the store
+        #[arg(long, default_value = "http://localhost:8080")]
+        url: String,
+        /// Key with the `admin` scope, to read the audit trail
+        #[arg(long, env = "STORE_API_KEY")]
+        api_key: String,
+    },
+    /// The CSV files published by the agent, labelled by a file with a `road_state` column,
+    /// one row per sample. The samples are filtered as by the edge
+    Files {
+        #[arg(long, default_value = "../agent/data/accelerometer.csv")]
+        accelerometer: PathBuf,
+        #[arg(long, default_value = "../agent/data/gps.csv")]
+        gps: PathBuf,
+        #[arg(long)]
+        labels: PathBuf,
+        /// Rate at which the samples were taken, as the files don't have timestamps
+        #[arg(long, default_value_t = 10.0)]
+        sample_rate_hz: f64,
+    },
+}
+
+#[derive(Deserialize)]
+struct TripId {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct Point {
+    id: i64,
+    #[serde(flatten)]
+    data: ProcessedAgent,
+}
+
+/// Entry of the audit trail of a processed agent data, the parts telling a correction apart
+#[derive(Deserialize)]
+struct AuditEntry {
+    action: AuditAction,
+    before: Option<RoadStateOf>,
+    after: Option<RoadStateOf>,
+}
+
+#[derive(Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum AuditAction {
+    Update,
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, PartialEq)]
+struct RoadStateOf {
+    road_state: RoadState,
+}
+
+/// Size of the pages of the trips read from the store, the largest allowed
+const PAGE_SIZE: u8 = 20;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    color_eyre::install()?;
+    let args = Args::parse();
+    let config = Configuration::try_read()?;
+    let windowing = config
+        .pipeline
+        .windowing
+        .as_ref()
+        .ok_or_eyre("The windowing must be configured to compute the features")?;
+
+    // Each sequence is windowed on its own: a run of the corrected samples of one trip,
+    // or the samples of the files
+    let sequences = match args.source {
+        Source::Store { url, api_key } => read_trips(&url, &api_key).await?,
+        Source::Files {
+            accelerometer,
+            gps,
+            labels,
+            sample_rate_hz,
+        } => {
+            let samples = dataset::read_agent_files(&accelerometer, &gps, &labels, sample_rate_hz)?;
+            let samples = match &config.pipeline.filtering {
+                Some(filtering) => dataset::filter(samples, &mut Filters::new(filtering)?, ""),
+                None => samples,
+            };
+            vec![(labels.display().to_string(), samples)]
+        }
+    };
+
+    let output: Box<dyn io::Write> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut writer = csv::Writer::from_writer(output);
+    writer.write_record(
+        ["source", "start", "end"]
+            .into_iter()
+            .chain(Feature::ALL.map(Feature::name))
+            .chain(["road_state"]),
+    )?;
+    for (source, samples) in sequences {
+        for LabelledWindow {
+            start,
+            end,
+            features,
+            road_state,
+        } in dataset::windows(samples, windowing)
+        {
+            writer.write_record(
+                [source.clone(), start.to_rfc3339(), end.to_rfc3339()]
+                    .into_iter()
+                    .chain(Feature::ALL.map(|feature| features.get(feature).to_string()))
+                    .chain([label(road_state).to_owned()]),
+            )?;
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Reads the samples of each trip corrected by hand, as runs of the consecutive ones,
+/// named by the ID of the trip
+async fn read_trips(url: &str, api_key: &str) -> Result<Vec<(String, Vec<Labelled>)>> {
+    let client = reqwest::Client::new();
+    let get = |path: String| {
+        client
+            .get(format!("{url}/api/{path}"))
+            .header("X-API-Key", api_key)
+    };
+
+    let mut runs = Vec::new();
+    for page in 1.. {
+        let page: Vec<TripId> = get(format!("trips?page={page}&size={PAGE_SIZE}"))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if page.is_empty() {
+            break;
+        }
+        for TripId { id } in page {
+            let points: Vec<Point> = get(format!("trips/{id}/points"))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            let mut run = Vec::new();
+            for point in points {
+                let history: Vec<AuditEntry> =
+                    get(format!("processed-agent-data/{}/history", point.id))
+                        .send()
+                        .await?
+                        .error_for_status()?
+                        .json()
+                        .await?;
+                // The current road state is the one set by hand, if it was ever changed
+                let corrected = history.iter().any(|entry| {
+                    entry.action == AuditAction::Update && entry.before != entry.after
+                });
+                if corrected {
+                    run.push(Labelled {
+                        road_state: point.data.road_state(),
+                        data: point.data.agent_data().clone(),
+                    });
+                } else if !run.is_empty() {
+                    runs.push((format!("trip {id}"), std::mem::take(&mut run)));
+                }
+            }
+            if !run.is_empty() {
+                runs.push((format!("trip {id}"), run));
+            }
+        }
+    }
+
+    Ok(runs)
+}
+
+fn label(road_state: RoadState) -> &'static str {
+    proto::RoadState::from(road_state).as_str_name()
+}
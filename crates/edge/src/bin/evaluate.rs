@@ -0,0 +1,196 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use color_eyre::Result;
+use edge::{
+    config::{Configuration, Pipeline},
+    dataset::{self, Labelled},
+    Processor,
+};
+use iot_system::{config::TryRead, domain::RoadState, proto};
+
+/// Replays the labelled samples through the pipelines of the edge and reports
+/// how well each of them classifies the road, side by side
+#[derive(Debug, Parser)]
+struct Args {
+    #[arg(long, default_value = "../agent/data/accelerometer.csv")]
+    accelerometer: PathBuf,
+    #[arg(long, default_value = "../agent/data/gps.csv")]
+    gps: PathBuf,
+    /// File with a `road_state` column, one row per sample
+    #[arg(long)]
+    labels: PathBuf,
+    /// Rate at which the samples were taken, as the files don't have timestamps
+    #[arg(long, default_value_t = 10.0)]
+    sample_rate_hz: f64,
+    /// TOML file of a pipeline to evaluate, with the `processing`, `calibration`, `filtering`
+    /// and `windowing` sections as in the configuration of the edge. May be repeated.
+    /// The pipeline of the configuration of the edge if absent
+    #[arg(long = "pipeline")]
+    pipelines: Vec<PathBuf>,
+}
+
+const ROAD_STATES: [RoadState; 2] = [RoadState::Smooth, RoadState::Rough];
+
+/// Outcome of a pipeline over the samples
+#[derive(Debug, Default)]
+struct Evaluation {
+    /// Number of the processed data by the labelled and the classified road state
+    confusion: [[usize; ROAD_STATES.len()]; ROAD_STATES.len()],
+    /// Time taken to process each sample
+    latencies: Vec<Duration>,
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+    let args = Args::parse();
+
+    let pipelines = if args.pipelines.is_empty() {
+        vec![("edge".to_owned(), Configuration::try_read()?.pipeline)]
+    } else {
+        args.pipelines
+            .iter()
+            .map(|path| {
+                let pipeline = config::Config::builder()
+                    .add_source(config::File::from(path.as_path()))
+                    .build()?
+                    .try_deserialize::<Pipeline>()?;
+                let name = path.file_stem().unwrap_or(path.as_os_str());
+                Ok((name.to_string_lossy().into_owned(), pipeline))
+            })
+            .collect::<Result<_>>()?
+    };
+    let samples = dataset::read_agent_files(
+        &args.accelerometer,
+        &args.gps,
+        &args.labels,
+        args.sample_rate_hz,
+    )?;
+    // The samples are told apart by their timestamps, as they are all different
+    let labels: HashMap<_, _> = samples
+        .iter()
+        .map(|sample| (sample.data.timestamp(), sample.road_state))
+        .collect();
+
+    let mut evaluations = Vec::with_capacity(pipelines.len());
+    for (name, pipeline) in pipelines {
+        let mut processor = Processor::new(pipeline)?;
+        let mut evaluation = Evaluation::default();
+        for Labelled { data, .. } in samples.iter().cloned() {
+            let start = Instant::now();
+            let processed = processor.process("", data);
+            evaluation.latencies.push(start.elapsed());
+            for processed_data in processed {
+                let label = labels[&processed_data.agent_data().timestamp()];
+                evaluation.confusion[label as usize][processed_data.road_state() as usize] += 1;
+            }
+        }
+        evaluations.push((name, evaluation));
+    }
+
+    print_report(samples.len(), &evaluations);
+
+    Ok(())
+}
+
+fn print_report(samples: usize, evaluations: &[(String, Evaluation)]) {
+    let width = evaluations
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0)
+        .max(12);
+    let row = |title: &str, value: &dyn Fn(&Evaluation) -> String| {
+        print!("{title:<28}");
+        for (_, evaluation) in evaluations {
+            print!(" {:>width$}", value(evaluation));
+        }
+        println!();
+    };
+
+    print!("{:<28}", "");
+    for (name, _) in evaluations {
+        print!(" {name:>width$}");
+    }
+    println!();
+    row("samples", &|_| samples.to_string());
+    row("classified", &|evaluation| {
+        evaluation
+            .confusion
+            .iter()
+            .flatten()
+            .sum::<usize>()
+            .to_string()
+    });
+    row("accuracy", &|evaluation| {
+        let correct = (0..ROAD_STATES.len())
+            .map(|state| evaluation.confusion[state][state])
+            .sum::<usize>();
+        ratio(correct, evaluation.confusion.iter().flatten().sum())
+    });
+    for state in ROAD_STATES {
+        let name = label(state);
+        row(&format!("{name} precision"), &|evaluation| {
+            let classified = evaluation.confusion.iter().map(|row| row[state as usize]);
+            ratio(
+                evaluation.confusion[state as usize][state as usize],
+                classified.sum(),
+            )
+        });
+        row(&format!("{name} recall"), &|evaluation| {
+            ratio(
+                evaluation.confusion[state as usize][state as usize],
+                evaluation.confusion[state as usize].iter().sum(),
+            )
+        });
+    }
+    for labelled in ROAD_STATES {
+        for classified in ROAD_STATES {
+            row(
+                &format!("{} as {}", label(labelled), label(classified)),
+                &|evaluation| {
+                    evaluation.confusion[labelled as usize][classified as usize].to_string()
+                },
+            );
+        }
+    }
+    row("latency mean (µs)", &|evaluation| {
+        let total = evaluation.latencies.iter().sum::<Duration>();
+        micros(total / evaluation.latencies.len().max(1) as u32)
+    });
+    row("latency p95 (µs)", &|evaluation| {
+        let mut latencies = evaluation.latencies.clone();
+        latencies.sort_unstable();
+        let index = (latencies.len() * 95 / 100).min(latencies.len().saturating_sub(1));
+        latencies.get(index).copied().map_or("-".to_owned(), micros)
+    });
+    row("latency max (µs)", &|evaluation| {
+        evaluation
+            .latencies
+            .iter()
+            .max()
+            .copied()
+            .map_or("-".to_owned(), micros)
+    });
+}
+
+/// The ratio, or a dash if the denominator is zero
+fn ratio(numerator: usize, denominator: usize) -> String {
+    if denominator == 0 {
+        "-".to_owned()
+    } else {
+        format!("{:.3}", numerator as f64 / denominator as f64)
+    }
+}
+
+fn micros(duration: Duration) -> String {
+    format!("{:.1}", duration.as_secs_f64() * 1e6)
+}
+
+fn label(road_state: RoadState) -> &'static str {
+    proto::RoadState::from(road_state).as_str_name()
+}
@@ -0,0 +1,299 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use iot_system::domain::{Accelerometer, Agent};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Calibration, CalibrationMethod},
+    data_processing::motion,
+};
+
+/// Below this norm the gravity estimate is not trusted to give a direction
+const MIN_GRAVITY_NORM: f64 = 1e-9;
+
+type Vector = [f64; 3];
+
+/// Rotates the accelerometer samples of each agent from the axes of the sensor into the frame
+/// of the vehicle, where the gravity is along `z`, so that the sensor may be mounted at any angle.
+///
+/// The gravity is estimated per agent, as set up by [`CalibrationMethod`]. Until it is,
+/// the axes of the sensor are assumed to be the axes of the vehicle.
+/// The estimates are saved to a file and loaded from it on start
+#[derive(Debug)]
+pub struct Calibrator {
+    config: Calibration,
+    /// Speed below which the vehicle is stationary, in meters per second
+    min_speed_mps: f64,
+    agents: HashMap<String, AgentCalibration>,
+    /// Whether the estimates changed since they were saved
+    dirty: bool,
+    saved_at: Instant,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AgentCalibration {
+    /// Gravity in the axes of the sensor, `None` until estimated
+    gravity: Option<Vector>,
+    /// Sum and number of the latest samples in a row taken while stationary
+    #[serde(skip)]
+    stationary: (Vector, usize),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CalibrationError {
+    #[error("Failed to access the calibration state: {0}")]
+    Io(#[from] io::Error),
+    #[error("Failed to (de)serialize the calibration state: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("The smoothing of {0} must be from 0 to 1")]
+    Smoothing(f64),
+}
+
+impl Calibrator {
+    /// Loads the estimates saved before, if any
+    pub fn load(config: Calibration, min_speed_mps: f64) -> Result<Self, CalibrationError> {
+        if let CalibrationMethod::LowPass { smoothing } = config.method {
+            if !(0.0..=1.0).contains(&smoothing) {
+                return Err(CalibrationError::Smoothing(smoothing));
+            }
+        }
+        let agents = match fs::read(&config.state_path) {
+            Ok(state) => serde_json::from_slice(&state)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self {
+            config,
+            min_speed_mps,
+            agents,
+            dirty: false,
+            saved_at: Instant::now(),
+        })
+    }
+
+    /// Updates the gravity estimate of the agent with the data,
+    /// then rotates the accelerometer sample of the data into the frame of the vehicle
+    pub fn calibrate(&mut self, agent: &str, data: Agent, prev_data: Option<&Agent>) -> Agent {
+        let sample = vector(data.accelerometer());
+        let calibration = self.agents.entry(agent.to_owned()).or_default();
+        let updated = match self.config.method {
+            CalibrationMethod::Stationary { min_samples } => {
+                let stationary = prev_data
+                    .and_then(|prev_data| motion(prev_data, &data))
+                    .is_some_and(|motion| motion.speed_mps() < self.min_speed_mps);
+                calibration.push_stationary(sample, stationary, min_samples.get())
+            }
+            CalibrationMethod::LowPass { smoothing } => {
+                calibration.push_low_pass(sample, smoothing)
+            }
+        };
+        self.dirty |= updated;
+
+        let Some(gravity) = calibration.gravity else {
+            return data;
+        };
+        let [x, y, z] = rotate_to_z(sample, gravity);
+        data.with_accelerometer(Accelerometer::new(x, y, z))
+    }
+
+    /// Saves the estimates if they changed and the save interval has passed since the last save
+    pub async fn save_if_due(&mut self) -> Result<(), CalibrationError> {
+        if self.saved_at.elapsed() < self.save_interval() {
+            return Ok(());
+        }
+        self.save().await
+    }
+
+    /// Saves the estimates if they changed
+    pub async fn save(&mut self) -> Result<(), CalibrationError> {
+        if !self.dirty {
+            return Ok(());
+        }
+        // Written next to the state, then renamed over it, so that a crash can't corrupt it
+        let temporary = PathBuf::from(format!("{}.tmp", self.config.state_path.display()));
+        tokio::fs::write(&temporary, serde_json::to_vec(&self.agents)?).await?;
+        tokio::fs::rename(&temporary, &self.config.state_path).await?;
+        self.dirty = false;
+        self.saved_at = Instant::now();
+
+        Ok(())
+    }
+
+    fn save_interval(&self) -> Duration {
+        Duration::from_secs(self.config.save_interval_secs.get())
+    }
+}
+
+impl AgentCalibration {
+    /// Averages the samples while stationary, as the only acceleration then is the gravity.
+    /// Returns `true` if the estimate changed
+    fn push_stationary(&mut self, sample: Vector, stationary: bool, min_samples: usize) -> bool {
+        if !stationary {
+            self.stationary = Default::default();
+            return false;
+        }
+        let (sum, count) = &mut self.stationary;
+        for (sum, sample) in sum.iter_mut().zip(sample) {
+            *sum += sample;
+        }
+        *count += 1;
+        if *count < min_samples {
+            return false;
+        }
+        self.gravity = Some(sum.map(|sum| sum / *count as f64));
+        true
+    }
+
+    /// Smooths the samples with an exponential moving average, which keeps the gravity
+    /// and filters out the short accelerations of the vehicle. Returns `true`
+    fn push_low_pass(&mut self, sample: Vector, smoothing: f64) -> bool {
+        let gravity = self.gravity.get_or_insert(sample);
+        for (gravity, sample) in gravity.iter_mut().zip(sample) {
+            *gravity += smoothing * (sample - *gravity);
+        }
+        true
+    }
+}
+
+fn vector(accelerometer: Accelerometer) -> Vector {
+    [accelerometer.x(), accelerometer.y(), accelerometer.z()]
+}
+
+/// Rotates the vector by the shortest rotation taking the direction of the gravity to `+z`,
+/// by the Rodrigues' rotation formula
+fn rotate_to_z(vector: Vector, gravity: Vector) -> Vector {
+    let norm = dot(gravity, gravity).sqrt();
+    if norm < MIN_GRAVITY_NORM {
+        return vector;
+    }
+    let up = gravity.map(|component| component / norm);
+    let cos = up[2];
+    // The axis is `up × z`, normalized. Its length is the sine of the angle
+    let axis = [up[1], -up[0], 0.0];
+    let sin = dot(axis, axis).sqrt();
+    let axis = if sin < MIN_GRAVITY_NORM {
+        if cos > 0.0 {
+            return vector;
+        }
+        // Upside down: any horizontal axis takes the gravity to `+z`
+        [1.0, 0.0, 0.0]
+    } else {
+        axis.map(|component| component / sin)
+    };
+
+    let cross = cross(axis, vector);
+    let projection = dot(axis, vector) * (1.0 - cos);
+    [0, 1, 2].map(|i| vector[i] * cos + cross[i] * sin + axis[i] * projection)
+}
+
+fn dot(a: Vector, b: Vector) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: Vector, b: Vector) -> Vector {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::{NonZeroU64, NonZeroUsize};
+
+    use chrono::DateTime;
+    use iot_system::domain::Gps;
+
+    use super::*;
+
+    fn assert_close(actual: Vector, expected: Vector) {
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(
+                (actual - expected).abs() < 1e-9,
+                "expected {expected:?}, got {actual:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn upright_sensor_is_not_rotated() {
+        assert_close(
+            rotate_to_z([1.0, 2.0, 3.0], [0.0, 0.0, 9.8]),
+            [1.0, 2.0, 3.0],
+        );
+    }
+
+    #[test]
+    fn tilted_sensor_is_rotated_to_the_gravity() {
+        // Tilted by 90° around `y`: the gravity reads along `x`
+        let gravity = [9.8, 0.0, 0.0];
+        assert_close(rotate_to_z(gravity, gravity), [0.0, 0.0, 9.8]);
+        assert_close(rotate_to_z([0.0, 0.0, 1.0], gravity), [-1.0, 0.0, 0.0]);
+        assert_close(rotate_to_z([0.0, 1.0, 0.0], gravity), [0.0, 1.0, 0.0]);
+
+        // Tilted by 45°, the length is kept
+        let gravity = [0.0, 1.0, 1.0];
+        assert_close(rotate_to_z(gravity, gravity), [0.0, 0.0, 2f64.sqrt()]);
+    }
+
+    #[test]
+    fn upside_down_sensor_is_flipped() {
+        let gravity = [0.0, 0.0, -9.8];
+        assert_close(rotate_to_z(gravity, gravity), [0.0, 0.0, 9.8]);
+        assert_close(rotate_to_z([1.0, 2.0, 0.0], gravity), [1.0, -2.0, 0.0]);
+    }
+
+    #[test]
+    fn stationary_estimate_restarts_on_motion() {
+        let mut calibration = AgentCalibration::default();
+        assert!(!calibration.push_stationary([1.0, 0.0, 0.0], true, 2));
+        // The motion drops the sample taken before it
+        assert!(!calibration.push_stationary([5.0, 5.0, 5.0], false, 2));
+        assert_eq!(calibration.stationary, ([0.0; 3], 0));
+        assert!(!calibration.push_stationary([0.0, 0.0, 2.0], true, 2));
+        assert!(calibration.push_stationary([0.0, 0.0, 4.0], true, 2));
+        assert_eq!(calibration.gravity, Some([0.0, 0.0, 3.0]));
+    }
+
+    #[test]
+    fn calibration_keeps_everything_but_the_accelerometer_sample() {
+        let mut calibrator = Calibrator::load(
+            Calibration {
+                method: CalibrationMethod::Stationary {
+                    min_samples: NonZeroUsize::MIN,
+                },
+                state_path: std::env::temp_dir().join("calibration-test-never-written.json"),
+                save_interval_secs: NonZeroU64::MIN,
+            },
+            1.0,
+        )
+        .unwrap();
+        let data = Agent::new(
+            Accelerometer::new(9.8, 0.0, 0.0),
+            Gps::new(50.45.try_into().unwrap(), 30.52.try_into().unwrap()),
+            DateTime::UNIX_EPOCH,
+        )
+        .with_idempotency_key(Some("agent:1".to_owned().try_into().unwrap()))
+        .with_agent_id(Some("AA1234BB".to_owned().try_into().unwrap()));
+
+        let prev_data = Agent::new(
+            data.accelerometer(),
+            data.gps(),
+            data.timestamp() - chrono::Duration::seconds(1),
+        );
+
+        // Standing still, so the first sample gives the gravity
+        let calibrated = calibrator.calibrate("agent", data.clone(), Some(&prev_data));
+
+        assert_close(vector(calibrated.accelerometer()), [0.0, 0.0, 9.8]);
+        assert_eq!(calibrated.agent_id(), data.agent_id());
+        assert_eq!(calibrated.idempotency_key(), data.idempotency_key());
+        assert_eq!(calibrated.timestamp(), data.timestamp());
+    }
+}
@@ -0,0 +1,71 @@
+use iot_system::domain::RoadState;
+
+pub use self::model::{Model, ModelError, Node};
+use crate::{
+    config::{Classification, Thresholds},
+    features::Features,
+};
+
+mod model;
+
+/// Classifies the road by the features of a window
+#[derive(Debug)]
+pub enum Classifier {
+    Thresholds(Thresholds),
+    Model(Model),
+}
+
+/// Road state of a window, with the confidence of the classifier in it, if it tells one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Verdict {
+    pub road_state: RoadState,
+    /// From 0.5 to 1
+    pub confidence: Option<f64>,
+}
+
+impl Classifier {
+    /// Loads the model, if the classification is by one
+    pub fn new(config: &Classification) -> Result<Self, ModelError> {
+        Ok(match config {
+            Classification::Thresholds(thresholds) => Self::Thresholds(*thresholds),
+            Classification::Model { model_path } => Self::Model(Model::load(model_path)?),
+        })
+    }
+
+    pub fn classify(&self, features: &Features) -> Verdict {
+        match self {
+            Self::Thresholds(thresholds) => Verdict {
+                road_state: thresholds.classify(features),
+                confidence: None,
+            },
+            Self::Model(model) => {
+                let rough_probability = model.rough_probability(features);
+                if rough_probability >= 0.5 {
+                    Verdict {
+                        road_state: RoadState::Rough,
+                        confidence: Some(rough_probability),
+                    }
+                } else {
+                    Verdict {
+                        road_state: RoadState::Smooth,
+                        confidence: Some(1.0 - rough_probability),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Thresholds {
+    /// The road is rough if any of the features reaches its threshold
+    pub fn classify(&self, features: &Features) -> RoadState {
+        if features.max_jerk > self.max_jerk
+            || features.std_dev > self.std_dev
+            || features.peak_count >= self.peak_count.get()
+        {
+            RoadState::Rough
+        } else {
+            RoadState::Smooth
+        }
+    }
+}
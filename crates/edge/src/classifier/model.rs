@@ -0,0 +1,139 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use serde::Deserialize;
+
+use crate::features::{Feature, Features};
+
+/// Model trained on the features of the windows, estimating the probability of a rough road
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Model {
+    /// `sigmoid(bias + Σ weight × feature)`. The features without a weight are not used
+    LogisticRegression {
+        weights: HashMap<Feature, f64>,
+        bias: f64,
+    },
+    DecisionTree {
+        root: Node,
+    },
+}
+
+/// Node of a decision tree
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Node {
+    /// Goes `below` if the feature is at or below the threshold, `above` otherwise
+    Split {
+        feature: Feature,
+        threshold: f64,
+        below: Box<Node>,
+        above: Box<Node>,
+    },
+    /// Share of the rough windows among the training windows reaching the leaf
+    Leaf { rough_probability: f64 },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ModelError {
+    #[error("Failed to read the model: {0}")]
+    Io(#[from] io::Error),
+    #[error("Failed to parse the model: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("The weight of {feature} of {weight} must be finite")]
+    Weight { feature: &'static str, weight: f64 },
+    #[error("The bias of {0} must be finite")]
+    Bias(f64),
+    #[error("The threshold of {feature} of {threshold} must be finite")]
+    Threshold {
+        feature: &'static str,
+        threshold: f64,
+    },
+    #[error("The rough probability of {0} must be from 0 to 1")]
+    RoughProbability(f64),
+}
+
+impl Model {
+    /// Loads the model, checking that it gives a probability from 0 to 1 for any features
+    pub fn load(path: &Path) -> Result<Self, ModelError> {
+        let model: Self = serde_json::from_slice(&fs::read(path)?)?;
+        match &model {
+            Self::LogisticRegression { weights, bias } => {
+                if let Some((feature, &weight)) =
+                    weights.iter().find(|(_, weight)| !weight.is_finite())
+                {
+                    return Err(ModelError::Weight {
+                        feature: feature.name(),
+                        weight,
+                    });
+                }
+                if !bias.is_finite() {
+                    return Err(ModelError::Bias(*bias));
+                }
+            }
+            Self::DecisionTree { root } => root.validate()?,
+        }
+        Ok(model)
+    }
+
+    /// Probability of a rough road, from 0 to 1
+    pub fn rough_probability(&self, features: &Features) -> f64 {
+        match self {
+            Self::LogisticRegression { weights, bias } => {
+                let logit = weights
+                    .iter()
+                    .map(|(&feature, weight)| weight * features.get(feature))
+                    .sum::<f64>()
+                    + bias;
+                1.0 / (1.0 + (-logit).exp())
+            }
+            Self::DecisionTree { root } => {
+                let mut node = root;
+                loop {
+                    match node {
+                        Node::Split {
+                            feature,
+                            threshold,
+                            below,
+                            above,
+                        } => {
+                            node = if features.get(*feature) <= *threshold {
+                                below
+                            } else {
+                                above
+                            };
+                        }
+                        Node::Leaf { rough_probability } => break *rough_probability,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Node {
+    fn validate(&self) -> Result<(), ModelError> {
+        match self {
+            Self::Split {
+                feature,
+                threshold,
+                below,
+                above,
+            } => {
+                if !threshold.is_finite() {
+                    return Err(ModelError::Threshold {
+                        feature: feature.name(),
+                        threshold: *threshold,
+                    });
+                }
+                below.validate()?;
+                above.validate()
+            }
+            Self::Leaf { rough_probability } => {
+                if !(0.0..=1.0).contains(rough_probability) {
+                    return Err(ModelError::RoughProbability(*rough_probability));
+                }
+                Ok(())
+            }
+        }
+    }
+}
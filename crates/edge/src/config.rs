@@ -1,3 +1,8 @@
+use std::{
+    num::{NonZeroU64, NonZeroUsize},
+    path::PathBuf,
+};
+
 use iot_system::config::{Mqtt, Server};
 use serde::Deserialize;
 
@@ -6,6 +11,159 @@ pub struct Configuration {
     pub agent_mqtt: Mqtt,
     pub hub_mqtt: Mqtt,
     pub hub_grpc: Server,
+    #[serde(flatten)]
+    pub pipeline: Pipeline,
+    /// Every processed data is forwarded to the hub if absent
+    pub forwarding: Option<Forwarding>,
+}
+
+/// Processing of the data of the agents, from the calibration to the classification
+#[derive(Debug, Deserialize)]
+pub struct Pipeline {
+    #[serde(default)]
+    pub processing: Processing,
+    /// The sensor is assumed to be mounted with `z` vertical if absent
+    pub calibration: Option<Calibration>,
+    /// The accelerometer samples are classified unfiltered if absent
+    pub filtering: Option<Filtering>,
+    /// Each sample is classified with the one before it if absent
+    pub windowing: Option<Windowing>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Processing {
+    /// Speed below which the vehicle is considered stationary, in meters per second.
+    /// The road is not classified while stationary, as the vibration of an idling engine
+    /// reads as rough road. 0 by default, so the vehicle is never considered stationary
+    pub min_speed_mps: f64,
+}
+
+/// Estimation of the orientation of the sensor in the vehicle
+#[derive(Debug, Deserialize)]
+pub struct Calibration {
+    #[serde(flatten)]
+    pub method: CalibrationMethod,
+    /// File the estimates are kept in across restarts
+    pub state_path: PathBuf,
+    /// Minimum seconds between the saves of the changed estimates
+    pub save_interval_secs: NonZeroU64,
+}
+
+/// How the gravity is estimated
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum CalibrationMethod {
+    /// Mean of the samples taken while stationary, once `min_samples` of them are taken in a row
+    Stationary { min_samples: NonZeroUsize },
+    /// Exponential moving average of the samples,
+    /// `smoothing` being the weight of the latest sample, from 0 to 1
+    LowPass { smoothing: f64 },
+}
+
+/// Chain of filters the accelerometer samples of each agent pass through before the classification
+#[derive(Debug, Deserialize)]
+pub struct Filtering {
+    /// Rate at which the agents take the samples, in hertz
+    pub sample_rate_hz: f64,
+    /// Applied in order, each axis separately
+    pub stages: Vec<Filter>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Filter {
+    /// Mean of the latest `window` samples
+    MovingAverage { window: NonZeroUsize },
+    /// Median of the latest `window` samples, removing the spikes
+    Median { window: NonZeroUsize },
+    /// First order high-pass, removing the drift of the gravity
+    HighPass { cutoff_hz: f64 },
+    /// Second order Butterworth low-pass
+    ButterworthLowPass { cutoff_hz: f64 },
+    /// Replaces the samples further than `max_deviations` median absolute deviations
+    /// from the median of the latest `window` samples with the median
+    OutlierRejection {
+        window: NonZeroUsize,
+        max_deviations: f64,
+    },
+}
+
+/// Classification of the road by the features of windows of the samples of each agent
+#[derive(Debug, Deserialize)]
+pub struct Windowing {
+    #[serde(flatten)]
+    pub length: WindowLength,
+    /// Part of each window shared with the next one, from 0 inclusive to 1 exclusive
+    pub overlap: f64,
+    pub emit: Emit,
+    /// Deviation of the vertical acceleration from its mean counted as a peak, in mm/s^2
+    pub peak_height: f64,
+    pub classification: Classification,
+}
+
+/// When a window is full
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(untagged)]
+pub enum WindowLength {
+    /// Once it has the number of samples
+    Samples { samples: NonZeroUsize },
+    /// Once its samples span the seconds
+    Duration { duration_secs: f64 },
+}
+
+/// What is emitted once a window is classified
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Emit {
+    /// The latest sample of the window, with the motion over the whole window
+    Window,
+    /// Each sample of the window not emitted with the previous window
+    Sample,
+}
+
+/// How the windows are classified by their features
+#[derive(Debug, Deserialize)]
+#[serde(tag = "classifier", rename_all = "snake_case")]
+pub enum Classification {
+    Thresholds(Thresholds),
+    /// Trained model, read from a JSON file
+    Model {
+        model_path: PathBuf,
+    },
+}
+
+/// Thresholds of the features, any of which reached makes the road rough
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Thresholds {
+    /// In mm/s^3
+    pub max_jerk: f64,
+    /// In mm/s^2
+    pub std_dev: f64,
+    pub peak_count: NonZeroUsize,
+}
+
+/// Which of the processed data of each agent are forwarded to the hub,
+/// to save the bandwidth of metered links
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Forwarding {
+    #[serde(flatten)]
+    pub policy: ForwardingPolicy,
+    /// Drops the processed data of a sample equal to the previous sample of the agent,
+    /// e.g. delivered again by the broker
+    #[serde(default)]
+    pub drop_duplicates: bool,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum ForwardingPolicy {
+    /// Every processed data
+    All,
+    /// The rough road, and the smooth road once `heartbeat_secs` passed
+    /// since the latest forwarded data of the agent, so that the hub knows it's alive
+    RoughWithHeartbeat { heartbeat_secs: NonZeroU64 },
+    /// The processed data of a road state other than of the latest forwarded data of the agent
+    OnChange,
 }
 
 impl iot_system::config::TryRead<'_> for Configuration {}
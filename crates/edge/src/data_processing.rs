@@ -1,16 +1,26 @@
-use iot_system::domain::{Agent, ProcessedAgent, RoadState};
+use iot_system::domain::{Agent, Motion, ProcessedAgent, RoadState};
+
+use crate::config::Processing;
 
 #[tracing::instrument]
-pub fn process_agent_data(current_data: Agent, prev_data: Option<&Agent>) -> ProcessedAgent {
-    let road_state = 'b: {
-        let Some(prev_data) = prev_data else {
-            break 'b RoadState::default();
-        };
-        let dt = current_data
-            .timestamp()
-            .signed_duration_since(prev_data.timestamp())
-            .num_milliseconds() as f64
-            / 1000.0; // seconds
+pub fn process_agent_data(
+    current_data: Agent,
+    prev_data: Option<&Agent>,
+    config: &Processing,
+) -> ProcessedAgent {
+    let Some(prev_data) = prev_data else {
+        return ProcessedAgent::new(current_data, RoadState::default());
+    };
+    let Some(motion) = motion(prev_data, &current_data) else {
+        tracing::debug!("Not following the previous data in time, the road is not classified");
+        return ProcessedAgent::new(current_data, RoadState::default());
+    };
+    let dt = seconds_between(prev_data, &current_data);
+
+    let road_state = if motion.speed_mps() < config.min_speed_mps {
+        tracing::debug!("Stationary, the road is not classified");
+        RoadState::default()
+    } else {
         let a1_z = prev_data.accelerometer().z(); // mm/s^2
         let a2_z = current_data.accelerometer().z(); // mm/s^2
         let da_z = a2_z - a1_z; // mm/s^2
@@ -23,5 +33,60 @@ pub fn process_agent_data(current_data: Agent, prev_data: Option<&Agent>) -> Pro
         }
     };
 
-    ProcessedAgent::new(current_data, road_state)
+    ProcessedAgent::new(current_data, road_state).with_motion(motion)
+}
+
+/// Motion from the previous data to the current one.
+/// Unknown if the current data doesn't follow the previous one in time
+pub(crate) fn motion(prev_data: &Agent, current_data: &Agent) -> Option<Motion> {
+    let dt = seconds_between(prev_data, current_data);
+    (dt > 0.0).then(|| Motion::between(prev_data.gps(), current_data.gps(), dt))
+}
+
+pub(crate) fn seconds_between(prev_data: &Agent, current_data: &Agent) -> f64 {
+    current_data
+        .timestamp()
+        .signed_duration_since(prev_data.timestamp())
+        .num_milliseconds() as f64
+        / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration};
+    use iot_system::domain::{Accelerometer, Gps};
+
+    use super::*;
+
+    fn agent(z: f64, seconds: i64) -> Agent {
+        Agent::new(
+            Accelerometer::new(0.0, 0.0, z),
+            Gps::new(50.45.try_into().unwrap(), 30.52.try_into().unwrap()),
+            DateTime::UNIX_EPOCH + Duration::seconds(seconds),
+        )
+    }
+
+    #[test]
+    fn sharp_vertical_change_is_rough_road() {
+        let processed = process_agent_data(
+            agent(3000.0, 1),
+            Some(&agent(0.0, 0)),
+            &Processing::default(),
+        );
+
+        assert_eq!(processed.road_state(), RoadState::Rough);
+        assert!(processed.motion().is_some());
+    }
+
+    #[test]
+    fn data_at_the_same_time_is_not_classified() {
+        let processed = process_agent_data(
+            agent(3000.0, 0),
+            Some(&agent(0.0, 0)),
+            &Processing::default(),
+        );
+
+        assert_eq!(processed.road_state(), RoadState::default());
+        assert!(processed.motion().is_none());
+    }
 }
@@ -0,0 +1,112 @@
+use std::{borrow::Borrow, fs::File, path::Path};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use iot_system::domain::{Accelerometer, Agent, Gps, RoadState};
+use serde::Deserialize;
+
+use crate::{config::Windowing, features::Features, windowing::SlidingWindow, Filters};
+
+/// Sample of an agent with the road state labelled by a human
+#[derive(Debug, Clone)]
+pub struct Labelled {
+    pub data: Agent,
+    pub road_state: RoadState,
+}
+
+/// Features of a window of the labelled samples
+#[derive(Debug, Clone)]
+pub struct LabelledWindow {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub features: Features,
+    /// The most common label of the samples, rough if as common as smooth
+    pub road_state: RoadState,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DatasetError {
+    #[error("Failed to read the file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse the file: {0}")]
+    Csv(#[from] csv::Error),
+}
+
+#[derive(Deserialize)]
+struct Label {
+    road_state: RoadState,
+}
+
+impl Borrow<Agent> for Labelled {
+    fn borrow(&self) -> &Agent {
+        &self.data
+    }
+}
+
+/// Reads the samples from the CSV files published by the agent, paired row by row as the agent
+/// pairs them, and labels them by the file with a `road_state` column, one row per sample.
+///
+/// The files don't have timestamps, so the samples are taken `1 / sample_rate_hz` seconds
+/// apart from the Unix epoch. The samples past the end of any of the files are skipped
+pub fn read_agent_files(
+    accelerometer: &Path,
+    gps: &Path,
+    labels: &Path,
+    sample_rate_hz: f64,
+) -> Result<Vec<Labelled>, DatasetError> {
+    let period = TimeDelta::nanoseconds((1e9 / sample_rate_hz) as i64);
+    let mut accelerometer = csv::Reader::from_reader(File::open(accelerometer)?);
+    let mut gps = csv::Reader::from_reader(File::open(gps)?);
+    let mut labels = csv::Reader::from_reader(File::open(labels)?);
+
+    accelerometer
+        .deserialize::<Accelerometer>()
+        .zip(gps.deserialize::<Gps>())
+        .zip(labels.deserialize::<Label>())
+        .zip(0..)
+        .map(|(((accelerometer, gps), label), index)| {
+            Ok(Labelled {
+                data: Agent::new(accelerometer?, gps?, DateTime::UNIX_EPOCH + period * index),
+                road_state: label?.road_state,
+            })
+        })
+        .collect()
+}
+
+/// Passes the samples through the filters, as the edge does before the classification
+pub fn filter(samples: Vec<Labelled>, filters: &mut Filters, agent: &str) -> Vec<Labelled> {
+    samples
+        .into_iter()
+        .map(|Labelled { data, road_state }| Labelled {
+            data: filters.filter(agent, data),
+            road_state,
+        })
+        .collect()
+}
+
+/// Collects the samples, ordered by time, into windows as the edge does,
+/// and computes the features of each window
+pub fn windows(samples: Vec<Labelled>, config: &Windowing) -> Vec<LabelledWindow> {
+    let mut window = SlidingWindow::default();
+    samples
+        .into_iter()
+        .filter_map(|sample| {
+            window.push(sample, config, |window| {
+                let rough = window
+                    .samples
+                    .iter()
+                    .filter(|sample| sample.road_state == RoadState::Rough)
+                    .count();
+                LabelledWindow {
+                    start: window.samples[0].data.timestamp(),
+                    end: window.samples[window.samples.len() - 1].data.timestamp(),
+                    features: Features::of(window.samples, config.peak_height),
+                    road_state: if 2 * rough >= window.samples.len() {
+                        RoadState::Rough
+                    } else {
+                        RoadState::Smooth
+                    },
+                }
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,90 @@
+use std::borrow::Borrow;
+
+use iot_system::domain::Agent;
+use serde::Deserialize;
+
+use crate::data_processing::seconds_between;
+
+/// Features of a window of the samples of an agent, computed from the vertical acceleration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Features {
+    /// Largest absolute rate of change of the acceleration between two samples, in mm/s^3
+    pub max_jerk: f64,
+    /// Standard deviation of the acceleration, in mm/s^2
+    pub std_dev: f64,
+    /// Number of times the acceleration deviates from its mean by more than the peak height
+    pub peak_count: usize,
+}
+
+/// One of the [`Features`], named as its field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    MaxJerk,
+    StdDev,
+    PeakCount,
+}
+
+impl Features {
+    /// Computes the features of the samples, ordered by time.
+    /// A deviation from the mean larger than `peak_height` mm/s^2 is counted as a peak
+    pub fn of<T: Borrow<Agent>>(samples: &[T], peak_height: f64) -> Self {
+        let max_jerk = samples
+            .windows(2)
+            .filter_map(|pair| {
+                let pair = [pair[0].borrow(), pair[1].borrow()];
+                let dt = seconds_between(pair[0], pair[1]);
+                let da_z = pair[1].accelerometer().z() - pair[0].accelerometer().z();
+                (dt > 0.0).then(|| (da_z / dt).abs())
+            })
+            .fold(0.0, f64::max);
+
+        let count = samples.len().max(1) as f64;
+        let mean = samples
+            .iter()
+            .map(|sample| sample.borrow().accelerometer().z())
+            .sum::<f64>()
+            / count;
+        let deviations = samples
+            .iter()
+            .map(|sample| sample.borrow().accelerometer().z() - mean);
+        let std_dev = (deviations
+            .clone()
+            .map(|deviation| deviation * deviation)
+            .sum::<f64>()
+            / count)
+            .sqrt();
+        // Counted as the deviation rises above the height, so that a peak is counted once
+        // however many samples it lasts
+        let (peak_count, _) = deviations.fold((0, false), |(count, in_peak), deviation| {
+            let peak = deviation.abs() > peak_height;
+            (count + usize::from(peak && !in_peak), peak)
+        });
+
+        Self {
+            max_jerk,
+            std_dev,
+            peak_count,
+        }
+    }
+
+    pub fn get(&self, feature: Feature) -> f64 {
+        match feature {
+            Feature::MaxJerk => self.max_jerk,
+            Feature::StdDev => self.std_dev,
+            Feature::PeakCount => self.peak_count as f64,
+        }
+    }
+}
+
+impl Feature {
+    pub const ALL: [Self; 3] = [Self::MaxJerk, Self::StdDev, Self::PeakCount];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::MaxJerk => "max_jerk",
+            Self::StdDev => "std_dev",
+            Self::PeakCount => "peak_count",
+        }
+    }
+}
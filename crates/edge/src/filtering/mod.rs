@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use iot_system::domain::{Accelerometer, Agent};
+
+pub use self::stage::{
+    ButterworthLowPass, HighPass, Median, MovingAverage, OutlierRejection, Stage,
+};
+use crate::config::{Filter, Filtering};
+
+mod stage;
+
+/// Filters the accelerometer samples of each agent through the chain of the configured stages,
+/// each axis separately, so that the noise of the sensor is not classified as a rough road
+#[derive(Debug)]
+pub struct Filters {
+    /// Stages in their initial state, cloned for each new agent
+    chain: Vec<Stage>,
+    agents: HashMap<String, [Vec<Stage>; 3]>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FilterError {
+    #[error("The cutoff frequency of {cutoff_hz} Hz must be positive and below the half of the sample rate of {sample_rate_hz} Hz")]
+    Cutoff { cutoff_hz: f64, sample_rate_hz: f64 },
+    #[error("The sample rate of {0} Hz must be positive")]
+    SampleRate(f64),
+}
+
+impl Filters {
+    pub fn new(config: &Filtering) -> Result<Self, FilterError> {
+        let sample_rate_hz = config.sample_rate_hz;
+        if sample_rate_hz.is_nan() || sample_rate_hz <= 0.0 {
+            return Err(FilterError::SampleRate(sample_rate_hz));
+        }
+        let check_cutoff = |cutoff_hz: f64| {
+            if cutoff_hz > 0.0 && cutoff_hz < sample_rate_hz / 2.0 {
+                Ok(cutoff_hz)
+            } else {
+                Err(FilterError::Cutoff {
+                    cutoff_hz,
+                    sample_rate_hz,
+                })
+            }
+        };
+        let chain = config
+            .stages
+            .iter()
+            .map(|&filter| {
+                Ok(match filter {
+                    Filter::MovingAverage { window } => {
+                        Stage::MovingAverage(MovingAverage::new(window.get()))
+                    }
+                    Filter::Median { window } => Stage::Median(Median::new(window.get())),
+                    Filter::HighPass { cutoff_hz } => {
+                        Stage::HighPass(HighPass::new(check_cutoff(cutoff_hz)?, sample_rate_hz))
+                    }
+                    Filter::ButterworthLowPass { cutoff_hz } => Stage::ButterworthLowPass(
+                        ButterworthLowPass::new(check_cutoff(cutoff_hz)?, sample_rate_hz),
+                    ),
+                    Filter::OutlierRejection {
+                        window,
+                        max_deviations,
+                    } => {
+                        Stage::OutlierRejection(OutlierRejection::new(window.get(), max_deviations))
+                    }
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            chain,
+            agents: HashMap::new(),
+        })
+    }
+
+    /// Passes the accelerometer sample of the data through the stages of the agent
+    pub fn filter(&mut self, agent: &str, data: Agent) -> Agent {
+        let axes = self
+            .agents
+            .entry(agent.to_owned())
+            .or_insert_with(|| [(); 3].map(|_| self.chain.clone()));
+        let accelerometer = data.accelerometer();
+        let samples = [accelerometer.x(), accelerometer.y(), accelerometer.z()];
+        let [x, y, z] = std::array::from_fn(|axis| {
+            axes[axis]
+                .iter_mut()
+                .fold(samples[axis], |sample, stage| stage.apply(sample))
+        });
+        data.with_accelerometer(Accelerometer::new(x, y, z))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use iot_system::domain::Gps;
+
+    use super::*;
+
+    #[test]
+    fn filtering_keeps_everything_but_the_accelerometer_sample() {
+        let mut filters = Filters::new(&Filtering {
+            sample_rate_hz: 10.0,
+            stages: vec![Filter::MovingAverage {
+                window: NonZeroUsize::new(2).unwrap(),
+            }],
+        })
+        .unwrap();
+        let gps = Gps::new(50.45.try_into().unwrap(), 30.52.try_into().unwrap());
+        let data = |z| {
+            Agent::new(
+                Accelerometer::new(0.0, 0.0, z),
+                gps,
+                chrono::DateTime::UNIX_EPOCH,
+            )
+            .with_idempotency_key(Some("agent:1".to_owned().try_into().unwrap()))
+            .with_agent_id(Some("AA1234BB".to_owned().try_into().unwrap()))
+        };
+
+        filters.filter("agent", data(1.0));
+        let filtered = filters.filter("agent", data(3.0));
+
+        assert_eq!(filtered.accelerometer().z(), 2.0);
+        assert_eq!(
+            filtered.idempotency_key().map(|key| key.as_str()),
+            Some("agent:1")
+        );
+        assert_eq!(filtered.agent_id(), data(3.0).agent_id());
+        assert_eq!(filtered.gps(), gps);
+    }
+}
@@ -0,0 +1,315 @@
+use std::{collections::VecDeque, f64::consts::PI};
+
+/// Stage of a filter chain, filtering the samples of one axis one at a time.
+/// The output only depends on the samples given before, so a stage may be checked
+/// by feeding it a fixed sequence
+#[derive(Debug, Clone)]
+pub enum Stage {
+    MovingAverage(MovingAverage),
+    Median(Median),
+    HighPass(HighPass),
+    ButterworthLowPass(ButterworthLowPass),
+    OutlierRejection(OutlierRejection),
+}
+
+/// Mean of the latest `window` samples
+#[derive(Debug, Clone)]
+pub struct MovingAverage {
+    window: Window,
+    sum: f64,
+}
+
+/// Median of the latest `window` samples, which removes the spikes shorter than half of it
+#[derive(Debug, Clone)]
+pub struct Median {
+    window: Window,
+}
+
+/// First order high-pass, which removes the constant and slowly drifting part of the samples
+#[derive(Debug, Clone)]
+pub struct HighPass {
+    /// Weight of the previous output
+    alpha: f64,
+    /// Previous sample and output
+    previous: Option<(f64, f64)>,
+}
+
+/// Second order Butterworth low-pass, discretized by the bilinear transform
+#[derive(Debug, Clone)]
+pub struct ButterworthLowPass {
+    /// Coefficients of the current and the two previous samples
+    b: [f64; 3],
+    /// Coefficients of the two previous outputs
+    a: [f64; 2],
+    /// Two previous samples and outputs, the latest first
+    previous: Option<([f64; 2], [f64; 2])>,
+}
+
+/// Replaces the samples deviating from the median of the latest `window` samples by more than
+/// `max_deviations` median absolute deviations with the median, or mean absolute deviations
+/// if the median one is zero. The replaced samples are still kept in the window,
+/// so that a lasting change of the level is let through once it fills the half of the window
+#[derive(Debug, Clone)]
+pub struct OutlierRejection {
+    window: Window,
+    max_deviations: f64,
+}
+
+/// Latest samples, up to the size of the window
+#[derive(Debug, Clone)]
+struct Window {
+    size: usize,
+    samples: VecDeque<f64>,
+}
+
+impl Stage {
+    pub fn apply(&mut self, sample: f64) -> f64 {
+        match self {
+            Self::MovingAverage(stage) => stage.apply(sample),
+            Self::Median(stage) => stage.apply(sample),
+            Self::HighPass(stage) => stage.apply(sample),
+            Self::ButterworthLowPass(stage) => stage.apply(sample),
+            Self::OutlierRejection(stage) => stage.apply(sample),
+        }
+    }
+}
+
+impl MovingAverage {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: Window::new(window),
+            sum: 0.0,
+        }
+    }
+
+    pub fn apply(&mut self, sample: f64) -> f64 {
+        self.sum += sample;
+        if let Some(removed) = self.window.push(sample) {
+            self.sum -= removed;
+        }
+        self.sum / self.window.samples.len() as f64
+    }
+}
+
+impl Median {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: Window::new(window),
+        }
+    }
+
+    pub fn apply(&mut self, sample: f64) -> f64 {
+        self.window.push(sample);
+        self.window.median()
+    }
+}
+
+impl HighPass {
+    pub fn new(cutoff_hz: f64, sample_rate_hz: f64) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff_hz);
+        let dt = 1.0 / sample_rate_hz;
+        Self {
+            alpha: rc / (rc + dt),
+            previous: None,
+        }
+    }
+
+    pub fn apply(&mut self, sample: f64) -> f64 {
+        // The first sample is taken as the constant part, as if it lasted forever before
+        let output = match self.previous {
+            Some((previous_sample, previous_output)) => {
+                self.alpha * (previous_output + sample - previous_sample)
+            }
+            None => 0.0,
+        };
+        self.previous = Some((sample, output));
+        output
+    }
+}
+
+impl ButterworthLowPass {
+    /// The cutoff frequency must be below the half of the sample rate
+    pub fn new(cutoff_hz: f64, sample_rate_hz: f64) -> Self {
+        let k = (PI * cutoff_hz / sample_rate_hz).tan();
+        let norm = 1.0 / (1.0 + 2f64.sqrt() * k + k * k);
+        let b0 = k * k * norm;
+        Self {
+            b: [b0, 2.0 * b0, b0],
+            a: [
+                2.0 * (k * k - 1.0) * norm,
+                (1.0 - 2f64.sqrt() * k + k * k) * norm,
+            ],
+            previous: None,
+        }
+    }
+
+    pub fn apply(&mut self, sample: f64) -> f64 {
+        // The first sample is taken as if it lasted forever before, so the output starts settled
+        let ([x1, x2], [y1, y2]) = self
+            .previous
+            .unwrap_or(([sample, sample], [sample, sample]));
+        let output =
+            self.b[0] * sample + self.b[1] * x1 + self.b[2] * x2 - self.a[0] * y1 - self.a[1] * y2;
+        self.previous = Some(([sample, x1], [output, y1]));
+        output
+    }
+}
+
+impl OutlierRejection {
+    pub fn new(window: usize, max_deviations: f64) -> Self {
+        Self {
+            window: Window::new(window),
+            max_deviations,
+        }
+    }
+
+    pub fn apply(&mut self, sample: f64) -> f64 {
+        // Too few samples to tell an outlier
+        if self.window.samples.len() < 3 {
+            self.window.push(sample);
+            return sample;
+        }
+        let median = self.window.median();
+        let mut deviations: Vec<_> = self
+            .window
+            .samples
+            .iter()
+            .map(|sample| (sample - median).abs())
+            .collect();
+        let mean_deviation = deviations.iter().sum::<f64>() / deviations.len() as f64;
+        // Quantized samples are often equal to the median for the most part
+        let deviation = match median_of(&mut deviations) {
+            0.0 => mean_deviation,
+            deviation => deviation,
+        };
+        self.window.push(sample);
+        if (sample - median).abs() > self.max_deviations * deviation {
+            median
+        } else {
+            sample
+        }
+    }
+}
+
+impl Window {
+    fn new(size: usize) -> Self {
+        Self {
+            size,
+            samples: VecDeque::with_capacity(size),
+        }
+    }
+
+    /// Returns the sample pushed out of the window, if it's full
+    fn push(&mut self, sample: f64) -> Option<f64> {
+        let removed = (self.samples.len() == self.size)
+            .then(|| self.samples.pop_front())
+            .flatten();
+        self.samples.push_back(sample);
+        removed
+    }
+
+    fn median(&self) -> f64 {
+        median_of(&mut self.samples.iter().copied().collect::<Vec<_>>())
+    }
+}
+
+/// Median of the non-empty samples, which are reordered
+fn median_of(samples: &mut [f64]) -> f64 {
+    samples.sort_unstable_by(f64::total_cmp);
+    let middle = samples.len() / 2;
+    if samples.len().is_multiple_of(2) {
+        (samples[middle - 1] + samples[middle]) / 2.0
+    } else {
+        samples[middle]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(mut stage: Stage, samples: &[f64]) -> Vec<f64> {
+        samples.iter().map(|&sample| stage.apply(sample)).collect()
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (index, (actual, expected)) in actual.iter().zip(expected).enumerate() {
+            assert!(
+                (actual - expected).abs() < 1e-9,
+                "sample {index}: expected {expected}, got {actual}"
+            );
+        }
+    }
+
+    #[test]
+    fn moving_average_follows_a_ramp_behind_by_half_of_the_window() {
+        let output = apply(
+            Stage::MovingAverage(MovingAverage::new(3)),
+            &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0],
+        );
+        assert_close(&output, &[0.0, 0.5, 1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn median_removes_a_spike() {
+        let output = apply(
+            Stage::Median(Median::new(3)),
+            &[1.0, 1.0, 9.0, 1.0, 1.0, 1.0],
+        );
+        assert_close(&output, &[1.0; 6]);
+    }
+
+    #[test]
+    fn high_pass_decays_a_constant_to_zero() {
+        let stage = HighPass::new(1.0, 10.0);
+        let alpha = stage.alpha;
+        let mut samples = vec![0.0];
+        samples.extend([1.0; 99]);
+        let output = apply(Stage::HighPass(stage), &samples);
+
+        let expected: Vec<_> = (0..5)
+            .map(|index| match index {
+                0 => 0.0,
+                index => alpha.powi(index),
+            })
+            .collect();
+        assert_close(&output[..5], &expected);
+        assert!(output[99].abs() < 1e-9);
+    }
+
+    #[test]
+    fn butterworth_low_pass_has_a_dc_gain_of_one() {
+        let stage = Stage::ButterworthLowPass(ButterworthLowPass::new(2.0, 50.0));
+        let mut samples = vec![0.0; 10];
+        samples.extend([1.0; 190]);
+        let output = apply(stage.clone(), &samples);
+
+        assert_close(&output[..10], &[0.0; 10]);
+        assert!(output[10] > 0.0 && output[10] < 1.0);
+        assert_close(&output[190..], &[1.0; 10]);
+
+        let output = apply(stage, &[3.0; 20]);
+        assert_close(&output, &[3.0; 20]);
+    }
+
+    #[test]
+    fn outlier_rejection_replaces_outliers_with_the_median() {
+        let output = apply(
+            Stage::OutlierRejection(OutlierRejection::new(5, 3.0)),
+            // The median absolute deviation is 0 before 50, so the mean one is used,
+            // and 1 before 20
+            &[1.0, 2.0, 1.0, 2.0, 1.0, 50.0, 2.0, 20.0],
+        );
+        assert_close(&output, &[1.0, 2.0, 1.0, 2.0, 1.0, 1.0, 2.0, 2.0]);
+    }
+
+    #[test]
+    fn outlier_rejection_lets_a_lasting_change_of_the_level_through() {
+        let output = apply(
+            Stage::OutlierRejection(OutlierRejection::new(5, 3.0)),
+            &[1.0, 1.0, 1.0, 1.0, 1.0, 9.0, 9.0, 9.0, 9.0],
+        );
+        assert_close(&output, &[1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 9.0, 9.0]);
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use iot_system::domain::{Agent, ProcessedAgent, RoadState};
+
+use crate::config::{Forwarding, ForwardingPolicy};
+
+/// Decides which of the processed data of each agent are forwarded to the hub,
+/// as set up by [`ForwardingPolicy`].
+///
+/// The time is told by the timestamps of the data, so the heartbeat follows the agent's clock
+#[derive(Debug)]
+pub struct Forwarder {
+    config: Forwarding,
+    agents: HashMap<String, AgentForwarding>,
+}
+
+#[derive(Debug, Default)]
+struct AgentForwarding {
+    /// The sample of the latest processed data, forwarded or not
+    sample: Option<Agent>,
+    /// The timestamp and the road state of the latest forwarded data
+    forwarded: Option<(DateTime<Utc>, RoadState)>,
+}
+
+impl Forwarder {
+    pub fn new(config: Forwarding) -> Self {
+        Self {
+            config,
+            agents: HashMap::new(),
+        }
+    }
+
+    /// Whether the processed data of the agent is to be forwarded
+    pub fn forwards(&mut self, agent: &str, data: &ProcessedAgent) -> bool {
+        let state = self.agents.entry(agent.to_owned()).or_default();
+        let sample = data.agent_data();
+        if self.config.drop_duplicates && state.sample.as_ref() == Some(sample) {
+            return false;
+        }
+        state.sample = Some(sample.clone());
+
+        let forwards = match (self.config.policy, state.forwarded) {
+            (ForwardingPolicy::All, _) | (_, None) => true,
+            (ForwardingPolicy::RoughWithHeartbeat { heartbeat_secs }, Some((at, _))) => {
+                data.road_state() == RoadState::Rough
+                    || sample.timestamp() - at >= TimeDelta::seconds(heartbeat_secs.get() as i64)
+            }
+            (ForwardingPolicy::OnChange, Some((_, road_state))) => data.road_state() != road_state,
+        };
+        if forwards {
+            state.forwarded = Some((sample.timestamp(), data.road_state()));
+        }
+        forwards
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU64;
+
+    use chrono::Duration;
+    use iot_system::domain::{Accelerometer, Gps};
+
+    use super::*;
+
+    fn data(seconds: i64, road_state: RoadState) -> ProcessedAgent {
+        let agent = Agent::new(
+            Accelerometer::new(0.0, 0.0, 9800.0),
+            Gps::new(50.45.try_into().unwrap(), 30.52.try_into().unwrap()),
+            DateTime::UNIX_EPOCH + Duration::seconds(seconds),
+        );
+        ProcessedAgent::new(agent, road_state)
+    }
+
+    fn forwarder(policy: ForwardingPolicy, drop_duplicates: bool) -> Forwarder {
+        Forwarder::new(Forwarding {
+            policy,
+            drop_duplicates,
+        })
+    }
+
+    #[test]
+    fn smooth_road_is_forwarded_once_the_heartbeat_passes() {
+        let mut forwarder = forwarder(
+            ForwardingPolicy::RoughWithHeartbeat {
+                heartbeat_secs: NonZeroU64::new(10).unwrap(),
+            },
+            false,
+        );
+
+        // The first data tells the hub the agent is alive
+        assert!(forwarder.forwards("agent", &data(0, RoadState::Smooth)));
+        assert!(!forwarder.forwards("agent", &data(5, RoadState::Smooth)));
+        assert!(forwarder.forwards("agent", &data(6, RoadState::Rough)));
+        // The heartbeat counts from the latest forwarded data
+        assert!(!forwarder.forwards("agent", &data(15, RoadState::Smooth)));
+        assert!(forwarder.forwards("agent", &data(16, RoadState::Smooth)));
+    }
+
+    #[test]
+    fn only_the_changes_of_the_road_state_are_forwarded() {
+        let mut forwarder = forwarder(ForwardingPolicy::OnChange, false);
+
+        assert!(forwarder.forwards("agent", &data(0, RoadState::Smooth)));
+        assert!(!forwarder.forwards("agent", &data(1, RoadState::Smooth)));
+        assert!(forwarder.forwards("agent", &data(2, RoadState::Rough)));
+        assert!(!forwarder.forwards("agent", &data(3, RoadState::Rough)));
+        assert!(forwarder.forwards("agent", &data(4, RoadState::Smooth)));
+    }
+
+    #[test]
+    fn duplicates_are_dropped_only_if_configured() {
+        let mut dropping = forwarder(ForwardingPolicy::All, true);
+        assert!(dropping.forwards("agent", &data(0, RoadState::Smooth)));
+        assert!(!dropping.forwards("agent", &data(0, RoadState::Smooth)));
+        assert!(dropping.forwards("agent", &data(1, RoadState::Smooth)));
+
+        let mut keeping = forwarder(ForwardingPolicy::All, false);
+        assert!(keeping.forwards("agent", &data(0, RoadState::Smooth)));
+        assert!(keeping.forwards("agent", &data(0, RoadState::Smooth)));
+    }
+
+    #[test]
+    fn agents_are_forwarded_separately() {
+        let mut forwarder = forwarder(ForwardingPolicy::OnChange, true);
+
+        assert!(forwarder.forwards("first", &data(0, RoadState::Smooth)));
+        // Neither a change nor a duplicate of the data of the other agent
+        assert!(forwarder.forwards("second", &data(0, RoadState::Smooth)));
+        assert!(forwarder.forwards("second", &data(1, RoadState::Rough)));
+        assert!(!forwarder.forwards("first", &data(1, RoadState::Smooth)));
+    }
+}
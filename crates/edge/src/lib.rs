@@ -1,5 +1,18 @@
 pub mod adapter;
+mod calibration;
+pub mod classifier;
 pub mod config;
 mod data_processing;
+pub mod dataset;
+pub mod features;
+mod filtering;
+mod forwarding;
+mod processor;
+mod windowing;
 
+pub use calibration::{CalibrationError, Calibrator};
 pub use data_processing::process_agent_data;
+pub use filtering::{FilterError, Filters};
+pub use forwarding::Forwarder;
+pub use processor::{Processor, ProcessorError};
+pub use windowing::{FullWindow, SlidingWindow, WindowError, Windows};
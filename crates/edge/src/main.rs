@@ -7,9 +7,10 @@ use edge::{
         hub::{hub_mqtt_adapter::HubMqttAdapter, HubGateway},
     },
     config::Configuration,
-    process_agent_data,
+    Forwarder, Processor,
 };
 use iot_system::{config::TryRead, setup_tracing};
+use tokio::signal;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -17,6 +18,8 @@ async fn main() -> Result<()> {
     let _guard = setup_tracing("./logs", "lab4.log")?;
 
     let config = Configuration::try_read()?;
+    let mut processor = Processor::new(config.pipeline)?;
+    let mut forwarder = config.forwarding.map(Forwarder::new);
 
     let mut hub_adapter = HubMqttAdapter::new(config.hub_mqtt).await?;
     let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
@@ -25,16 +28,60 @@ async fn main() -> Result<()> {
         _ = agent_adapter.listen_for_data().await?;
         Ok::<(), agent_mqtt_adapter::SendError>(())
     });
-    let mut prev_data = None;
-    while let Some(data) = receiver.recv().await {
-        hub_adapter
-            .save_data(process_agent_data(
-                data.clone(),
-                prev_data.replace(data).as_ref(),
-            ))
-            .await?;
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let stopped = loop {
+        let (topic, data) = tokio::select! {
+            received = receiver.recv() => match received {
+                Some(received) => received,
+                None => break false,
+            },
+            signal = &mut shutdown => {
+                signal?;
+                tracing::info!("Shutting down");
+                break true;
+            }
+        };
+        // Each agent is told by the ID in its data, else by the topic it publishes to
+        let agent = data
+            .agent_id()
+            .map_or(topic, |agent_id| agent_id.to_string());
+        let processed = processor.process(&agent, data);
+        if let Err(err) = processor.save_calibration_if_due().await {
+            tracing::error!("Failed to save the calibration: {err}");
+        }
+        for processed_data in processed {
+            if let Some(forwarder) = &mut forwarder {
+                if !forwarder.forwards(&agent, &processed_data) {
+                    continue;
+                }
+            }
+            hub_adapter.save_data(processed_data).await?;
+        }
+    };
+    // The estimates changed since the last save would be lost otherwise
+    if let Err(err) = processor.save_calibration().await {
+        tracing::error!("Failed to save the calibration: {err}");
+    }
+    if stopped {
+        handle.abort();
+    } else {
+        handle.await??;
     }
-    handle.await??;
 
     Ok(())
 }
+
+/// Completes on Ctrl+C, or on `SIGTERM` on Unix, as sent by `docker stop`
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    signal::ctrl_c().await
+}
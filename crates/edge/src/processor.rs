@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use iot_system::domain::{Agent, ProcessedAgent};
+
+use crate::{
+    config::{Pipeline, Processing},
+    process_agent_data, CalibrationError, Calibrator, FilterError, Filters, WindowError, Windows,
+};
+
+/// Processes the data of each agent as configured by the pipeline: calibrates and filters
+/// the accelerometer samples, then classifies the road
+#[derive(Debug)]
+pub struct Processor {
+    processing: Processing,
+    calibrator: Option<Calibrator>,
+    filters: Option<Filters>,
+    windows: Option<Windows>,
+    /// The latest calibrated and filtered data of each agent
+    prev_data: HashMap<String, Agent>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProcessorError {
+    #[error(transparent)]
+    Calibration(#[from] CalibrationError),
+    #[error(transparent)]
+    Filter(#[from] FilterError),
+    #[error(transparent)]
+    Window(#[from] WindowError),
+}
+
+impl Processor {
+    pub fn new(config: Pipeline) -> Result<Self, ProcessorError> {
+        let min_speed_mps = config.processing.min_speed_mps;
+        Ok(Self {
+            calibrator: config
+                .calibration
+                .map(|calibration| Calibrator::load(calibration, min_speed_mps))
+                .transpose()?,
+            filters: config.filtering.as_ref().map(Filters::new).transpose()?,
+            windows: config
+                .windowing
+                .map(|windowing| Windows::new(windowing, min_speed_mps))
+                .transpose()?,
+            processing: config.processing,
+            prev_data: HashMap::new(),
+        })
+    }
+
+    /// Processes the data of the agent. Returns the processed data to forward,
+    /// which is none until a window is full if the samples are classified by windows
+    pub fn process(&mut self, agent: &str, data: Agent) -> Vec<ProcessedAgent> {
+        let data = match &mut self.calibrator {
+            Some(calibrator) => calibrator.calibrate(agent, data, self.prev_data.get(agent)),
+            None => data,
+        };
+        let data = match &mut self.filters {
+            Some(filters) => filters.filter(agent, data),
+            None => data,
+        };
+        let prev = self.prev_data.insert(agent.to_owned(), data.clone());
+        match &mut self.windows {
+            Some(windows) => windows.push(agent, data),
+            None => vec![process_agent_data(data, prev.as_ref(), &self.processing)],
+        }
+    }
+
+    /// Saves the calibration, if it's due
+    pub async fn save_calibration_if_due(&mut self) -> Result<(), CalibrationError> {
+        match &mut self.calibrator {
+            Some(calibrator) => calibrator.save_if_due().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Saves the calibration if it changed, e.g. before stopping
+    pub async fn save_calibration(&mut self) -> Result<(), CalibrationError> {
+        match &mut self.calibrator {
+            Some(calibrator) => calibrator.save().await,
+            None => Ok(()),
+        }
+    }
+}
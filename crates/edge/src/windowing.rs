@@ -0,0 +1,351 @@
+use std::{borrow::Borrow, collections::HashMap};
+
+use iot_system::domain::{Agent, ProcessedAgent, RoadState};
+
+use crate::{
+    classifier::{Classifier, ModelError, Verdict},
+    config::{Emit, WindowLength, Windowing},
+    data_processing::{motion, seconds_between},
+    features::Features,
+};
+
+/// Collects the samples of each agent into windows and classifies the road by the features
+/// of the whole window, instead of by each sample and the one before it
+#[derive(Debug)]
+pub struct Windows {
+    config: Windowing,
+    classifier: Classifier,
+    /// Speed below which the vehicle is stationary, in meters per second
+    min_speed_mps: f64,
+    agents: HashMap<String, SlidingWindow>,
+}
+
+/// Window sliding over the samples of one agent, as set up by the windowing
+#[derive(Debug)]
+pub struct SlidingWindow<T = Agent> {
+    samples: Vec<T>,
+    /// Number of the samples at the start of the window in the previous full window
+    emitted: usize,
+    /// Latest sample that slid out of the window
+    previous: Option<T>,
+}
+
+/// Samples of a window once it's full
+#[derive(Debug)]
+pub struct FullWindow<'a, T> {
+    pub samples: &'a [T],
+    /// Number of the samples at the start of the window in the previous full window
+    pub emitted: usize,
+    /// Latest sample that slid out of the window
+    pub previous: Option<&'a T>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WindowError {
+    #[error("The overlap of the windows of {0} must be from 0 inclusive to 1 exclusive")]
+    Overlap(f64),
+    #[error("The duration of the windows of {0} seconds must be positive")]
+    Duration(f64),
+    #[error(transparent)]
+    Model(#[from] ModelError),
+}
+
+impl Windows {
+    pub fn new(config: Windowing, min_speed_mps: f64) -> Result<Self, WindowError> {
+        if !(0.0..1.0).contains(&config.overlap) {
+            return Err(WindowError::Overlap(config.overlap));
+        }
+        if let WindowLength::Duration { duration_secs } = config.length {
+            if duration_secs.is_nan() || duration_secs <= 0.0 {
+                return Err(WindowError::Duration(duration_secs));
+            }
+        }
+        Ok(Self {
+            classifier: Classifier::new(&config.classification)?,
+            config,
+            min_speed_mps,
+            agents: HashMap::new(),
+        })
+    }
+
+    /// Adds the data to the window of the agent. Once the window is full, classifies it
+    /// and returns the processed data to emit, then slides the window
+    pub fn push(&mut self, agent: &str, data: Agent) -> Vec<ProcessedAgent> {
+        let Self {
+            config,
+            classifier,
+            min_speed_mps,
+            agents,
+        } = self;
+        agents
+            .entry(agent.to_owned())
+            .or_default()
+            .push(data, config, |window| {
+                let (first, last) = (
+                    &window.samples[0],
+                    &window.samples[window.samples.len() - 1],
+                );
+                let window_motion = motion(first, last);
+                let verdict =
+                    if window_motion.is_some_and(|motion| motion.speed_mps() < *min_speed_mps) {
+                        tracing::debug!("Stationary, the window is not classified");
+                        Verdict {
+                            road_state: RoadState::default(),
+                            confidence: None,
+                        }
+                    } else {
+                        let features = Features::of(window.samples, config.peak_height);
+                        tracing::debug!(?features);
+                        classifier.classify(&features)
+                    };
+
+                match config.emit {
+                    Emit::Window => vec![ProcessedAgent::new(last.clone(), verdict.road_state)
+                        .with_motion(window_motion)
+                        .with_confidence(verdict.confidence)],
+                    Emit::Sample => (window.emitted..window.samples.len())
+                        .map(|index| {
+                            let sample = &window.samples[index];
+                            let previous = match index.checked_sub(1) {
+                                Some(previous) => Some(&window.samples[previous]),
+                                None => window.previous,
+                            };
+                            ProcessedAgent::new(sample.clone(), verdict.road_state)
+                                .with_motion(previous.and_then(|previous| motion(previous, sample)))
+                                .with_confidence(verdict.confidence)
+                        })
+                        .collect(),
+                }
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl<T: Borrow<Agent>> SlidingWindow<T> {
+    /// Adds the sample to the window. Once the window is full, returns the result of `full`
+    /// called with it, then slides the window
+    pub fn push<R>(
+        &mut self,
+        sample: T,
+        config: &Windowing,
+        full: impl FnOnce(FullWindow<'_, T>) -> R,
+    ) -> Option<R> {
+        self.samples.push(sample);
+        if !config.length.is_full(&self.samples) {
+            return None;
+        }
+        let result = full(FullWindow {
+            samples: &self.samples,
+            emitted: self.emitted,
+            previous: self.previous.as_ref(),
+        });
+
+        let length = self.samples.len();
+        let step = ((length as f64 * (1.0 - config.overlap)).ceil() as usize).clamp(1, length);
+        self.previous = self.samples.drain(..step).next_back();
+        self.emitted = length - step;
+
+        Some(result)
+    }
+}
+
+impl<T> Default for SlidingWindow<T> {
+    fn default() -> Self {
+        Self {
+            samples: Vec::new(),
+            emitted: 0,
+            previous: None,
+        }
+    }
+}
+
+impl WindowLength {
+    fn is_full<T: Borrow<Agent>>(&self, samples: &[T]) -> bool {
+        match *self {
+            Self::Samples { samples: size } => samples.len() >= size.get(),
+            Self::Duration { duration_secs } => match samples {
+                [first, .., last] => {
+                    seconds_between(first.borrow(), last.borrow()) >= duration_secs
+                }
+                _ => false,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use chrono::{DateTime, Duration};
+    use iot_system::domain::{Accelerometer, Gps};
+
+    use super::*;
+    use crate::config::{Classification, Thresholds};
+
+    /// Full window, by the seconds the samples are taken at
+    #[derive(Debug, PartialEq)]
+    struct Full {
+        samples: Vec<i64>,
+        emitted: usize,
+        previous: Option<i64>,
+    }
+
+    fn agent(seconds: i64) -> Agent {
+        Agent::new(
+            Accelerometer::new(0.0, 0.0, 9800.0),
+            Gps::new(50.45.try_into().unwrap(), 30.52.try_into().unwrap()),
+            DateTime::UNIX_EPOCH + Duration::seconds(seconds),
+        )
+    }
+
+    fn seconds(agent: &Agent) -> i64 {
+        agent.timestamp().timestamp()
+    }
+
+    fn emitted_seconds(emitted: &[ProcessedAgent]) -> Vec<i64> {
+        emitted
+            .iter()
+            .map(|data| seconds(data.agent_data()))
+            .collect()
+    }
+
+    fn config(length: WindowLength, overlap: f64, emit: Emit) -> Windowing {
+        Windowing {
+            length,
+            overlap,
+            emit,
+            peak_height: 1000.0,
+            classification: Classification::Thresholds(Thresholds {
+                max_jerk: f64::INFINITY,
+                std_dev: f64::INFINITY,
+                peak_count: NonZeroUsize::MAX,
+            }),
+        }
+    }
+
+    fn samples(length: usize) -> WindowLength {
+        WindowLength::Samples {
+            samples: NonZeroUsize::new(length).unwrap(),
+        }
+    }
+
+    fn push(window: &mut SlidingWindow, at: i64, config: &Windowing) -> Option<Full> {
+        window.push(agent(at), config, |window| Full {
+            samples: window.samples.iter().map(seconds).collect(),
+            emitted: window.emitted,
+            previous: window.previous.map(seconds),
+        })
+    }
+
+    #[test]
+    fn window_is_full_at_its_length_and_slides_by_the_hop() {
+        // Half of the window of 4 samples is shared, so it slides by 2
+        let config = config(samples(4), 0.5, Emit::Window);
+        let mut window = SlidingWindow::default();
+
+        for at in 0..3 {
+            assert_eq!(push(&mut window, at, &config), None);
+        }
+        assert_eq!(
+            push(&mut window, 3, &config),
+            Some(Full {
+                samples: vec![0, 1, 2, 3],
+                emitted: 0,
+                previous: None,
+            })
+        );
+        assert_eq!(push(&mut window, 4, &config), None);
+        assert_eq!(
+            push(&mut window, 5, &config),
+            Some(Full {
+                samples: vec![2, 3, 4, 5],
+                emitted: 2,
+                previous: Some(1),
+            })
+        );
+    }
+
+    #[test]
+    fn window_without_overlap_starts_over() {
+        let config = config(samples(2), 0.0, Emit::Window);
+        let mut window = SlidingWindow::default();
+
+        assert_eq!(push(&mut window, 0, &config), None);
+        assert!(push(&mut window, 1, &config).is_some());
+        assert_eq!(push(&mut window, 2, &config), None);
+        assert_eq!(
+            push(&mut window, 3, &config),
+            Some(Full {
+                samples: vec![2, 3],
+                emitted: 0,
+                previous: Some(1),
+            })
+        );
+    }
+
+    #[test]
+    fn duration_window_is_full_once_its_samples_span_it() {
+        let config = config(
+            WindowLength::Duration { duration_secs: 2.0 },
+            0.0,
+            Emit::Window,
+        );
+        let mut window = SlidingWindow::default();
+
+        assert_eq!(push(&mut window, 0, &config), None);
+        assert_eq!(push(&mut window, 1, &config), None);
+        assert_eq!(
+            push(&mut window, 2, &config),
+            Some(Full {
+                samples: vec![0, 1, 2],
+                emitted: 0,
+                previous: None,
+            })
+        );
+    }
+
+    #[test]
+    fn sample_emission_skips_the_samples_emitted_with_the_previous_window() {
+        let mut windows = Windows::new(config(samples(2), 0.5, Emit::Sample), 0.0).unwrap();
+
+        assert!(windows.push("agent", agent(0)).is_empty());
+        let emitted = windows.push("agent", agent(1));
+        assert_eq!(emitted_seconds(&emitted), [0, 1]);
+        assert!(emitted[0].motion().is_none());
+
+        let emitted = windows.push("agent", agent(2));
+        assert_eq!(emitted_seconds(&emitted), [2]);
+        // The motion is from the sample emitted with the previous window
+        assert!(emitted[0].motion().is_some());
+    }
+
+    #[test]
+    fn windows_of_the_agents_are_separate() {
+        let mut windows = Windows::new(config(samples(2), 0.0, Emit::Window), 0.0).unwrap();
+
+        assert!(windows.push("first", agent(0)).is_empty());
+        assert!(windows.push("second", agent(10)).is_empty());
+        assert_eq!(emitted_seconds(&windows.push("first", agent(1))), [1]);
+        assert_eq!(emitted_seconds(&windows.push("second", agent(11))), [11]);
+    }
+
+    #[test]
+    fn invalid_windows_are_rejected() {
+        assert!(matches!(
+            Windows::new(config(samples(2), 1.0, Emit::Window), 0.0),
+            Err(WindowError::Overlap(_))
+        ));
+        assert!(matches!(
+            Windows::new(
+                config(
+                    WindowLength::Duration { duration_secs: 0.0 },
+                    0.0,
+                    Emit::Window
+                ),
+                0.0
+            ),
+            Err(WindowError::Duration(_))
+        ));
+    }
+}
@@ -0,0 +1,34 @@
+use secrecy::{ExposeSecret, SecretString};
+use tonic::{
+    metadata::{errors::InvalidMetadataValue, AsciiMetadataValue},
+    service::Interceptor,
+};
+
+/// gRPC interceptor that attaches the store API key to every request.
+#[derive(Clone)]
+pub struct ApiKeyInterceptor(Option<AsciiMetadataValue>);
+
+impl ApiKeyInterceptor {
+    pub fn new(api_key: Option<&SecretString>) -> Result<Self, InvalidMetadataValue> {
+        api_key
+            .map(|key| {
+                let mut value: AsciiMetadataValue = key.expose_secret().parse()?;
+                value.set_sensitive(true);
+                Ok(value)
+            })
+            .transpose()
+            .map(Self)
+    }
+}
+
+impl Interceptor for ApiKeyInterceptor {
+    fn call(
+        &mut self,
+        mut request: tonic::Request<()>,
+    ) -> Result<tonic::Request<()>, tonic::Status> {
+        if let Some(api_key) = &self.0 {
+            request.metadata_mut().insert("x-api-key", api_key.clone());
+        }
+        Ok(request)
+    }
+}
@@ -1,11 +1,14 @@
 use std::num::NonZeroUsize;
 
 use iot_system::config::{Mqtt, Server};
+use secrecy::SecretString;
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize)]
 pub struct Configuration {
     pub store_api: Server,
+    /// API key with the `write` scope, sent to the store with every request
+    pub store_api_key: Option<SecretString>,
     pub redis: Server,
     pub batch_size: NonZeroUsize,
     pub mqtt: Mqtt,
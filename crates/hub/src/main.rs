@@ -2,6 +2,7 @@ use std::num::NonZeroUsize;
 
 use color_eyre::eyre::WrapErr;
 use iot_system::{
+    codec::Encoding,
     config::{Mqtt, TryRead},
     domain::ProcessedAgent,
     proto::{self, store_client::StoreClient},
@@ -9,13 +10,19 @@ use iot_system::{
 };
 use redis::Commands;
 use tokio_stream::StreamExt;
-use tonic::transport::Channel;
+use tonic::{
+    codegen::InterceptedService,
+    transport::{Channel, Endpoint},
+};
 use tracing::instrument;
 
-use crate::config::Configuration;
+use crate::{auth::ApiKeyInterceptor, config::Configuration};
 
+mod auth;
 mod config;
 
+type StoreApiClient = StoreClient<InterceptedService<Channel, ApiKeyInterceptor>>;
+
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
@@ -23,6 +30,7 @@ async fn main() -> color_eyre::Result<()> {
 
     let Configuration {
         store_api: store_api_config,
+        store_api_key,
         redis: redis_config,
         batch_size,
         mqtt: mqtt_config,
@@ -30,14 +38,26 @@ async fn main() -> color_eyre::Result<()> {
 
     let redis_client = redis::Client::open(redis_config)?;
     let mqtt_client = iot_system::mqtt::connect(mqtt_config.clone()).await?;
-    let store_api_client = StoreClient::connect(store_api_config).await?;
-
-    let Mqtt { topic, .. } = mqtt_config;
-    listen_for_topic(mqtt_client, redis_client, store_api_client, batch_size, {
-        let t = topic.to_string();
-        drop(topic);
-        t
-    })
+    let store_api_client = StoreClient::with_interceptor(
+        Endpoint::try_from(store_api_config)?.connect().await?,
+        ApiKeyInterceptor::new(store_api_key.as_ref())?,
+    );
+
+    let Mqtt {
+        topic, encoding, ..
+    } = mqtt_config;
+    listen_for_topic(
+        mqtt_client,
+        redis_client,
+        store_api_client,
+        batch_size,
+        encoding,
+        {
+            let t = topic.to_string();
+            drop(topic);
+            t
+        },
+    )
     .await?;
 
     Ok(())
@@ -47,8 +67,9 @@ async fn main() -> color_eyre::Result<()> {
 async fn listen_for_topic(
     mut mqtt_client: mqtt::AsyncClient,
     mut redis_client: redis::Client,
-    store_api_client: StoreClient<Channel>,
+    store_api_client: StoreApiClient,
     batch_size: NonZeroUsize,
+    encoding: Encoding,
     topic: String,
 ) -> color_eyre::Result<()> {
     let (data_sender, data_receiver) =
@@ -60,9 +81,9 @@ async fn listen_for_topic(
 
     let mut messages = mqtt_client.get_stream(None);
     while let Some(message) = messages.next().await.flatten() {
-        let payload = message.payload();
-        let processed_agent_data: ProcessedAgent =
-            serde_json::from_slice(payload).wrap_err("Failed to decode the payload")?;
+        let processed_agent_data: ProcessedAgent = encoding
+            .decode(&message)
+            .wrap_err("Failed to decode the payload")?;
 
         tracing::info!("Received message: {processed_agent_data:?}");
 
@@ -80,8 +101,11 @@ async fn listen_for_topic(
                 break;
             };
         } else {
+            // Kept as JSON whatever the codec of the payload
+            let data = serde_json::to_vec(&processed_agent_data)
+                .wrap_err("Failed to encode the data for Redis")?;
             redis_client
-                .lpush::<_, _, ()>(REDIS_KEY, payload)
+                .lpush::<_, _, ()>(REDIS_KEY, data)
                 .wrap_err("Failed to push the data to Redis")?;
         }
     }
@@ -93,7 +117,7 @@ async fn listen_for_topic(
 
 #[instrument(skip_all)]
 async fn send_data_to_store_api(
-    mut store_api_client: StoreClient<Channel>,
+    mut store_api_client: StoreApiClient,
     mut data_receiver: tokio::sync::mpsc::UnboundedReceiver<(Vec<Vec<u8>>, ProcessedAgent)>,
 ) -> color_eyre::Result<()> {
     while let Some((data, processed_agent_data)) = data_receiver.recv().await {
@@ -104,7 +128,10 @@ async fn send_data_to_store_api(
             .map(|result| result.map(Into::into))
             .collect::<Result<_, _>>()
             .wrap_err("Failed to decode the data from Redis")?;
-        let request = tonic::Request::new(proto::Input { data });
+        let request = tonic::Request::new(proto::Input {
+            data,
+            best_effort: false,
+        });
         let ids = store_api_client
             .create_processed_agent_data(request)
             .await
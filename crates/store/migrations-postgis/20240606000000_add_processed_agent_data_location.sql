@@ -0,0 +1,8 @@
+-- Applied with the `postgis` feature only, after the common migrations
+CREATE EXTENSION IF NOT EXISTS postgis;
+
+ALTER TABLE processed_agent_data
+    ADD COLUMN location GEOGRAPHY(POINT, 4326)
+        GENERATED ALWAYS AS (ST_SetSRID(ST_MakePoint(longitude, latitude), 4326)::GEOGRAPHY) STORED;
+
+CREATE INDEX processed_agent_data_location_idx ON processed_agent_data USING GIST (location);
@@ -0,0 +1,42 @@
+-- Schema of the SQLite storage, equivalent to the Postgres migrations.
+-- Timestamps are RFC 3339 text in UTC, so that they are ordered as text
+CREATE TABLE processed_agent_data(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    road_state TEXT NOT NULL CHECK (road_state IN ('Smooth', 'Rough')),
+    x REAL NOT NULL,
+    y REAL NOT NULL,
+    z REAL NOT NULL,
+    latitude REAL NOT NULL,
+    longitude REAL NOT NULL,
+    timestamp TEXT NOT NULL,
+    idempotency_key TEXT UNIQUE,
+    version INTEGER NOT NULL DEFAULT 1,
+    deleted_at TEXT
+);
+
+CREATE INDEX processed_agent_data_timestamp_idx ON processed_agent_data (timestamp);
+CREATE INDEX processed_agent_data_latitude_idx ON processed_agent_data (latitude);
+
+CREATE TABLE audit_log(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    processed_agent_data_id INTEGER NOT NULL,
+    actor TEXT NOT NULL,
+    action TEXT NOT NULL CHECK (action IN ('Update', 'Delete', 'Restore')),
+    before TEXT,
+    after TEXT,
+    timestamp TEXT NOT NULL
+);
+
+CREATE INDEX audit_log_processed_agent_data_id_idx ON audit_log (processed_agent_data_id, id);
+
+CREATE TABLE processed_agent_data_hourly(
+    hour TEXT NOT NULL,
+    tile_latitude REAL NOT NULL,
+    tile_longitude REAL NOT NULL,
+    samples INTEGER NOT NULL,
+    rough_samples INTEGER NOT NULL,
+    mean_z REAL NOT NULL,
+    min_z REAL NOT NULL,
+    max_z REAL NOT NULL,
+    PRIMARY KEY (hour, tile_latitude, tile_longitude)
+);
@@ -0,0 +1,11 @@
+CREATE TABLE trips(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    start_time TEXT NOT NULL UNIQUE,
+    end_time TEXT NOT NULL,
+    distance_m REAL NOT NULL,
+    samples INTEGER NOT NULL,
+    rough_percentage REAL NOT NULL,
+    polyline TEXT NOT NULL
+);
+
+CREATE INDEX trips_end_time_idx ON trips (end_time);
@@ -0,0 +1,6 @@
+ALTER TABLE processed_agent_data ADD COLUMN way_id INTEGER;
+ALTER TABLE processed_agent_data ADD COLUMN way_offset_m REAL;
+ALTER TABLE processed_agent_data ADD COLUMN map_matched INTEGER NOT NULL DEFAULT FALSE;
+
+CREATE INDEX processed_agent_data_unmatched_idx ON processed_agent_data (id) WHERE NOT map_matched;
+CREATE INDEX processed_agent_data_way_id_idx ON processed_agent_data (way_id) WHERE way_id IS NOT NULL;
@@ -0,0 +1,3 @@
+ALTER TABLE processed_agent_data ADD COLUMN motion_speed_mps REAL;
+ALTER TABLE processed_agent_data ADD COLUMN motion_heading_deg REAL;
+ALTER TABLE processed_agent_data ADD COLUMN motion_distance_m REAL;
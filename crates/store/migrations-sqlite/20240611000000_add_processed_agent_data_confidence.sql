@@ -0,0 +1 @@
+ALTER TABLE processed_agent_data ADD COLUMN confidence REAL;
@@ -0,0 +1,3 @@
+ALTER TABLE processed_agent_data ADD COLUMN agent_id TEXT;
+CREATE INDEX processed_agent_data_agent_id_timestamp_idx
+    ON processed_agent_data (agent_id, timestamp);
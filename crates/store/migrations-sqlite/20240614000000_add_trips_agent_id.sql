@@ -0,0 +1,35 @@
+-- The trips are segmented from the data of each agent separately.
+-- The uniqueness of the start time alone is replaced by the one per agent,
+-- which SQLite only allows by recreating the table
+CREATE TABLE trips_by_agent(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    agent_id TEXT,
+    start_time TEXT NOT NULL,
+    end_time TEXT NOT NULL,
+    distance_m REAL NOT NULL,
+    samples INTEGER NOT NULL,
+    rough_percentage REAL NOT NULL,
+    polyline TEXT NOT NULL
+);
+INSERT INTO trips_by_agent (id, start_time, end_time, distance_m, samples, rough_percentage, polyline)
+SELECT id, start_time, end_time, distance_m, samples, rough_percentage, polyline FROM trips;
+DROP TABLE trips;
+ALTER TABLE trips_by_agent RENAME TO trips;
+CREATE UNIQUE INDEX trips_agent_id_start_time_idx ON trips (COALESCE(agent_id, ''), start_time);
+
+ALTER TABLE processed_agent_data ADD COLUMN segmented BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE processed_agent_data ADD COLUMN trip_id INTEGER;
+
+UPDATE processed_agent_data
+SET segmented = TRUE,
+    trip_id = (
+        SELECT id FROM trips
+        WHERE processed_agent_data.timestamp BETWEEN trips.start_time AND trips.end_time
+        LIMIT 1
+    )
+WHERE timestamp <= (SELECT max(end_time) FROM trips);
+
+CREATE INDEX processed_agent_data_unsegmented_idx
+    ON processed_agent_data (agent_id, timestamp, id) WHERE NOT segmented;
+CREATE INDEX processed_agent_data_trip_id_idx
+    ON processed_agent_data (trip_id, timestamp) WHERE trip_id IS NOT NULL;
@@ -0,0 +1,3 @@
+-- Client-supplied key making the ingestion of a sample idempotent
+ALTER TABLE processed_agent_data
+    ADD COLUMN idempotency_key TEXT UNIQUE;
@@ -0,0 +1,3 @@
+-- Row version for optimistic concurrency, incremented on every update and exposed as the `ETag`
+ALTER TABLE processed_agent_data
+    ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
@@ -0,0 +1,15 @@
+-- Trail of the manual changes of the processed agent data
+CREATE TYPE AUDIT_ACTION AS ENUM ('Update', 'Delete');
+
+CREATE TABLE audit_log(
+    id BIGSERIAL PRIMARY KEY NOT NULL,
+    -- Not a foreign key, so that the trail outlives the deleted rows
+    processed_agent_data_id BIGINT NOT NULL,
+    actor TEXT NOT NULL,
+    action AUDIT_ACTION NOT NULL,
+    before JSONB,
+    after JSONB,
+    timestamp TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX audit_log_processed_agent_data_id_idx ON audit_log (processed_agent_data_id, id);
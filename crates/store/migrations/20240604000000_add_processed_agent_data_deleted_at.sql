@@ -0,0 +1,8 @@
+-- Soft deletion. Deleted rows are hidden from reads and purged after the configured retention
+ALTER TABLE processed_agent_data
+    ADD COLUMN deleted_at TIMESTAMPTZ;
+
+CREATE INDEX processed_agent_data_deleted_at_idx ON processed_agent_data (deleted_at)
+    WHERE deleted_at IS NOT NULL;
+
+ALTER TYPE AUDIT_ACTION ADD VALUE 'Restore';
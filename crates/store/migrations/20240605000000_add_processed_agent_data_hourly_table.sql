@@ -0,0 +1,13 @@
+-- Hourly per-tile aggregates of the raw processed agent data older than the retention
+CREATE TABLE processed_agent_data_hourly(
+    hour TIMESTAMPTZ NOT NULL,
+    -- South-west corner of the tile
+    tile_latitude FLOAT NOT NULL,
+    tile_longitude FLOAT NOT NULL,
+    samples BIGINT NOT NULL,
+    rough_samples BIGINT NOT NULL,
+    mean_z FLOAT NOT NULL,
+    min_z FLOAT NOT NULL,
+    max_z FLOAT NOT NULL,
+    PRIMARY KEY (hour, tile_latitude, tile_longitude)
+);
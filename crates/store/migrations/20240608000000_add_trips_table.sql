@@ -0,0 +1,17 @@
+-- Trips segmented from the processed agent data at the gaps in the data and at the stops
+CREATE TABLE trips(
+    id BIGSERIAL PRIMARY KEY NOT NULL,
+    -- Unique, so that a trip segmented by several store instances is stored once
+    start_time TIMESTAMPTZ NOT NULL UNIQUE,
+    end_time TIMESTAMPTZ NOT NULL,
+    distance_m FLOAT NOT NULL,
+    samples BIGINT NOT NULL,
+    rough_percentage FLOAT NOT NULL,
+    -- Simplified route, an array of `{"latitude": ..., "longitude": ...}` objects
+    polyline JSONB NOT NULL
+);
+
+CREATE INDEX trips_end_time_idx ON trips (end_time);
+
+-- The trips are segmented from the data in the order of the timestamps
+CREATE INDEX processed_agent_data_timestamp_id_idx ON processed_agent_data (timestamp, id);
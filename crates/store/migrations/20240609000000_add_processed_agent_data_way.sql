@@ -0,0 +1,9 @@
+-- Way of the road network the data is matched to, and the distance along it in meters.
+-- Both are NULL if the data is not matched yet, or no way is near it
+ALTER TABLE processed_agent_data
+    ADD COLUMN way_id BIGINT,
+    ADD COLUMN way_offset_m FLOAT,
+    ADD COLUMN map_matched BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX processed_agent_data_unmatched_idx ON processed_agent_data (id) WHERE NOT map_matched;
+CREATE INDEX processed_agent_data_way_id_idx ON processed_agent_data (way_id) WHERE way_id IS NOT NULL;
@@ -0,0 +1,6 @@
+-- Motion since the previous GPS fix of the agent, derived by the edge.
+-- Either all of the columns are set, or none, e.g. for the first data of the agent
+ALTER TABLE processed_agent_data
+    ADD COLUMN motion_speed_mps FLOAT,
+    ADD COLUMN motion_heading_deg FLOAT,
+    ADD COLUMN motion_distance_m FLOAT;
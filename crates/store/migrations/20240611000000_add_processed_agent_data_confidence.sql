@@ -0,0 +1,3 @@
+-- Confidence of the classifier of the edge in the road state, from 0 to 1.
+-- NULL if the classifier doesn't tell one
+ALTER TABLE processed_agent_data ADD COLUMN confidence FLOAT;
@@ -0,0 +1,18 @@
+-- Idempotency keys of the processed agent data, unique across all the monthly partitions,
+-- as the unique indexes of a partitioned table must include the timestamp
+CREATE TABLE idempotency_keys (
+    key TEXT PRIMARY KEY NOT NULL,
+    id BIGINT NOT NULL,
+    timestamp TIMESTAMPTZ NOT NULL
+);
+
+INSERT INTO idempotency_keys (key, id, timestamp)
+SELECT idempotency_key, id, timestamp
+FROM processed_agent_data
+WHERE idempotency_key IS NOT NULL;
+
+-- The same index whether the table is partitioned or not, as the conflict target of the inserts
+ALTER TABLE processed_agent_data
+    DROP CONSTRAINT IF EXISTS processed_agent_data_idempotency_key_key;
+CREATE UNIQUE INDEX IF NOT EXISTS processed_agent_data_idempotency_key_timestamp_idx
+    ON processed_agent_data (idempotency_key, timestamp);
@@ -0,0 +1,4 @@
+-- Identifier of the agent which took the sample. NULL for the data of the agents not telling one
+ALTER TABLE processed_agent_data ADD COLUMN agent_id TEXT;
+CREATE INDEX processed_agent_data_agent_id_timestamp_idx
+    ON processed_agent_data (agent_id, timestamp);
@@ -0,0 +1,28 @@
+-- The trips are segmented from the data of each agent separately.
+-- The data of the agents not telling their ID is segmented as the data of a single vehicle
+ALTER TABLE trips ADD COLUMN agent_id TEXT;
+ALTER TABLE trips DROP CONSTRAINT trips_start_time_key;
+DROP INDEX trips_end_time_idx;
+-- Unique, so that a trip segmented by several store instances is stored once
+CREATE UNIQUE INDEX trips_agent_id_start_time_idx ON trips ((COALESCE(agent_id, '')), start_time);
+
+-- Each data is segmented once: into the trip it belongs to, or into none,
+-- like the data of a parked vehicle. The data arriving late is segmented
+-- along with the other data of its agent not segmented yet
+ALTER TABLE processed_agent_data ADD COLUMN segmented BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE processed_agent_data ADD COLUMN trip_id BIGINT;
+
+-- The data up to the end of the latest trip was segmented by the timestamps
+UPDATE processed_agent_data
+SET segmented = TRUE,
+    trip_id = (
+        SELECT id FROM trips
+        WHERE processed_agent_data.timestamp BETWEEN trips.start_time AND trips.end_time
+        LIMIT 1
+    )
+WHERE timestamp <= (SELECT max(end_time) FROM trips);
+
+CREATE INDEX processed_agent_data_unsegmented_idx
+    ON processed_agent_data (agent_id, timestamp, id) WHERE NOT segmented;
+CREATE INDEX processed_agent_data_trip_id_idx
+    ON processed_agent_data (trip_id, timestamp) WHERE trip_id IS NOT NULL;
@@ -0,0 +1,35 @@
+use std::sync::Arc;
+
+use derive_more::Constructor;
+use tonic::service::Interceptor;
+
+use super::{AuthError, Authenticator, Credentials, Scope, API_KEY_HEADER};
+
+/// gRPC interceptor that authenticates the caller and requires the given scope.
+///
+/// On success, the [`Principal`](super::Principal) is stored in the request extensions.
+#[derive(Clone, Constructor)]
+pub struct AuthInterceptor {
+    authenticator: Arc<Authenticator>,
+    scope: Scope,
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(
+        &mut self,
+        mut request: tonic::Request<()>,
+    ) -> Result<tonic::Request<()>, tonic::Status> {
+        let metadata = request.metadata();
+        let api_key = metadata.get(API_KEY_HEADER).and_then(|v| v.to_str().ok());
+        let authorization = metadata.get("authorization").and_then(|v| v.to_str().ok());
+        let credentials =
+            Credentials::parse(api_key, authorization).ok_or(AuthError::MissingCredentials)?;
+
+        let principal = self.authenticator.authenticate(credentials)?;
+        principal.authorize(self.scope)?;
+        tracing::debug!("Authenticated `{}`", principal.name());
+        request.extensions_mut().insert(principal);
+
+        Ok(request)
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    future::{ready, Future, Ready},
+    pin::Pin,
+    rc::Rc,
+};
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header,
+    web, HttpMessage,
+};
+
+use super::{AuthError, Authenticator, Credentials, Scope, API_KEY_HEADER};
+
+/// Middleware that authenticates the caller and requires the given scope.
+///
+/// The [`Authenticator`] is taken from the application data. On success, the
+/// [`Principal`](super::Principal) is stored in the request extensions.
+#[derive(Debug, Clone, Copy)]
+pub struct RequireScope(Scope);
+
+pub struct RequireScopeMiddleware<S> {
+    service: Rc<S>,
+    scope: Scope,
+}
+
+impl RequireScope {
+    pub fn read() -> Self {
+        Self(Scope::Read)
+    }
+
+    pub fn write() -> Self {
+        Self(Scope::Write)
+    }
+
+    pub fn admin() -> Self {
+        Self(Scope::Admin)
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequireScope
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RequireScopeMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireScopeMiddleware {
+            service: Rc::new(service),
+            scope: self.0,
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for RequireScopeMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if let Err(err) = authorize(&req, self.scope) {
+            return Box::pin(ready(Err(err.into())));
+        }
+        let service = Rc::clone(&self.service);
+        Box::pin(async move { service.call(req).await })
+    }
+}
+
+fn authorize(req: &ServiceRequest, scope: Scope) -> Result<(), AuthError> {
+    let authenticator = req
+        .app_data::<web::Data<Authenticator>>()
+        .expect("Authenticator must be registered as app data");
+
+    let headers = req.headers();
+    let api_key = headers.get(API_KEY_HEADER).and_then(|v| v.to_str().ok());
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok());
+    let credentials = Credentials::parse(api_key, authorization)
+        .or_else(|| {
+            let protocols = headers.get(header::SEC_WEBSOCKET_PROTOCOL)?.to_str().ok()?;
+            Credentials::parse_websocket_protocols(protocols).map(|(_, credentials)| credentials)
+        })
+        .ok_or(AuthError::MissingCredentials)?;
+
+    let principal = authenticator.authenticate(credentials)?;
+    principal.authorize(scope)?;
+    tracing::debug!("Authenticated `{}`", principal.name());
+    req.extensions_mut().insert(principal);
+
+    Ok(())
+}
@@ -0,0 +1,312 @@
+use std::{fs, io, path::Path, str::FromStr, sync::Arc};
+
+use actix_web::{
+    http::{header, StatusCode},
+    HttpResponse, ResponseError,
+};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use secrecy::ExposeSecret;
+use serde::Deserialize;
+
+use crate::{
+    config::{self, JwtKey},
+    error::{ErrorCode, Problem},
+};
+
+mod interceptor;
+mod middleware;
+
+pub use interceptor::AuthInterceptor;
+pub use middleware::RequireScope;
+
+/// Name of the header carrying a static API key.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Access scopes of the store API.
+///
+/// Scopes are hierarchical: `admin` implies `write`, which implies `read`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Read,
+    Write,
+    Admin,
+}
+
+/// An authenticated caller of the API.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    name: Arc<str>,
+    scopes: Arc<[Scope]>,
+}
+
+/// Credentials presented by a caller.
+#[derive(Debug, Clone, Copy)]
+pub enum Credentials<'a> {
+    ApiKey(&'a str),
+    Bearer(&'a str),
+}
+
+/// Verifies credentials against the configured API keys and JWT keys.
+pub struct Authenticator {
+    api_keys: Vec<config::ApiKey>,
+    jwt: Option<JwtVerifier>,
+}
+
+struct JwtVerifier {
+    keys: Vec<(Option<String>, DecodingKey)>,
+    validation: Validation,
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    /// Space-separated list of scopes, as in RFC 8693
+    #[serde(default)]
+    scope: String,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Self::Read),
+            "write" => Ok(Self::Write),
+            "admin" => Ok(Self::Admin),
+            _ => Err(format!("Unknown scope: {s}")),
+        }
+    }
+}
+
+impl Principal {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn has_scope(&self, required: Scope) -> bool {
+        self.scopes.iter().any(|&scope| scope >= required)
+    }
+
+    pub fn authorize(&self, required: Scope) -> Result<(), AuthError> {
+        if self.has_scope(required) {
+            Ok(())
+        } else {
+            Err(AuthError::InsufficientScope(required))
+        }
+    }
+}
+
+impl<'a> Credentials<'a> {
+    /// Extracts credentials from the values of the `X-API-Key` and `Authorization` headers.
+    /// The API key takes precedence if both are present.
+    pub fn parse(api_key: Option<&'a str>, authorization: Option<&'a str>) -> Option<Self> {
+        if let Some(key) = api_key {
+            return Some(Self::ApiKey(key.trim()));
+        }
+        let (scheme, token) = authorization?.trim().split_once(' ')?;
+        scheme
+            .eq_ignore_ascii_case("bearer")
+            .then(|| Self::Bearer(token.trim()))
+    }
+
+    /// Extracts credentials from the value of the `Sec-WebSocket-Protocol` header, as browsers
+    /// can't set the other headers of a WebSocket handshake. The `api-key` or `bearer`
+    /// subprotocol tells the kind of the credentials in the next one,
+    /// e.g. `Sec-WebSocket-Protocol: bearer, <token>`. It is returned along with them,
+    /// as the server has to accept it in the response.
+    pub fn parse_websocket_protocols(protocols: &'a str) -> Option<(&'static str, Self)> {
+        let mut protocols = protocols.split(',').map(str::trim);
+        while let Some(protocol) = protocols.next() {
+            let (accepted, credentials): (_, fn(&'a str) -> Self) = match protocol {
+                "api-key" => ("api-key", Self::ApiKey),
+                "bearer" => ("bearer", Self::Bearer),
+                _ => continue,
+            };
+            return protocols
+                .next()
+                .map(|credentials_protocol| (accepted, credentials(credentials_protocol)));
+        }
+        None
+    }
+}
+
+impl Authenticator {
+    pub fn new(config: &config::Auth) -> Result<Self, AuthSetupError> {
+        let jwt = config.jwt().map(JwtVerifier::new).transpose()?;
+        if config.api_keys().is_empty() && jwt.is_none() {
+            tracing::warn!(
+                "No API keys or JWT keys are configured, every request will be rejected"
+            );
+        }
+        Ok(Self {
+            api_keys: config.api_keys().to_vec(),
+            jwt,
+        })
+    }
+
+    pub fn authenticate(&self, credentials: Credentials<'_>) -> Result<Principal, AuthError> {
+        match credentials {
+            Credentials::ApiKey(key) => self
+                .api_keys
+                .iter()
+                .find(|api_key| {
+                    constant_time_eq(api_key.key().expose_secret().as_bytes(), key.as_bytes())
+                })
+                .map(|api_key| Principal {
+                    name: api_key.name().into(),
+                    scopes: api_key.scopes().into(),
+                })
+                .ok_or(AuthError::InvalidApiKey),
+            Credentials::Bearer(token) => self
+                .jwt
+                .as_ref()
+                .ok_or(AuthError::JwtNotSupported)?
+                .verify(token),
+        }
+    }
+}
+
+impl JwtVerifier {
+    fn new(config: &config::Jwt) -> Result<Self, AuthSetupError> {
+        let (keys, mut validation) = match config.key() {
+            JwtKey::Hs256 { secret } => (
+                vec![(
+                    None,
+                    DecodingKey::from_secret(secret.expose_secret().as_bytes()),
+                )],
+                Validation::new(Algorithm::HS256),
+            ),
+            JwtKey::Rs256 { jwks_path } => {
+                (read_jwks(jwks_path)?, Validation::new(Algorithm::RS256))
+            }
+        };
+        match config.audience() {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        if let Some(issuer) = config.issuer() {
+            validation.set_issuer(&[issuer]);
+        }
+        Ok(Self { keys, validation })
+    }
+
+    fn verify(&self, token: &str) -> Result<Principal, AuthError> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let key = match header.kid {
+            Some(kid) => self
+                .keys
+                .iter()
+                .find(|(key_id, _)| key_id.as_deref() == Some(kid.as_str())),
+            None => self.keys.first(),
+        }
+        .map(|(_, key)| key)
+        .ok_or(AuthError::UnknownKeyId)?;
+
+        let claims = jsonwebtoken::decode::<Claims>(token, key, &self.validation)?.claims;
+        let scopes = claims
+            .scope
+            .split_whitespace()
+            .filter_map(|scope| scope.parse().ok())
+            .collect();
+        Ok(Principal {
+            name: claims.sub.into(),
+            scopes,
+        })
+    }
+}
+
+fn read_jwks(path: &Path) -> Result<Vec<(Option<String>, DecodingKey)>, AuthSetupError> {
+    let jwks: JwkSet = serde_json::from_slice(&fs::read(path)?)?;
+    jwks.keys
+        .iter()
+        .map(|jwk| Ok((jwk.common.key_id.clone(), DecodingKey::from_jwk(jwk)?)))
+        .collect()
+}
+
+/// Compares two byte strings in time independent of the position of the first mismatch.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("Missing credentials")]
+    MissingCredentials,
+    #[error("Invalid API key")]
+    InvalidApiKey,
+    #[error("Bearer tokens are not accepted by this server")]
+    JwtNotSupported,
+    #[error("Token is signed with an unknown key")]
+    UnknownKeyId,
+    #[error("Invalid token: {0}")]
+    InvalidToken(
+        #[from]
+        #[source]
+        jsonwebtoken::errors::Error,
+    ),
+    #[error("The `{}` scope is required", .0.as_str())]
+    InsufficientScope(Scope),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthSetupError {
+    #[error("Failed to read the JWKS file: {0}")]
+    Io(#[from] io::Error),
+    #[error("Failed to parse the JWKS file: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("Invalid JWK: {0}")]
+    Jwk(#[from] jsonwebtoken::errors::Error),
+}
+
+impl AuthError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            Self::InsufficientScope(_) => ErrorCode::Forbidden,
+            _ => ErrorCode::Unauthorized,
+        }
+    }
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        self.code().status()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let challenge = match self {
+            Self::InsufficientScope(scope) => format!(
+                r#"Bearer error="insufficient_scope", scope="{}""#,
+                scope.as_str()
+            ),
+            Self::MissingCredentials => "Bearer".to_owned(),
+            _ => r#"Bearer error="invalid_token""#.to_owned(),
+        };
+        let mut response = Problem::new(self.code(), self.to_string(), None).response();
+        if let Ok(challenge) = header::HeaderValue::try_from(challenge) {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, challenge);
+        }
+        response
+    }
+}
+
+impl From<AuthError> for tonic::Status {
+    fn from(err: AuthError) -> Self {
+        match err {
+            AuthError::InsufficientScope(_) => tonic::Status::permission_denied(err.to_string()),
+            _ => tonic::Status::unauthenticated(err.to_string()),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+
+use crate::{
+    config,
+    control::ws::{Event, Subscribers},
+};
+
+mod postgres;
+mod redis;
+
+/// Delay before reconnecting to the bus after the connection is lost
+const RECONNECT_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Publishes the data changes to every store instance, each of which broadcasts them
+/// to its own websocket subscribers. This instance receives its own events through the bus too.
+///
+/// Publishing does not wait for the bus: the events are queued and sent by a background task.
+pub struct EventBus {
+    outbox: mpsc::UnboundedSender<Event>,
+}
+
+impl EventBus {
+    /// Connects to the configured bus, then starts sending the published events
+    /// and relaying the received ones to `subscribers`.
+    /// The Postgres bus requires the pool of the Postgres storage
+    pub async fn start(
+        config: &config::EventBus,
+        subscribers: Arc<Subscribers>,
+        pool: Option<&PgPool>,
+    ) -> Result<Self, EventBusError> {
+        let (outbox, events) = mpsc::unbounded_channel();
+        match config {
+            config::EventBus::Local => {
+                tokio::spawn(relay_local(events, subscribers));
+            }
+            config::EventBus::Postgres { channel } => {
+                let pool = pool.ok_or(EventBusError::NoPostgresStorage)?;
+                let listener = postgres::listen(pool, channel).await?;
+                tokio::spawn(postgres::relay(listener, subscribers));
+                tokio::spawn(postgres::publish(events, channel.clone(), pool.clone()));
+            }
+            config::EventBus::Redis { redis, channel } => {
+                let client = ::redis::Client::open(redis)?;
+                let pubsub = redis::subscribe(&client, channel).await?;
+                let connection = ::redis::aio::ConnectionManager::new(client.clone()).await?;
+                tokio::spawn(redis::relay(pubsub, client, channel.clone(), subscribers));
+                tokio::spawn(redis::publish(events, channel.clone(), connection));
+            }
+        }
+        tracing::info!("Event bus started: {config:?}");
+
+        Ok(Self { outbox })
+    }
+
+    pub fn publish(&self, event: Event) {
+        if self.outbox.send(event).is_err() {
+            tracing::error!("The event publisher has stopped, the event is lost");
+        }
+    }
+}
+
+async fn relay_local(mut events: mpsc::UnboundedReceiver<Event>, subscribers: Arc<Subscribers>) {
+    while let Some(event) = events.recv().await {
+        subscribers.broadcast(event);
+    }
+}
+
+/// Passes the event received from the bus to the local subscribers
+fn relay_payload(payload: &str, subscribers: &Subscribers) {
+    match serde_json::from_str(payload) {
+        Ok(event) => subscribers.broadcast(event),
+        Err(err) => tracing::error!("Received a malformed event from the bus: {err}"),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EventBusError {
+    #[error("Postgres event bus error: {0}")]
+    Postgres(#[from] sqlx::Error),
+    #[error("Postgres event bus requires the Postgres storage")]
+    NoPostgresStorage,
+    #[error("Redis event bus error: {0}")]
+    Redis(#[from] ::redis::RedisError),
+}
@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use sqlx::{postgres::PgListener, PgPool};
+use tokio::sync::mpsc;
+
+use super::{relay_payload, RECONNECT_DELAY};
+use crate::{
+    control::ws::{Event, Subscribers},
+    data::repo,
+    error::AppResult,
+};
+
+/// Maximum size of a `NOTIFY` payload in bytes, as limited by Postgres
+const MAX_PAYLOAD_SIZE: usize = 7999;
+
+pub async fn listen(pool: &PgPool, channel: &str) -> sqlx::Result<PgListener> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(channel).await?;
+    Ok(listener)
+}
+
+/// Relays the notifications to the subscribers. The listener reconnects by itself,
+/// but the notifications sent while it was disconnected are lost
+pub async fn relay(mut listener: PgListener, subscribers: Arc<Subscribers>) {
+    loop {
+        match listener.recv().await {
+            Ok(notification) => relay_payload(notification.payload(), &subscribers),
+            Err(err) => {
+                tracing::error!("Failed to receive an event from Postgres: {err}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+pub async fn publish(mut events: mpsc::UnboundedReceiver<Event>, channel: String, pool: PgPool) {
+    while let Some(event) = events.recv().await {
+        if let Err(err) = notify(event, &channel, &pool).await {
+            tracing::error!("Failed to publish an event to Postgres: {err}");
+        }
+    }
+}
+
+async fn notify(event: Event, channel: &str, pool: &PgPool) -> AppResult<()> {
+    for payload in payloads(event)? {
+        repo::postgres::notify(channel, &payload, pool).await?;
+    }
+    Ok(())
+}
+
+/// Serializes the event, splitting the created entries into several events
+/// if the payload would be too large for a single notification
+fn payloads(event: Event) -> serde_json::Result<Vec<String>> {
+    let payload = serde_json::to_string(&event)?;
+    match event {
+        Event::New { entries, batch } if payload.len() > MAX_PAYLOAD_SIZE && entries.len() > 1 => {
+            let mut first = entries;
+            let second = first.split_off(first.len() / 2);
+            let mut split = payloads(Event::New {
+                entries: first,
+                batch,
+            })?;
+            split.extend(payloads(Event::New {
+                entries: second,
+                batch,
+            })?);
+            Ok(split)
+        }
+        _ => Ok(vec![payload]),
+    }
+}
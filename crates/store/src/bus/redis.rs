@@ -0,0 +1,61 @@
+use std::sync::Arc;
+
+use redis::{aio::ConnectionManager, AsyncCommands, Client, RedisResult};
+use tokio::sync::mpsc;
+use tokio_stream::StreamExt;
+
+use super::{relay_payload, RECONNECT_DELAY};
+use crate::control::ws::{Event, Subscribers};
+
+pub async fn subscribe(client: &Client, channel: &str) -> RedisResult<redis::aio::PubSub> {
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.subscribe(channel).await?;
+    Ok(pubsub)
+}
+
+/// Relays the messages to the subscribers, resubscribing if the connection is lost.
+/// The messages sent while disconnected are lost
+pub async fn relay(
+    mut pubsub: redis::aio::PubSub,
+    client: Client,
+    channel: String,
+    subscribers: Arc<Subscribers>,
+) {
+    loop {
+        let mut messages = pubsub.into_on_message();
+        while let Some(msg) = messages.next().await {
+            match msg.get_payload::<String>() {
+                Ok(payload) => relay_payload(&payload, &subscribers),
+                Err(err) => tracing::error!("Received a malformed event from Redis: {err}"),
+            }
+        }
+        tracing::warn!("Lost the connection to Redis, resubscribing");
+
+        pubsub = loop {
+            tokio::time::sleep(RECONNECT_DELAY).await;
+            match subscribe(&client, &channel).await {
+                Ok(pubsub) => break pubsub,
+                Err(err) => tracing::error!("Failed to resubscribe to Redis: {err}"),
+            }
+        };
+    }
+}
+
+pub async fn publish(
+    mut events: mpsc::UnboundedReceiver<Event>,
+    channel: String,
+    mut connection: ConnectionManager,
+) {
+    while let Some(event) = events.recv().await {
+        let payload = match serde_json::to_string(&event) {
+            Ok(payload) => payload,
+            Err(err) => {
+                tracing::error!("Failed to serialize an event: {err}");
+                continue;
+            }
+        };
+        if let Err(err) = connection.publish::<_, _, ()>(&channel, payload).await {
+            tracing::error!("Failed to publish an event to Redis: {err}");
+        }
+    }
+}
@@ -1,13 +1,47 @@
+use std::{
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
 use iot_system::config::Server;
 use secrecy::{ExposeSecret, SecretString};
 use serde::Deserialize;
 use sqlx::postgres::PgConnectOptions;
 
+use crate::auth::Scope;
+
 #[derive(Debug, Deserialize)]
 pub struct Configuration {
+    #[serde(default)]
+    storage: Storage,
     database: Database,
     http_server: Server,
     grpc_server: Server,
+    websocket: Websocket,
+    event_bus: EventBus,
+    soft_delete: SoftDelete,
+    /// Retention is disabled if absent
+    retention: Option<Retention>,
+    /// Trip segmentation is disabled if absent
+    trips: Option<Trips>,
+    /// Map matching is disabled if absent
+    map_matching: Option<MapMatching>,
+    #[serde(default)]
+    auth: Auth,
+}
+
+/// Where the processed agent data is stored
+#[derive(Debug, Default, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Storage {
+    /// The Postgres database of the `database` section
+    #[default]
+    Postgres,
+    /// SQLite database file, created if missing
+    Sqlite { path: PathBuf },
+    /// Memory of the process. The data is lost when the store stops
+    Memory,
 }
 
 #[derive(Debug, Deserialize)]
@@ -20,7 +54,127 @@ pub struct Database {
     name: SecretString,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct Websocket {
+    /// Maximum number of messages waiting to be sent to a single subscriber
+    queue_capacity: NonZeroUsize,
+    slow_subscriber: SlowSubscriberPolicy,
+}
+
+/// What to do with a subscriber whose queue is full
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlowSubscriberPolicy {
+    /// Drop the messages that don't fit and notify the subscriber about them
+    Lag,
+    /// Close the connection
+    Disconnect,
+}
+
+/// Bus delivering the data changes to the websocket subscribers of every store instance
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum EventBus {
+    /// Only the subscribers of this instance receive the changes
+    Local,
+    /// Postgres `LISTEN/NOTIFY` on the store database
+    Postgres { channel: String },
+    /// Redis pub/sub
+    Redis { redis: Server, channel: String },
+}
+
+/// Deleted data can be restored until it is purged
+#[derive(Debug, Deserialize)]
+pub struct SoftDelete {
+    /// Days to keep the deleted data for
+    retention_days: NonZeroU32,
+    /// Seconds between the purges of the deleted data older than the retention
+    purge_interval_secs: NonZeroU64,
+}
+
+/// Raw data older than the retention is rolled into hourly per-tile aggregates, then deleted
+#[derive(Debug, Clone, Deserialize)]
+pub struct Retention {
+    /// Days to keep the raw data for
+    raw_days: NonZeroU32,
+    /// Side of the square tile the data is aggregated by, in degrees
+    tile_size_deg: f64,
+    /// Seconds between the runs of the retention job
+    interval_secs: NonZeroU64,
+    /// Only report what would be aggregated and deleted, rolling the changes back
+    #[serde(default)]
+    dry_run: bool,
+    /// Partition the table by month, so that the expired months are dropped
+    /// instead of deleted row by row. The table is converted on the first run.
+    /// Ignored by the storages other than Postgres
+    #[serde(default)]
+    partition_by_month: bool,
+}
+
+/// The data is segmented into trips, which end at the gaps in the data and at the stops
+#[derive(Debug, Clone, Deserialize)]
+pub struct Trips {
+    /// Seconds without data after which the trip ends
+    max_gap_secs: NonZeroU64,
+    /// Seconds the vehicle has to stay within the stop radius for the trip to end
+    min_stop_secs: NonZeroU64,
+    /// Radius the position of a stopped vehicle drifts within, in meters
+    stop_radius_m: f64,
+    /// Maximum deviation of the simplified polyline of a trip from its samples, in meters
+    simplify_tolerance_m: f64,
+    /// Seconds between the runs of the segmentation job
+    interval_secs: NonZeroU64,
+}
+
+/// The data is matched to the nearest way of a road network loaded at startup
+#[derive(Debug, Clone, Deserialize)]
+pub struct MapMatching {
+    /// GeoJSON file of the ways, like the one exported from an OpenStreetMap extract by
+    /// `osmium export -u type_id -f geojson`
+    road_network_path: PathBuf,
+    /// Maximum distance from the data to the way it is matched to, in meters
+    max_distance_m: f64,
+    /// Seconds between the runs of the map matching job
+    interval_secs: NonZeroU64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Auth {
+    #[serde(default)]
+    api_keys: Vec<ApiKey>,
+    jwt: Option<Jwt>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKey {
+    /// Name of the key owner, used to identify the caller
+    name: String,
+    key: SecretString,
+    scopes: Vec<Scope>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Jwt {
+    #[serde(flatten)]
+    key: JwtKey,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "algorithm")]
+pub enum JwtKey {
+    #[serde(rename = "HS256")]
+    Hs256 { secret: SecretString },
+    #[serde(rename = "RS256")]
+    Rs256 { jwks_path: PathBuf },
+}
+
 impl Configuration {
+    pub fn storage(&self) -> &Storage {
+        &self.storage
+    }
+
     pub fn database(&self) -> &Database {
         &self.database
     }
@@ -32,6 +186,34 @@ impl Configuration {
     pub fn grpc_server(&self) -> &Server {
         &self.grpc_server
     }
+
+    pub fn websocket(&self) -> &Websocket {
+        &self.websocket
+    }
+
+    pub fn event_bus(&self) -> &EventBus {
+        &self.event_bus
+    }
+
+    pub fn soft_delete(&self) -> &SoftDelete {
+        &self.soft_delete
+    }
+
+    pub fn retention(&self) -> Option<&Retention> {
+        self.retention.as_ref()
+    }
+
+    pub fn trips(&self) -> Option<&Trips> {
+        self.trips.as_ref()
+    }
+
+    pub fn map_matching(&self) -> Option<&MapMatching> {
+        self.map_matching.as_ref()
+    }
+
+    pub fn auth(&self) -> &Auth {
+        &self.auth
+    }
 }
 
 impl iot_system::config::TryRead<'_> for Configuration {}
@@ -46,3 +228,121 @@ impl Database {
             .database(self.name.expose_secret())
     }
 }
+
+impl Websocket {
+    pub fn queue_capacity(&self) -> NonZeroUsize {
+        self.queue_capacity
+    }
+
+    pub fn slow_subscriber(&self) -> SlowSubscriberPolicy {
+        self.slow_subscriber
+    }
+}
+
+impl SoftDelete {
+    pub fn retention(&self) -> chrono::Duration {
+        chrono::Duration::days(self.retention_days.get().into())
+    }
+
+    pub fn purge_interval(&self) -> Duration {
+        Duration::from_secs(self.purge_interval_secs.get())
+    }
+}
+
+impl Retention {
+    pub fn raw_retention(&self) -> chrono::Duration {
+        chrono::Duration::days(self.raw_days.get().into())
+    }
+
+    pub fn tile_size_deg(&self) -> f64 {
+        self.tile_size_deg
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.get())
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn partition_by_month(&self) -> bool {
+        self.partition_by_month
+    }
+}
+
+impl Trips {
+    pub fn max_gap(&self) -> chrono::Duration {
+        chrono::Duration::from_std(Duration::from_secs(self.max_gap_secs.get()))
+            .unwrap_or(chrono::Duration::max_value())
+    }
+
+    pub fn min_stop(&self) -> chrono::Duration {
+        chrono::Duration::from_std(Duration::from_secs(self.min_stop_secs.get()))
+            .unwrap_or(chrono::Duration::max_value())
+    }
+
+    pub fn stop_radius_m(&self) -> f64 {
+        self.stop_radius_m
+    }
+
+    pub fn simplify_tolerance_m(&self) -> f64 {
+        self.simplify_tolerance_m
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.get())
+    }
+}
+
+impl MapMatching {
+    pub fn road_network_path(&self) -> &Path {
+        &self.road_network_path
+    }
+
+    pub fn max_distance_m(&self) -> f64 {
+        self.max_distance_m
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.get())
+    }
+}
+
+impl Auth {
+    pub fn api_keys(&self) -> &[ApiKey] {
+        &self.api_keys
+    }
+
+    pub fn jwt(&self) -> Option<&Jwt> {
+        self.jwt.as_ref()
+    }
+}
+
+impl ApiKey {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn key(&self) -> &SecretString {
+        &self.key
+    }
+
+    pub fn scopes(&self) -> &[Scope] {
+        &self.scopes
+    }
+}
+
+impl Jwt {
+    pub fn key(&self) -> &JwtKey {
+        &self.key
+    }
+
+    pub fn issuer(&self) -> Option<&str> {
+        self.issuer.as_deref()
+    }
+
+    pub fn audience(&self) -> Option<&str> {
+        self.audience.as_deref()
+    }
+}
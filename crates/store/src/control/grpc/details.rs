@@ -0,0 +1,61 @@
+use prost::Message;
+
+use crate::error::Violation;
+
+const BAD_REQUEST_TYPE_URL: &str = "type.googleapis.com/google.rpc.BadRequest";
+
+/// `google.rpc.Status` from `google/rpc/status.proto`, sent in the `grpc-status-details-bin` metadata
+#[derive(Clone, PartialEq, Message)]
+struct Status {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+    #[prost(message, repeated, tag = "3")]
+    details: Vec<prost_types::Any>,
+}
+
+/// `google.rpc.BadRequest` from `google/rpc/error_details.proto`
+#[derive(Clone, PartialEq, Message)]
+struct BadRequest {
+    #[prost(message, repeated, tag = "1")]
+    field_violations: Vec<FieldViolation>,
+}
+
+/// `google.rpc.BadRequest.FieldViolation`
+#[derive(Clone, PartialEq, Message)]
+struct FieldViolation {
+    #[prost(string, tag = "1")]
+    field: String,
+    #[prost(string, tag = "2")]
+    description: String,
+}
+
+/// `INVALID_ARGUMENT` status carrying the violations as `google.rpc.BadRequest` field violations
+pub fn invalid_argument(
+    message: String,
+    violations: impl IntoIterator<Item = Violation>,
+) -> tonic::Status {
+    let bad_request = BadRequest {
+        field_violations: violations
+            .into_iter()
+            .map(|violation| FieldViolation {
+                field: violation.field().unwrap_or_default().to_owned(),
+                description: violation.message().to_owned(),
+            })
+            .collect(),
+    };
+    let status = Status {
+        code: tonic::Code::InvalidArgument as i32,
+        message: message.clone(),
+        details: vec![prost_types::Any {
+            type_url: BAD_REQUEST_TYPE_URL.to_owned(),
+            value: bad_request.encode_to_vec(),
+        }],
+    };
+    tonic::Status::with_details(
+        tonic::Code::InvalidArgument,
+        message,
+        status.encode_to_vec().into(),
+    )
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use derive_more::Constructor;
+use iot_system::{domain, proto, proto::store_server::Store};
+use tonic::{self, async_trait};
+
+pub use self::details::invalid_argument;
+use crate::{
+    bus::EventBus,
+    data::{repo::ProcessedAgentRepository, ItemResult},
+    error::Violation,
+    service,
+};
+
+mod details;
+
+#[derive(Clone, Constructor)]
+pub struct StoreService {
+    bus: Arc<EventBus>,
+    repo: Arc<dyn ProcessedAgentRepository>,
+}
+
+#[async_trait]
+impl Store for StoreService {
+    async fn create_processed_agent_data(
+        &self,
+        request: tonic::Request<proto::Input>,
+    ) -> Result<tonic::Response<proto::ProcessedAgentDataId>, tonic::Status> {
+        let input = request.into_inner();
+        if input.best_effort {
+            return self.create_best_effort(input.data).await;
+        }
+        let data = parse_all(input.data).map_err(|violations| {
+            let message = format!("{} of the items are invalid", violations.len());
+            invalid_argument(message, violations)
+        })?;
+        match <[_; 1]>::try_from(data) {
+            Ok([data]) => {
+                let id = service::create_processed_agent_data(data, &self.bus, &*self.repo).await?;
+                Ok(tonic::Response::new(proto::ProcessedAgentDataId {
+                    ids: vec![id.into()],
+                    results: vec![],
+                }))
+            }
+            Err(data) if data.is_empty() => Ok(tonic::Response::new(proto::ProcessedAgentDataId {
+                ids: vec![],
+                results: vec![],
+            })),
+            Err(data) => {
+                let ids = service::create_processed_agent_data_list(data, &self.bus, &*self.repo)
+                    .await?
+                    .into_iter()
+                    .map(Into::into)
+                    .collect();
+                Ok(tonic::Response::new(proto::ProcessedAgentDataId {
+                    ids,
+                    results: vec![],
+                }))
+            }
+        }
+    }
+}
+
+impl StoreService {
+    async fn create_best_effort(
+        &self,
+        data: Vec<proto::ProcessedAgentData>,
+    ) -> Result<tonic::Response<proto::ProcessedAgentDataId>, tonic::Status> {
+        let items = data
+            .into_iter()
+            .map(|item| domain::ProcessedAgent::try_from(item).map_err(|err| Violation::from(&err)))
+            .collect();
+        let results =
+            service::create_processed_agent_data_best_effort(items, &self.bus, &*self.repo)
+                .await?
+                .into_iter()
+                .map(Into::into)
+                .collect();
+        Ok(tonic::Response::new(proto::ProcessedAgentDataId {
+            ids: vec![],
+            results,
+        }))
+    }
+}
+
+/// Converts every item, collecting the violations of all the invalid ones
+fn parse_all(
+    data: Vec<proto::ProcessedAgentData>,
+) -> Result<Vec<domain::ProcessedAgent>, Vec<Violation>> {
+    let mut parsed = Vec::with_capacity(data.len());
+    let mut violations = Vec::new();
+    for (index, item) in data.into_iter().enumerate() {
+        match domain::ProcessedAgent::try_from(item) {
+            Ok(item) => parsed.push(item),
+            Err(err) => violations.push(Violation::from(&err).within(&format!("[{index}]"))),
+        }
+    }
+    if violations.is_empty() {
+        Ok(parsed)
+    } else {
+        Err(violations)
+    }
+}
+
+impl From<ItemResult> for proto::ItemResult {
+    fn from(value: ItemResult) -> Self {
+        let result = match value {
+            ItemResult::Created { id } => proto::item_result::Result::Id(id.into()),
+            ItemResult::Failed { error } => proto::item_result::Result::Error(proto::ItemError {
+                code: error.code().as_str().to_owned(),
+                field: error.field().unwrap_or_default().to_owned(),
+                message: error.message().to_owned(),
+            }),
+        };
+        Self {
+            result: Some(result),
+        }
+    }
+}
@@ -6,21 +6,32 @@ use std::{
 use actix_web::{
     delete, get,
     http::header,
-    post, put,
-    web::{Data, Json, Path, Query},
-    Either, HttpResponse,
+    patch, post, put,
+    web::{Data, Json, Path, Query, ReqData},
+    HttpRequest, HttpResponse,
 };
 use serde::{Deserialize, Deserializer};
 use tracing::instrument;
 use utoipa::IntoParams;
 
 use crate::{
-    control::ws,
-    data::{ProcessedAgent, ProcessedAgentId, ProcessedAgentWithId},
+    auth::{AuthError, Principal, RequireScope, Scope},
+    bus::EventBus,
+    data::{
+        repo::ProcessedAgentRepository, Agent, AuditEntry, ItemResult, Latitude, Longitude,
+        NearbyProcessedAgent, ProcessedAgent, ProcessedAgentId, ProcessedAgentWithId, RoadQuality,
+        TripId, TripWithId, Version,
+    },
+    error::{AppError, ErrorCode, Violation},
+    jobs::{RetentionMetrics, RetentionStatus},
+    map_matching::RoadNetwork,
     service,
 };
 
 /// Post a single/list of processed agent data and notify ws subscribers
+///
+/// Samples whose `idempotency_key` is already stored are not inserted again,
+/// the locations of the stored samples are returned instead
 #[utoipa::path(
     path = "/api/processed-agent-data",
     request_body(
@@ -39,7 +50,9 @@ use crate::{
                         "latitude": 0.0,
                         "longitude": 0.0
                     },
-                    "timestamp": "2023-10-01T00:00:00Z"
+                    "timestamp": "2023-10-01T00:00:00Z",
+                    "idempotency_key": "0f8fad5b-d9cb-469f-a165-70867728950e",
+                    "agent_id": "AA1234BB"
                 })
             )),
             ("List" = (
@@ -65,37 +78,33 @@ use crate::{
             status = 201,
             headers(("Location" = Vec<String>, description = "Locations of the created resources")),
         ),
-        (status = 400, description = "Invalid request body"),
-        (status = "5XX", description = "Internal server error")
-    )
+        (status = 400, description = "Request body is not valid JSON", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid processed agent data. `field` points at the offending field, e.g. `[1].gps.latitude`", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The `write` scope is required", body = Problem, content_type = "application/problem+json"),
+        (status = "5XX", description = "Internal server error", body = Problem, content_type = "application/problem+json")
+    ),
+    security(("api_key" = ["write"]), ("bearer" = ["write"]))
 )]
-#[post("/processed-agent-data")]
-#[instrument(skip(subs, pool))]
+#[post("/processed-agent-data", wrap = "RequireScope::write()")]
+#[instrument(skip(bus, repo))]
 pub async fn create_processed_agent_data(
-    data: Either<Json<ProcessedAgent>, Json<Vec<ProcessedAgent>>>,
-    subs: Data<ws::Subscribers>,
-    pool: Data<sqlx::PgPool>,
+    Json(data): Json<serde_json::Value>,
+    bus: Data<EventBus>,
+    repo: Data<dyn ProcessedAgentRepository>,
 ) -> actix_web::Result<HttpResponse> {
     let result = match data {
-        Either::Right(Json(data)) if data.is_empty() => HttpResponse::Ok().finish(),
-        Either::Right(Json(data)) if data.len() == 1 => {
-            let [data] = unsafe { <[_; 1] as TryFrom<Vec<_>>>::try_from(data).unwrap_unchecked() };
-            let id = service::create_processed_agent_data(data, &subs, &pool).await?;
-            HttpResponse::Created()
-                .append_header((
-                    header::LOCATION,
-                    format!(r#"["/api/processed-agent-data/{id}"]"#),
-                ))
-                .finish()
-        }
-        Either::Left(Json(data)) => {
-            let id = service::create_processed_agent_data(data, &subs, &pool).await?;
-            HttpResponse::Created()
-                .append_header((header::LOCATION, format!("/api/processed-agent-data/{id}")))
-                .finish()
-        }
-        Either::Right(Json(data)) => {
-            let ids = service::create_processed_agent_data_list(data, &subs, &pool).await?;
+        serde_json::Value::Array(data) if data.is_empty() => HttpResponse::Ok().finish(),
+        serde_json::Value::Array(data) => {
+            let data = data
+                .into_iter()
+                .enumerate()
+                .map(|(index, item)| {
+                    parse_item(item)
+                        .map_err(|err| AppError::Invalid(err.within(&format!("[{index}]"))))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let ids = service::create_processed_agent_data_list(data, &bus, &**repo).await?;
             let mut response = HttpResponse::Created();
             response.append_header((
                 header::LOCATION,
@@ -107,19 +116,90 @@ pub async fn create_processed_agent_data(
             ));
             response.finish()
         }
+        data @ serde_json::Value::Object(_) => {
+            let data = parse_item(data).map_err(AppError::Invalid)?;
+            let id = service::create_processed_agent_data(data, &bus, &**repo).await?;
+            HttpResponse::Created()
+                .append_header((header::LOCATION, format!("/api/processed-agent-data/{id}")))
+                .finish()
+        }
+        _ => {
+            return Err(AppError::Invalid(Violation::new(
+                ErrorCode::InvalidValue,
+                None,
+                "expected an object or an array of objects",
+            ))
+            .into())
+        }
     };
     Ok(result)
 }
 
+/// Post a list of processed agent data, storing the valid items even if some are invalid,
+/// and notify ws subscribers about the stored ones
+#[utoipa::path(
+    path = "/api/processed-agent-data/best-effort",
+    request_body(
+        content = Vec<ProcessedAgent>,
+        description = "Processed agent data to post. Invalid items are reported and skipped",
+    ),
+    responses(
+        (
+            status = 200,
+            body = Vec<ItemResult>,
+            description = "Outcome of each item, in the order of the request",
+            example = json!([
+                {"id": 1},
+                {"error": {
+                    "code": "invalid_value",
+                    "field": "gps.latitude",
+                    "message": "latitude must be in range -90..90"
+                }},
+                {"error": {
+                    "code": "missing_field",
+                    "field": "timestamp",
+                    "message": "missing field `timestamp`"
+                }}
+            ])
+        ),
+        (status = 400, description = "Request body is not valid JSON", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Request body is not an array", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The `write` scope is required", body = Problem, content_type = "application/problem+json"),
+        (status = "5XX", description = "Internal server error", body = Problem, content_type = "application/problem+json")
+    ),
+    security(("api_key" = ["write"]), ("bearer" = ["write"]))
+)]
+#[post("/processed-agent-data/best-effort", wrap = "RequireScope::write()")]
+#[instrument(skip_all, fields(len = data.len()))]
+pub async fn create_processed_agent_data_best_effort(
+    Json(data): Json<Vec<serde_json::Value>>,
+    bus: Data<EventBus>,
+    repo: Data<dyn ProcessedAgentRepository>,
+) -> actix_web::Result<Json<Vec<ItemResult>>> {
+    let items = data.into_iter().map(parse_item).collect();
+    let results = service::create_processed_agent_data_best_effort(items, &bus, &**repo).await?;
+    Ok(Json(results))
+}
+
+/// Deserializes the processed agent data, tracking the path of the offending field
+fn parse_item(item: serde_json::Value) -> Result<ProcessedAgent, Violation> {
+    // The agent data is flattened into `ProcessedAgent`, which hides the paths of its fields,
+    // so it is checked on its own first
+    serde_path_to_error::deserialize::<_, Agent>(&item).map_err(Violation::from_json)?;
+    serde_path_to_error::deserialize(item).map_err(Violation::from_json)
+}
+
 /// Read a single processed agent data by ID
 #[utoipa::path(
     path = "/api/processed-agent-data/{id}",
-    params(ProcessedAgentId),
+    params(ProcessedAgentId, Visibility),
     responses(
         (
             status = 200,
             body = ProcessedAgent,
             description = "A single processed agent data, corresponding to the given id",
+            headers(("ETag" = String, description = "Version of the data, for `If-Match`")),
             example = json!({
                 "road_state": "NORMAL",
                 "accelerometer": {
@@ -131,47 +211,72 @@ pub async fn create_processed_agent_data(
                     "latitude": 0.0,
                     "longitude": 0.0
                 },
-                "timestamp": "2023-10-01T00:00:00Z"
+                "timestamp": "2023-10-01T00:00:00Z",
+                "motion": {
+                    "speed_mps": 12.5,
+                    "heading_deg": 90.0,
+                    "distance_m": 12.5
+                },
+                "confidence": 0.92
             }),
         ),
-        (status = 400, description = "Invalid ID"),
-        (status = 404, description = "Processed agent data not found"),
-        (status = "5XX", description = "Internal server error")
-    )
+        (status = 400, description = "Invalid ID", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The `read` scope is required, or `admin` with `include_deleted`", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Processed agent data not found or deleted", body = Problem, content_type = "application/problem+json"),
+        (status = "5XX", description = "Internal server error", body = Problem, content_type = "application/problem+json")
+    ),
+    security(("api_key" = ["read"]), ("bearer" = ["read"]))
 )]
-#[get("/processed-agent-data/{id}")]
-#[instrument(skip(pool))]
+#[get("/processed-agent-data/{id}", wrap = "RequireScope::read()")]
+#[instrument(skip(repo))]
 pub async fn read_processed_agent_data(
     id: Path<ProcessedAgentId>,
-    pool: Data<sqlx::PgPool>,
-) -> actix_web::Result<Option<Json<ProcessedAgent>>> {
-    let result = service::fetch_processed_agent_data(id.into_inner(), &pool).await?;
-    Ok(result.map(Json))
+    visibility: Query<Visibility>,
+    principal: ReqData<Principal>,
+    repo: Data<dyn ProcessedAgentRepository>,
+) -> actix_web::Result<HttpResponse> {
+    let include_deleted = visibility.include_deleted(&principal)?;
+    let (data, version) =
+        service::fetch_processed_agent_data(id.into_inner(), include_deleted, &**repo)
+            .await?
+            .ok_or(AppError::NotFound("Processed agent data"))?;
+    Ok(HttpResponse::Ok().insert_header(etag(version)).json(data))
 }
 
 /// Read a list of processed agent data
 #[utoipa::path(
     path = "/api/processed-agent-data",
-    params(Pagination),
+    params(Pagination, Visibility),
     responses(
         (
             status = 200,
             body = Vec<ProcessedAgentWithId>,
             description = "List of processed agent data"
         ),
-        (status = 400, description = "Invalid pagination parameters"),
-        (status = "5XX", description = "Internal server error")
-    )
+        (status = 400, description = "Invalid pagination parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The `read` scope is required, or `admin` with `include_deleted`", body = Problem, content_type = "application/problem+json"),
+        (status = "5XX", description = "Internal server error", body = Problem, content_type = "application/problem+json")
+    ),
+    security(("api_key" = ["read"]), ("bearer" = ["read"]))
 )]
-#[get("/processed-agent-data")]
-#[instrument(skip(pool))]
+#[get("/processed-agent-data", wrap = "RequireScope::read()")]
+#[instrument(skip(repo))]
 pub async fn read_processed_agent_data_list(
     pagination: Query<Pagination>,
-    pool: Data<sqlx::PgPool>,
+    visibility: Query<Visibility>,
+    principal: ReqData<Principal>,
+    repo: Data<dyn ProcessedAgentRepository>,
 ) -> actix_web::Result<Json<Vec<ProcessedAgentWithId>>> {
-    let result =
-        service::fetch_processed_agent_data_list(pagination.page.0, pagination.size.0, &pool)
-            .await?;
+    let include_deleted = visibility.include_deleted(&principal)?;
+    let result = service::fetch_processed_agent_data_list(
+        pagination.page.0,
+        pagination.size.0,
+        include_deleted,
+        &**repo,
+    )
+    .await?;
     Ok(Json(result))
 }
 
@@ -187,6 +292,14 @@ struct Pagination {
     size: PageSize,
 }
 
+#[derive(Debug, Default, Deserialize, IntoParams)]
+struct Visibility {
+    /// Whether to include the deleted data. Requires the `admin` scope
+    #[serde(default)]
+    #[param(default = false)]
+    include_deleted: bool,
+}
+
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Deserialize)]
 #[repr(transparent)]
 #[serde(transparent)]
@@ -196,10 +309,128 @@ struct PageNumber(NonZeroU32);
 #[repr(transparent)]
 struct PageSize(NonZeroU8);
 
+/// Read the processed agent data within a radius around a point, nearest first
+#[utoipa::path(
+    path = "/api/processed-agent-data/near",
+    params(Near),
+    responses(
+        (
+            status = 200,
+            body = Vec<NearbyProcessedAgent>,
+            description = "Processed agent data within the radius, nearest first",
+            example = json!([{
+                "id": 1,
+                "road_state": "SMOOTH",
+                "accelerometer": {"x": 0.0, "y": 0.0, "z": 0.0},
+                "gps": {"latitude": 50.45, "longitude": 30.52},
+                "timestamp": "2023-10-01T00:00:00Z",
+                "distance_m": 12.5
+            }])
+        ),
+        (status = 400, description = "Invalid point, radius or limit", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The `read` scope is required", body = Problem, content_type = "application/problem+json"),
+        (status = "5XX", description = "Internal server error", body = Problem, content_type = "application/problem+json")
+    ),
+    security(("api_key" = ["read"]), ("bearer" = ["read"]))
+)]
+#[get("/processed-agent-data/near", wrap = "RequireScope::read()")]
+#[instrument(skip(repo))]
+pub async fn read_processed_agent_data_near(
+    near: Query<Near>,
+    repo: Data<dyn ProcessedAgentRepository>,
+) -> actix_web::Result<Json<Vec<NearbyProcessedAgent>>> {
+    let result = service::fetch_processed_agent_data_near(
+        near.lat,
+        near.lon,
+        Some(near.radius_m.0),
+        near.limit.0.get().into(),
+        &**repo,
+    )
+    .await?;
+    Ok(Json(result))
+}
+
+/// Read the processed agent data nearest to a point
+#[utoipa::path(
+    path = "/api/processed-agent-data/nearest",
+    params(Nearest),
+    responses(
+        (
+            status = 200,
+            body = Vec<NearbyProcessedAgent>,
+            description = "Processed agent data nearest to the point, nearest first"
+        ),
+        (status = 400, description = "Invalid point or limit", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The `read` scope is required", body = Problem, content_type = "application/problem+json"),
+        (status = "5XX", description = "Internal server error", body = Problem, content_type = "application/problem+json")
+    ),
+    security(("api_key" = ["read"]), ("bearer" = ["read"]))
+)]
+#[get("/processed-agent-data/nearest", wrap = "RequireScope::read()")]
+#[instrument(skip(repo))]
+pub async fn read_processed_agent_data_nearest(
+    nearest: Query<Nearest>,
+    repo: Data<dyn ProcessedAgentRepository>,
+) -> actix_web::Result<Json<Vec<NearbyProcessedAgent>>> {
+    let result = service::fetch_processed_agent_data_near(
+        nearest.lat,
+        nearest.lon,
+        None,
+        nearest.limit.0.get().into(),
+        &**repo,
+    )
+    .await?;
+    Ok(Json(result))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+struct Near {
+    /// Latitude of the point, in degrees
+    #[param(minimum = -90.0, maximum = 90.0, value_type = f64)]
+    lat: Latitude,
+    /// Longitude of the point, in degrees
+    #[param(minimum = -180.0, maximum = 180.0, value_type = f64)]
+    lon: Longitude,
+    /// Radius around the point, in meters
+    #[param(exclusive_minimum = 0.0, value_type = f64)]
+    radius_m: Radius,
+    /// The maximum number of items, between 1 and 100
+    #[serde(default)]
+    #[param(minimum = 1, maximum = 100, value_type = u8, default = 10)]
+    limit: NearbyLimit,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+struct Nearest {
+    /// Latitude of the point, in degrees
+    #[param(minimum = -90.0, maximum = 90.0, value_type = f64)]
+    lat: Latitude,
+    /// Longitude of the point, in degrees
+    #[param(minimum = -180.0, maximum = 180.0, value_type = f64)]
+    lon: Longitude,
+    /// The number of items, between 1 and 100
+    #[serde(default)]
+    #[param(minimum = 1, maximum = 100, value_type = u8, default = 10)]
+    limit: NearbyLimit,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)] // `Deserialize` is derived manually
+#[repr(transparent)]
+struct Radius(f64);
+
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)] // `Deserialize` is derived manually
+#[repr(transparent)]
+struct NearbyLimit(NonZeroU8);
+
 /// Update a single processed agent data and notify ws subscribers
 #[utoipa::path(
     path = "/api/processed-agent-data/{id}",
-    params(ProcessedAgentId),
+    params(
+        ProcessedAgentId,
+        ("If-Match" = Option<String>, Header, description = "Apply only if the current `ETag` of the data is one of the given ones, or `*`")
+    ),
     request_body(
         content = ProcessedAgent,
         description = "New processed agent data to replace the existing one",
@@ -218,52 +449,505 @@ struct PageSize(NonZeroU8);
         }),
     ),
     responses(
-        (status = 204, description = "Processed agent data updated"),
-        (status = 400, description = "Invalid ID or request body"),
-        (status = 404, description = "Processed agent data for the given ID was not found"),
-        (status = "5XX", description = "Internal server error")
-    )
+        (
+            status = 204,
+            description = "Processed agent data updated",
+            headers(("ETag" = String, description = "New version of the data")),
+        ),
+        (status = 400, description = "Invalid ID, `If-Match` or malformed request body", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The `admin` scope is required", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Processed agent data for the given ID was not found", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "The data was modified since the version given in `If-Match`, or is absent", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid processed agent data", body = Problem, content_type = "application/problem+json"),
+        (status = "5XX", description = "Internal server error", body = Problem, content_type = "application/problem+json")
+    ),
+    security(("api_key" = ["admin"]), ("bearer" = ["admin"]))
 )]
-#[put("/processed-agent-data/{id}")]
-#[instrument(skip(pool, subs))]
+#[put("/processed-agent-data/{id}", wrap = "RequireScope::admin()")]
+#[instrument(skip(repo, bus))]
 pub async fn update_processed_agent_data(
     id: Path<ProcessedAgentId>,
-    data: Json<ProcessedAgent>,
-    subs: Data<ws::Subscribers>,
-    pool: Data<sqlx::PgPool>,
+    Json(data): Json<serde_json::Value>,
+    req: HttpRequest,
+    principal: ReqData<Principal>,
+    bus: Data<EventBus>,
+    repo: Data<dyn ProcessedAgentRepository>,
 ) -> actix_web::Result<HttpResponse> {
     let id = id.into_inner();
-    let data = data.into_inner();
-    let updated = service::update_processed_agent_data(id, data, &pool, &subs).await?;
-    Ok(if updated {
-        HttpResponse::NoContent().finish()
-    } else {
-        HttpResponse::NotFound().finish()
-    })
+    let expected = if_match(&req)?;
+    let data = parse_item(data).map_err(AppError::Invalid)?;
+    let version = service::update_processed_agent_data(
+        id,
+        data,
+        expected.versions.as_deref(),
+        principal.name(),
+        &**repo,
+        &bus,
+    )
+    .await
+    .map_err(|err| expected.absent(err))?;
+    Ok(HttpResponse::NoContent()
+        .insert_header(etag(version))
+        .finish())
+}
+
+/// Partially update a single processed agent data with a JSON Merge Patch (RFC 7396)
+/// and notify ws subscribers
+///
+/// Members of the patch replace the corresponding members of the data, `null` members are removed.
+/// The data is validated after the patch is applied
+#[utoipa::path(
+    path = "/api/processed-agent-data/{id}",
+    params(
+        ProcessedAgentId,
+        ("If-Match" = Option<String>, Header, description = "Apply only if the current `ETag` of the data is one of the given ones, or `*`")
+    ),
+    request_body(
+        content = serde_json::Value,
+        content_type = "application/merge-patch+json",
+        description = "Merge patch to apply to the existing data",
+        example = json!({
+            "road_state": "ROUGH"
+        }),
+    ),
+    responses(
+        (
+            status = 204,
+            description = "Processed agent data updated",
+            headers(("ETag" = String, description = "New version of the data")),
+        ),
+        (status = 400, description = "Invalid ID, `If-Match` or malformed request body", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The `admin` scope is required", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Processed agent data for the given ID was not found", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "The data was modified since the version given in `If-Match` or while being patched, or is absent", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "The patched processed agent data is invalid", body = Problem, content_type = "application/problem+json"),
+        (status = "5XX", description = "Internal server error", body = Problem, content_type = "application/problem+json")
+    ),
+    security(("api_key" = ["admin"]), ("bearer" = ["admin"]))
+)]
+#[patch("/processed-agent-data/{id}", wrap = "RequireScope::admin()")]
+#[instrument(skip(repo, bus))]
+pub async fn patch_processed_agent_data(
+    id: Path<ProcessedAgentId>,
+    Json(patch): Json<serde_json::Value>,
+    req: HttpRequest,
+    principal: ReqData<Principal>,
+    bus: Data<EventBus>,
+    repo: Data<dyn ProcessedAgentRepository>,
+) -> actix_web::Result<HttpResponse> {
+    let id = id.into_inner();
+    let expected = if_match(&req)?;
+    let (data, version) = service::fetch_processed_agent_data(id, false, &**repo)
+        .await?
+        .ok_or_else(|| expected.absent(AppError::NotFound("Processed agent data")))?;
+    if expected
+        .versions
+        .as_ref()
+        .is_some_and(|expected| !expected.contains(&version))
+    {
+        return Err(AppError::PreconditionFailed.into());
+    }
+
+    let mut data = serde_json::to_value(data)?;
+    merge_patch(&mut data, patch);
+    let data = parse_item(data).map_err(AppError::Invalid)?;
+    // Fails if the data was modified after it was fetched
+    let version = service::update_processed_agent_data(
+        id,
+        data,
+        Some(&[version]),
+        principal.name(),
+        &**repo,
+        &bus,
+    )
+    .await?;
+    Ok(HttpResponse::NoContent()
+        .insert_header(etag(version))
+        .finish())
+}
+
+/// Applies a JSON Merge Patch to the target as defined by RFC 7396
+fn merge_patch(target: &mut serde_json::Value, patch: serde_json::Value) {
+    let serde_json::Value::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = serde_json::Value::Object(serde_json::Map::new());
+    }
+    if let serde_json::Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(&key);
+            } else {
+                merge_patch(target.entry(key).or_insert(serde_json::Value::Null), value);
+            }
+        }
+    }
 }
 
 /// Delete a single processed agent data and notify ws subscribers
+///
+/// The data can be restored until it is purged after the configured retention
 #[utoipa::path(
     path = "/api/processed-agent-data/{id}",
-    params(ProcessedAgentId),
+    params(
+        ProcessedAgentId,
+        ("If-Match" = Option<String>, Header, description = "Apply only if the current `ETag` of the data is one of the given ones, or `*`")
+    ),
     responses(
         (status = 204, description = "Processed agent data deleted or was not present in the first place"),
-        (status = 400, description = "Invalid ID"),
-        (status = "5XX", description = "Internal server error")
-    )
+        (status = 400, description = "Invalid ID or `If-Match`", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The `admin` scope is required", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "The data was modified since the version given in `If-Match`, or is absent", body = Problem, content_type = "application/problem+json"),
+        (status = "5XX", description = "Internal server error", body = Problem, content_type = "application/problem+json")
+    ),
+    security(("api_key" = ["admin"]), ("bearer" = ["admin"]))
 )]
-#[delete("/processed-agent-data/{id}")]
-#[instrument(skip(pool, subs))]
+#[delete("/processed-agent-data/{id}", wrap = "RequireScope::admin()")]
+#[instrument(skip(repo, bus))]
 pub async fn delete_processed_agent_data(
     id: Path<ProcessedAgentId>,
-    subs: Data<ws::Subscribers>,
-    pool: Data<sqlx::PgPool>,
+    req: HttpRequest,
+    principal: ReqData<Principal>,
+    bus: Data<EventBus>,
+    repo: Data<dyn ProcessedAgentRepository>,
 ) -> actix_web::Result<HttpResponse> {
     let id = id.into_inner();
-    service::delete_processed_agent_data(id, &pool, &subs).await?;
+    let expected = if_match(&req)?;
+    let deleted = service::delete_processed_agent_data(
+        id,
+        expected.versions.as_deref(),
+        principal.name(),
+        &**repo,
+        &bus,
+    )
+    .await?;
+    if !deleted && expected.present {
+        return Err(AppError::PreconditionFailed.into());
+    }
     Ok(HttpResponse::NoContent().finish())
 }
 
+/// Restore a single deleted processed agent data and notify ws subscribers
+///
+/// Restoring data that isn't deleted does nothing
+#[utoipa::path(
+    path = "/api/processed-agent-data/{id}/restore",
+    params(
+        ProcessedAgentId,
+        ("If-Match" = Option<String>, Header, description = "Apply only if the current `ETag` of the data is one of the given ones, or `*`")
+    ),
+    responses(
+        (
+            status = 204,
+            description = "Processed agent data restored",
+            headers(("ETag" = String, description = "New version of the data")),
+        ),
+        (status = 400, description = "Invalid ID or `If-Match`", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The `admin` scope is required", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Processed agent data was never stored or is already purged", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "The data was modified since the version given in `If-Match`, or is absent", body = Problem, content_type = "application/problem+json"),
+        (status = "5XX", description = "Internal server error", body = Problem, content_type = "application/problem+json")
+    ),
+    security(("api_key" = ["admin"]), ("bearer" = ["admin"]))
+)]
+#[post("/processed-agent-data/{id}/restore", wrap = "RequireScope::admin()")]
+#[instrument(skip(repo, bus))]
+pub async fn restore_processed_agent_data(
+    id: Path<ProcessedAgentId>,
+    req: HttpRequest,
+    principal: ReqData<Principal>,
+    bus: Data<EventBus>,
+    repo: Data<dyn ProcessedAgentRepository>,
+) -> actix_web::Result<HttpResponse> {
+    let id = id.into_inner();
+    let expected = if_match(&req)?;
+    let version = service::restore_processed_agent_data(
+        id,
+        expected.versions.as_deref(),
+        principal.name(),
+        &**repo,
+        &bus,
+    )
+    .await
+    .map_err(|err| expected.absent(err))?;
+    Ok(HttpResponse::NoContent()
+        .insert_header(etag(version))
+        .finish())
+}
+
+/// Read the audit trail of a single processed agent data, oldest first
+///
+/// The trail of deleted data is kept
+#[utoipa::path(
+    path = "/api/processed-agent-data/{id}/history",
+    params(ProcessedAgentId),
+    responses(
+        (
+            status = 200,
+            body = Vec<AuditEntry>,
+            description = "Updates and deletions of the processed agent data",
+            example = json!([{
+                "id": 1,
+                "actor": "admin",
+                "action": "update",
+                "before": {
+                    "road_state": "SMOOTH",
+                    "accelerometer": {"x": 0.0, "y": 0.0, "z": 0.0},
+                    "gps": {"latitude": 0.0, "longitude": 0.0},
+                    "timestamp": "2023-10-01T00:00:00Z"
+                },
+                "after": {
+                    "road_state": "ROUGH",
+                    "accelerometer": {"x": 0.0, "y": 0.0, "z": 0.0},
+                    "gps": {"latitude": 0.0, "longitude": 0.0},
+                    "timestamp": "2023-10-01T00:00:00Z"
+                },
+                "timestamp": "2023-10-02T00:00:00Z"
+            }])
+        ),
+        (status = 400, description = "Invalid ID", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The `admin` scope is required", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Processed agent data neither exists nor has a history", body = Problem, content_type = "application/problem+json"),
+        (status = "5XX", description = "Internal server error", body = Problem, content_type = "application/problem+json")
+    ),
+    security(("api_key" = ["admin"]), ("bearer" = ["admin"]))
+)]
+#[get("/processed-agent-data/{id}/history", wrap = "RequireScope::admin()")]
+#[instrument(skip(repo))]
+pub async fn read_processed_agent_data_history(
+    id: Path<ProcessedAgentId>,
+    repo: Data<dyn ProcessedAgentRepository>,
+) -> actix_web::Result<Json<Vec<AuditEntry>>> {
+    let id = id.into_inner();
+    let history = service::fetch_processed_agent_data_history(id, &**repo).await?;
+    if history.is_empty()
+        && service::fetch_processed_agent_data(id, true, &**repo)
+            .await?
+            .is_none()
+    {
+        return Err(AppError::NotFound("Processed agent data").into());
+    }
+    Ok(Json(history))
+}
+
+/// Read a list of the trips segmented from the processed agent data, latest first
+#[utoipa::path(
+    path = "/api/trips",
+    params(Pagination),
+    responses(
+        (
+            status = 200,
+            body = Vec<TripWithId>,
+            description = "List of trips",
+            example = json!([{
+                "id": 1,
+                "start_time": "2023-10-01T08:00:00Z",
+                "end_time": "2023-10-01T08:25:00Z",
+                "distance_m": 12400.5,
+                "samples": 1500,
+                "rough_percentage": 12.5,
+                "polyline": [
+                    {"latitude": 50.45, "longitude": 30.52},
+                    {"latitude": 50.47, "longitude": 30.61}
+                ]
+            }])
+        ),
+        (status = 400, description = "Invalid pagination parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The `read` scope is required", body = Problem, content_type = "application/problem+json"),
+        (status = "5XX", description = "Internal server error", body = Problem, content_type = "application/problem+json")
+    ),
+    security(("api_key" = ["read"]), ("bearer" = ["read"]))
+)]
+#[get("/trips", wrap = "RequireScope::read()")]
+#[instrument(skip(repo))]
+pub async fn read_trips(
+    pagination: Query<Pagination>,
+    repo: Data<dyn ProcessedAgentRepository>,
+) -> actix_web::Result<Json<Vec<TripWithId>>> {
+    let result = service::fetch_trips(pagination.page.0, pagination.size.0, &**repo).await?;
+    Ok(Json(result))
+}
+
+/// Read the processed agent data of a trip, oldest first
+///
+/// Data deleted or rolled up by the retention since the trip was segmented is absent
+#[utoipa::path(
+    path = "/api/trips/{id}/points",
+    params(TripId),
+    responses(
+        (
+            status = 200,
+            body = Vec<ProcessedAgentWithId>,
+            description = "Processed agent data of the trip",
+        ),
+        (status = 400, description = "Invalid ID", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The `read` scope is required", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Trip not found", body = Problem, content_type = "application/problem+json"),
+        (status = "5XX", description = "Internal server error", body = Problem, content_type = "application/problem+json")
+    ),
+    security(("api_key" = ["read"]), ("bearer" = ["read"]))
+)]
+#[get("/trips/{id}/points", wrap = "RequireScope::read()")]
+#[instrument(skip(repo))]
+pub async fn read_trip_points(
+    id: Path<TripId>,
+    repo: Data<dyn ProcessedAgentRepository>,
+) -> actix_web::Result<Json<Vec<ProcessedAgentWithId>>> {
+    let points = service::fetch_trip_points(id.into_inner(), &**repo)
+        .await?
+        .ok_or(AppError::NotFound("Trip"))?;
+    Ok(Json(points))
+}
+
+/// Read the road quality of the ways of the road network, the largest share of rough road first
+///
+/// Summarized from the live data matched to the ways. Empty unless map matching is configured
+#[utoipa::path(
+    path = "/api/road-quality",
+    params(Pagination),
+    responses(
+        (
+            status = 200,
+            body = Vec<RoadQuality>,
+            description = "Road quality by way",
+            example = json!([{
+                "way_id": 4321234,
+                "name": "Khreshchatyk Street",
+                "samples": 240,
+                "rough_samples": 60,
+                "rough_percentage": 25.0
+            }])
+        ),
+        (status = 400, description = "Invalid pagination parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The `read` scope is required", body = Problem, content_type = "application/problem+json"),
+        (status = "5XX", description = "Internal server error", body = Problem, content_type = "application/problem+json")
+    ),
+    security(("api_key" = ["read"]), ("bearer" = ["read"]))
+)]
+#[get("/road-quality", wrap = "RequireScope::read()")]
+#[instrument(skip(network, repo))]
+pub async fn read_road_quality(
+    pagination: Query<Pagination>,
+    network: Option<Data<RoadNetwork>>,
+    repo: Data<dyn ProcessedAgentRepository>,
+) -> actix_web::Result<Json<Vec<RoadQuality>>> {
+    let result = service::fetch_road_quality(
+        pagination.page.0,
+        pagination.size.0,
+        network.as_deref().map(|network| &**network),
+        &**repo,
+    )
+    .await?;
+    Ok(Json(result))
+}
+
+/// Get the counters of the retention job
+#[utoipa::path(
+    path = "/api/retention",
+    responses(
+        (
+            status = 200,
+            body = RetentionMetrics,
+            description = "Counters of the retention job since the store started",
+            example = json!({
+                "enabled": true,
+                "dry_run": false,
+                "runs": 24,
+                "failed_runs": 0,
+                "expired_rows": 1200,
+                "aggregates": 40,
+                "dropped_partitions": 1,
+                "last_run": {
+                    "started_at": "2023-10-02T00:00:00Z",
+                    "duration_ms": 35,
+                    "cutoff": "2023-07-04T00:00:00Z",
+                    "applied": true,
+                    "expired_rows": 50,
+                    "aggregates": 2,
+                    "dropped_partitions": [],
+                    "error": null
+                }
+            })
+        ),
+        (status = 401, description = "Missing or invalid credentials", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The `admin` scope is required", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("api_key" = ["admin"]), ("bearer" = ["admin"]))
+)]
+#[get("/retention", wrap = "RequireScope::admin()")]
+#[instrument(skip(status))]
+pub async fn read_retention_metrics(status: Data<RetentionStatus>) -> Json<RetentionMetrics> {
+    Json(status.metrics())
+}
+
+fn etag(version: Version) -> header::ETag {
+    header::ETag(header::EntityTag::new_strong(version.to_string()))
+}
+
+/// Condition of the `If-Match` header
+#[derive(Debug, Default)]
+struct IfMatch {
+    /// Whether the header is present, in which case the data must exist
+    present: bool,
+    /// Versions accepted by the header. `None` if any version is accepted
+    versions: Option<Vec<Version>>,
+}
+
+impl IfMatch {
+    /// Turns the error of the absent data into a failed precondition if the header is present
+    fn absent(&self, err: AppError) -> AppError {
+        match err {
+            AppError::NotFound(_) if self.present => AppError::PreconditionFailed,
+            err => err,
+        }
+    }
+}
+
+/// Parses the `If-Match` header. Weak and foreign entity tags never match
+fn if_match(req: &HttpRequest) -> Result<IfMatch, AppError> {
+    if !req.headers().contains_key(header::IF_MATCH) {
+        return Ok(IfMatch::default());
+    }
+    let if_match = <header::IfMatch as header::Header>::parse(req).map_err(|err| {
+        AppError::Request(
+            format!("Invalid `If-Match`: {err}"),
+            ErrorCode::InvalidParameter,
+        )
+    })?;
+    let versions = match if_match {
+        header::IfMatch::Any => None,
+        header::IfMatch::Items(tags) => Some(
+            tags.iter()
+                .filter(|tag| !tag.weak)
+                .filter_map(|tag| tag.tag().parse::<i64>().ok().map(Version::from))
+                .collect(),
+        ),
+    };
+    Ok(IfMatch {
+        present: true,
+        versions,
+    })
+}
+
+impl Visibility {
+    fn include_deleted(&self, principal: &Principal) -> Result<bool, AuthError> {
+        if self.include_deleted {
+            principal.authorize(Scope::Admin)?;
+        }
+        Ok(self.include_deleted)
+    }
+}
+
 impl Default for PageNumber {
     #[inline(always)]
     fn default() -> Self {
@@ -315,3 +999,37 @@ impl<'de> Deserialize<'de> for PageSize {
         }
     }
 }
+
+impl Default for NearbyLimit {
+    #[inline(always)]
+    fn default() -> Self {
+        NearbyLimit(unsafe { NonZeroU8::new(10).unwrap_unchecked() })
+    }
+}
+
+impl<'de> Deserialize<'de> for NearbyLimit {
+    fn deserialize<D>(deserializer: D) -> Result<NearbyLimit, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = NonZeroU8::deserialize(deserializer)?;
+        match value.get() {
+            ..=100 => Ok(NearbyLimit(value)),
+            _ => Err(serde::de::Error::custom("limit must be between 1 and 100")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Radius {
+    fn deserialize<D>(deserializer: D) -> Result<Radius, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = f64::deserialize(deserializer)?;
+        if value.is_finite() && value > 0.0 {
+            Ok(Radius(value))
+        } else {
+            Err(serde::de::Error::custom("radius must be a positive number"))
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::borrow::Cow;
+
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
+
+use super::{MessageKind, Subscription};
+use crate::data::{ProcessedAgent, ProcessedAgentId};
+
+/// Change of the processed agent data to be broadcast to subscribers.
+/// Serialized as is to be sent over the event bus
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    /// Data was created. `batch` keeps the shape of the original request
+    New {
+        entries: Vec<(ProcessedAgentId, ProcessedAgent)>,
+        batch: bool,
+    },
+    Update {
+        id: ProcessedAgentId,
+        data: ProcessedAgent,
+    },
+    /// Data was soft-deleted
+    Delete { id: ProcessedAgentId },
+    /// Soft-deleted data was restored
+    Restore {
+        id: ProcessedAgentId,
+        data: ProcessedAgent,
+    },
+}
+
+/// Event as sent to the subscribers
+#[derive(Debug, Clone)]
+pub struct Message {
+    /// Position of the message in the broadcast order, `None` for replayed data
+    seq: Option<u64>,
+    event: Event,
+}
+
+impl Event {
+    pub fn created(id: ProcessedAgentId, data: ProcessedAgent) -> Self {
+        Self::New {
+            entries: vec![(id, data)],
+            batch: false,
+        }
+    }
+
+    pub fn created_list(ids: Vec<ProcessedAgentId>, data: Vec<ProcessedAgent>) -> Self {
+        Self::New {
+            entries: ids.into_iter().zip(data).collect(),
+            batch: true,
+        }
+    }
+
+    pub fn kind(&self) -> MessageKind {
+        match self {
+            Self::New { .. } => MessageKind::New,
+            Self::Update { .. } => MessageKind::Update,
+            Self::Delete { .. } => MessageKind::Delete,
+            Self::Restore { .. } => MessageKind::Restore,
+        }
+    }
+}
+
+impl Message {
+    pub fn broadcast(seq: u64, event: Event) -> Self {
+        Self {
+            seq: Some(seq),
+            event,
+        }
+    }
+
+    pub fn replayed(entries: Vec<(ProcessedAgentId, ProcessedAgent)>) -> Self {
+        Self {
+            seq: None,
+            event: Event::New {
+                entries,
+                batch: true,
+            },
+        }
+    }
+
+    pub fn kind(&self) -> MessageKind {
+        self.event.kind()
+    }
+
+    /// IDs of the created data, none for other kinds of messages
+    pub fn created_ids(&self) -> impl Iterator<Item = ProcessedAgentId> + '_ {
+        let entries = match &self.event {
+            Event::New { entries, .. } => entries.as_slice(),
+            _ => &[],
+        };
+        entries.iter().map(|&(id, _)| id)
+    }
+
+    /// Returns the part of the message matching the subscription filter,
+    /// or `None` if nothing matches. Deletions carry no data and always match.
+    /// Does not check [`Subscription::accepts`]
+    pub fn select(&self, subscription: &Subscription) -> Option<Cow<'_, Self>> {
+        match &self.event {
+            Event::Update { data, .. } | Event::Restore { data, .. } => {
+                subscription.matches(data).then_some(Cow::Borrowed(self))
+            }
+            _ => self.retain_new(|_, data| subscription.matches(data)),
+        }
+    }
+
+    /// Keeps only the created entries for which `keep` returns `true`.
+    /// Other kinds of messages are returned as is
+    pub fn retain_new(
+        &self,
+        mut keep: impl FnMut(ProcessedAgentId, &ProcessedAgent) -> bool,
+    ) -> Option<Cow<'_, Self>> {
+        let Event::New { entries, batch } = &self.event else {
+            return Some(Cow::Borrowed(self));
+        };
+        let kept: Vec<_> = entries
+            .iter()
+            .filter(|(id, data)| keep(*id, data))
+            .collect();
+        if kept.is_empty() {
+            None
+        } else if kept.len() == entries.len() {
+            Some(Cow::Borrowed(self))
+        } else {
+            Some(Cow::Owned(Self {
+                seq: self.seq,
+                event: Event::New {
+                    entries: kept.into_iter().cloned().collect(),
+                    batch: *batch,
+                },
+            }))
+        }
+    }
+}
+
+impl Serialize for Message {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        const KIND_FIELD: &str = "kind";
+        const SEQ_FIELD: &str = "seq";
+        const DATA_TYPE_FIELD: &str = "data_type";
+
+        let mut state = serializer.serialize_struct("Message", 4)?;
+        state.serialize_field(KIND_FIELD, self.kind().as_str())?;
+        match self.seq {
+            Some(seq) => state.serialize_field(SEQ_FIELD, &seq)?,
+            None => state.skip_field(SEQ_FIELD)?,
+        }
+        match &self.event {
+            Event::New {
+                entries,
+                batch: false,
+            } if entries.len() == 1 => {
+                let (id, data) = &entries[0];
+                state.serialize_field("id", id)?;
+                state.serialize_field("data", data)?;
+            }
+            Event::New { entries, .. } => {
+                state.serialize_field("id", &Ids(entries))?;
+                state.serialize_field("data", &Data(entries))?;
+            }
+            Event::Update { id, data } | Event::Restore { id, data } => {
+                state.serialize_field("id", id)?;
+                state.serialize_field("data", data)?;
+            }
+            Event::Delete { id } => {
+                state.serialize_field("id", id)?;
+                state.serialize_field(DATA_TYPE_FIELD, std::any::type_name::<ProcessedAgent>())?;
+            }
+        }
+        state.end()
+    }
+}
+
+struct Ids<'a>(&'a [(ProcessedAgentId, ProcessedAgent)]);
+
+struct Data<'a>(&'a [(ProcessedAgentId, ProcessedAgent)]);
+
+impl Serialize for Ids<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(self.0.iter().map(|(id, _)| id))
+    }
+}
+
+impl Serialize for Data<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(self.0.iter().map(|(_, data)| data))
+    }
+}
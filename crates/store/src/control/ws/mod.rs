@@ -0,0 +1,454 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{self, AtomicBool, AtomicU64},
+        Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+};
+
+use actix_web::{
+    get,
+    http::header,
+    web::{self, Bytes},
+    HttpRequest, HttpResponse,
+};
+use actix_ws::{CloseCode, CloseReason};
+use iot_system::reclone;
+pub use message::{Event, Message};
+use replay::Replay;
+use serde::Deserialize;
+pub use subscription::{ControlMessage, MessageKind, Since, Subscription};
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinHandle,
+};
+use tokio_stream::StreamExt;
+use tracing::instrument;
+use writer::Writer;
+
+use crate::{
+    auth::{Credentials, RequireScope},
+    config::{self, SlowSubscriberPolicy},
+    data::repo::ProcessedAgentRepository,
+    error::AppResult,
+};
+
+mod message;
+mod replay;
+mod subscription;
+mod writer;
+
+/// Websocket endpoint for subscribing to processed agent data. Requires the `read` scope.
+///
+/// Clients receive every event until they send a [`ControlMessage`] narrowing the subscription.
+/// If `since` is given, the stored data is replayed before switching to live events.
+/// Live messages a client can't keep up with are dropped and reported with a
+/// `{"kind": "lagged", "missed": N}` notice, or the connection is closed, depending on
+/// the configuration.
+///
+/// Clients unable to set the headers, like browsers, send the credentials as subprotocols,
+/// see [`Credentials::parse_websocket_protocols`].
+#[get("/ws", wrap = "RequireScope::read()")]
+#[instrument(skip_all)]
+pub async fn ws_endpoint(
+    req: HttpRequest,
+    body: web::Payload,
+    query: web::Query<WsQuery>,
+    subscribers: web::Data<Subscribers>,
+    repo: web::Data<dyn ProcessedAgentRepository>,
+) -> actix_web::Result<HttpResponse> {
+    let (mut response, session, msg_stream) = actix_ws::handle(&req, body)?;
+    // The client fails the handshake unless one of the offered subprotocols is accepted
+    let accepted = req
+        .headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|protocols| protocols.to_str().ok())
+        .and_then(Credentials::parse_websocket_protocols)
+        .map(|(accepted, _)| accepted);
+    if let Some(accepted) = accepted {
+        response.headers_mut().insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            header::HeaderValue::from_static(accepted),
+        );
+    }
+
+    actix_web::rt::spawn(ws_handler(
+        session,
+        msg_stream,
+        query.into_inner().since,
+        web::Data::into_inner(subscribers),
+        web::Data::into_inner(repo),
+    ));
+
+    Ok(response)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WsQuery {
+    /// The last seen ID or an RFC 3339 timestamp to replay the stored data from
+    since: Option<Since>,
+}
+
+#[instrument(skip_all)]
+async fn ws_handler(
+    session: actix_ws::Session,
+    mut msg_stream: actix_ws::MessageStream,
+    since: Option<Since>,
+    subscribers: Arc<Subscribers>,
+    repo: Arc<dyn ProcessedAgentRepository>,
+) {
+    let mut id = subscribers.add(session.clone(), repo);
+    if let Some(since) = since {
+        id.catch_up(since);
+    }
+    while let Some(msg) = msg_stream.next().await {
+        reclone!(mut session);
+        match msg {
+            Ok(actix_ws::Message::Ping(bytes)) => {
+                if session.pong(&bytes).await.is_err() {
+                    return; // session closed
+                }
+            }
+            Ok(actix_ws::Message::Text(text)) => {
+                match serde_json::from_str::<ControlMessage>(&text) {
+                    Ok(msg) => {
+                        tracing::debug!("Subscriber {} sent {msg:?}", id.value);
+                        let since = id.state.subscription().apply(msg);
+                        if let Some(since) = since {
+                            id.catch_up(since);
+                        }
+                    }
+                    Err(err) => {
+                        if send_error(&mut session, err.to_string()).await.is_err() {
+                            return; // session closed
+                        }
+                    }
+                }
+            }
+            Ok(_) => {}
+            // <editor-fold desc="Error handling" defaultstate="collapsed">
+            Err(err) => match err {
+                actix_ws::ProtocolError::UnmaskedFrame => {
+                    let reason = Some(CloseReason {
+                        code: CloseCode::Protocol,
+                        description: Some("Received unmasked frame".into()),
+                    });
+                    if session.close(reason).await.is_err() {
+                        return; // session closed
+                    }
+                }
+                actix_ws::ProtocolError::MaskedFrame => {
+                    let reason = Some(CloseReason {
+                        code: CloseCode::Protocol,
+                        description: Some("Received masked frame".into()),
+                    });
+                    if session.close(reason).await.is_err() {
+                        return; // session closed
+                    }
+                }
+                actix_ws::ProtocolError::InvalidOpcode(opcode) => {
+                    let reason = Some(CloseReason {
+                        code: CloseCode::Protocol,
+                        description: Some(format!("Received invalid opcode: {}", opcode)),
+                    });
+                    if session.close(reason).await.is_err() {
+                        return; // session closed
+                    }
+                }
+                actix_ws::ProtocolError::InvalidLength(len) => {
+                    let reason = Some(CloseReason {
+                        code: CloseCode::Protocol,
+                        description: Some(format!("Received invalid length: {}", len)),
+                    });
+                    if session.close(reason).await.is_err() {
+                        return; // session closed
+                    }
+                }
+                actix_ws::ProtocolError::BadOpCode => {
+                    let reason = Some(CloseReason {
+                        code: CloseCode::Protocol,
+                        description: Some("Received bad opcode".into()),
+                    });
+                    if session.close(reason).await.is_err() {
+                        return; // session closed
+                    }
+                }
+                actix_ws::ProtocolError::Overflow => {
+                    let reason = Some(CloseReason {
+                        code: CloseCode::Size,
+                        description: Some("Received message too big".into()),
+                    });
+                    if session.close(reason).await.is_err() {
+                        return; // session closed
+                    }
+                }
+                actix_ws::ProtocolError::ContinuationNotStarted => {
+                    let reason = Some(CloseReason {
+                        code: CloseCode::Protocol,
+                        description: Some("Received continuation frame before start".into()),
+                    });
+                    if session.close(reason).await.is_err() {
+                        return; // session closed
+                    }
+                }
+                actix_ws::ProtocolError::ContinuationStarted => {
+                    let reason = Some(CloseReason {
+                        code: CloseCode::Protocol,
+                        description: Some("Received start frame during continuation".into()),
+                    });
+                    if session.close(reason).await.is_err() {
+                        return; // session closed
+                    }
+                }
+                actix_ws::ProtocolError::ContinuationFragment(opcode) => {
+                    let description =
+                        format!("Received continuation fragment with opcode: {}", opcode);
+                    tracing::error!("{}", description);
+                    let reason = Some(CloseReason {
+                        code: CloseCode::Protocol,
+                        description: Some(description),
+                    });
+                    if session.close(reason).await.is_err() {
+                        return; // session closed
+                    }
+                }
+                actix_ws::ProtocolError::Io(err) => {
+                    tracing::error!("I/O error: {}", err);
+                    let reason = Some(CloseReason {
+                        code: CloseCode::Error,
+                        description: Some("I/O error".into()),
+                    });
+                    if session.close(reason).await.is_err() {
+                        return; // session closed
+                    }
+                }
+            },
+            // </editor-fold>
+        }
+    }
+
+    _ = session.close(None).await
+}
+
+async fn send_error(
+    session: &mut actix_ws::Session,
+    message: String,
+) -> Result<(), actix_ws::Closed> {
+    let reply = serde_json::json!({ "kind": "error", "message": message });
+    session.text(reply.to_string()).await
+}
+
+/// Registry of the websocket subscribers.
+///
+/// Broadcast events are queued and fanned out by [`Self::dispatch`], so that publishing never
+/// waits for the clients. Every subscriber has a bounded queue drained by its own [`Writer`] task;
+/// when the queue is full, the subscriber is handled according to the [`SlowSubscriberPolicy`].
+pub struct Subscribers {
+    subscribers: RwLock<HashMap<u64, Subscriber>>,
+    events: mpsc::UnboundedSender<Event>,
+    next_seq: AtomicU64,
+    queue_capacity: usize,
+    slow_subscriber: SlowSubscriberPolicy,
+}
+
+/// Item of the queue of a subscriber
+enum Queued {
+    /// Broadcast message along with its serialized form
+    Live(Arc<Message>, Bytes),
+    /// Page of the stored data sent by the replay with the given number
+    Replayed(u64, Message),
+    /// End of the replay with the given number
+    ReplayEnd(u64, AppResult<()>),
+}
+
+struct Subscriber {
+    queue: mpsc::Sender<Queued>,
+    state: Arc<SubscriberState>,
+}
+
+/// State shared by the reader and the writer of a subscriber, and the dispatcher
+#[derive(Default)]
+struct SubscriberState {
+    subscription: Mutex<Subscription>,
+    /// Number of messages dropped since the last lag notice
+    missed: AtomicU64,
+    /// Set when the subscriber is disconnected for not keeping up
+    overflowed: AtomicBool,
+    /// Number of the latest replay requested, the pages of the earlier ones are dropped
+    replay: AtomicU64,
+}
+
+struct SubscriberId {
+    value: u64,
+    state: Arc<SubscriberState>,
+    repo: Arc<dyn ProcessedAgentRepository>,
+    replay: Option<JoinHandle<()>>,
+    subscribers: Arc<Subscribers>,
+}
+
+impl Subscribers {
+    /// Returns the registry along with the receiver of the broadcast events,
+    /// which must be passed to [`Self::dispatch`]
+    pub fn new(config: &config::Websocket) -> (Self, mpsc::UnboundedReceiver<Event>) {
+        let (events, receiver) = mpsc::unbounded_channel();
+        let subscribers = Subscribers {
+            subscribers: RwLock::new(HashMap::new()),
+            events,
+            next_seq: AtomicU64::new(1),
+            queue_capacity: config.queue_capacity().get(),
+            slow_subscriber: config.slow_subscriber(),
+        };
+        (subscribers, receiver)
+    }
+
+    fn add(
+        self: &Arc<Self>,
+        session: actix_ws::Session,
+        repo: Arc<dyn ProcessedAgentRepository>,
+    ) -> SubscriberId {
+        let id = self.next_id();
+        let state = Arc::new(SubscriberState::default());
+        let (queue, queue_receiver) = mpsc::channel(self.queue_capacity);
+
+        self.write().insert(
+            id,
+            Subscriber {
+                queue,
+                state: Arc::clone(&state),
+            },
+        );
+        actix_web::rt::spawn(Writer::new(id, session, queue_receiver, Arc::clone(&state)).run());
+
+        SubscriberId {
+            value: id,
+            state,
+            repo,
+            replay: None,
+            subscribers: Arc::clone(self),
+        }
+    }
+
+    fn next_id(&self) -> u64 {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        NEXT_ID.fetch_add(1, atomic::Ordering::Relaxed)
+    }
+
+    /// Queues the event to be sent to every subscriber whose subscription matches it.
+    /// Does not wait for the delivery
+    pub fn broadcast(&self, event: Event) {
+        if self.events.send(event).is_err() {
+            tracing::error!("The event dispatcher has stopped, the event is lost");
+        }
+    }
+
+    /// Puts the broadcast events into the subscribers' queues, assigning each the next sequence
+    /// number. Runs for as long as the registry exists
+    pub async fn dispatch(self: Arc<Self>, mut events: mpsc::UnboundedReceiver<Event>) {
+        while let Some(event) = events.recv().await {
+            let seq = self.next_seq.fetch_add(1, atomic::Ordering::Relaxed);
+            if let Err(err) = self.fan_out(Message::broadcast(seq, event)) {
+                tracing::error!("Failed to broadcast message {seq}: {err}");
+            }
+        }
+    }
+
+    fn fan_out(&self, msg: Message) -> AppResult<()> {
+        let data: Bytes = serde_json::to_vec(&msg)?.into();
+        let msg = Arc::new(msg);
+
+        let mut to_remove = Vec::new();
+        for (&id, subscriber) in self.read().iter() {
+            if !subscriber.state.subscription().accepts(msg.kind()) {
+                continue;
+            }
+            match subscriber
+                .queue
+                .try_send(Queued::Live(Arc::clone(&msg), Bytes::clone(&data)))
+            {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => match self.slow_subscriber {
+                    SlowSubscriberPolicy::Lag => {
+                        subscriber
+                            .state
+                            .missed
+                            .fetch_add(1, atomic::Ordering::Relaxed);
+                    }
+                    SlowSubscriberPolicy::Disconnect => {
+                        tracing::warn!("Subscriber {id} can't keep up, disconnecting");
+                        subscriber
+                            .state
+                            .overflowed
+                            .store(true, atomic::Ordering::Relaxed);
+                        to_remove.push(id);
+                    }
+                },
+                Err(TrySendError::Closed(_)) => to_remove.push(id),
+            }
+        }
+
+        if !to_remove.is_empty() {
+            let mut subscribers = self.write();
+            for id in to_remove {
+                subscribers.remove(&id);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<u64, Subscriber>> {
+        self.subscribers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<u64, Subscriber>> {
+        self.subscribers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl SubscriberState {
+    fn subscription(&self) -> MutexGuard<'_, Subscription> {
+        self.subscription
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl SubscriberId {
+    /// Starts replaying the stored data along with the live messages, instead of the previous
+    /// replay if any
+    fn catch_up(&mut self, since: Since) {
+        if let Some(task) = self.replay.take() {
+            task.abort();
+        }
+        // Only the registry keeps the queue open for long, so that removing the subscriber
+        // stops the writer
+        let Some(queue) = self
+            .subscribers
+            .read()
+            .get(&self.value)
+            .map(|subscriber| subscriber.queue.clone())
+        else {
+            return; // disconnected
+        };
+        // Set before the replay starts, so that the writer remembers the live data sent
+        // in the meantime
+        let number = self.state.replay.fetch_add(1, atomic::Ordering::Relaxed) + 1;
+        self.replay = Some(actix_web::rt::spawn(
+            Replay::new(number, since, queue, Arc::clone(&self.repo)).run(),
+        ));
+    }
+}
+
+impl Drop for SubscriberId {
+    fn drop(&mut self) {
+        // Closes the queue, which stops the writer
+        if let Some(task) = self.replay.take() {
+            task.abort();
+        }
+        self.subscribers.write().remove(&self.value);
+    }
+}
@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use derive_more::Constructor;
+use tokio::sync::mpsc;
+
+use super::{Message, Queued, Since};
+use crate::{
+    data::{repo::ProcessedAgentRepository, ProcessedAgentId},
+    error::AppResult,
+    service,
+};
+
+/// Maximum number of stored rows sent in a single replayed message
+const REPLAY_PAGE_SIZE: i64 = 500;
+
+/// Task replaying the stored data to a single subscriber through its queue,
+/// so that the replay goes no faster than the writer sends the messages
+#[derive(Constructor)]
+pub(super) struct Replay {
+    number: u64,
+    since: Since,
+    queue: mpsc::Sender<Queued>,
+    repo: Arc<dyn ProcessedAgentRepository>,
+}
+
+impl Replay {
+    /// Queues the pages of the stored data, then the end of the replay
+    pub async fn run(self) {
+        let result = self.queue_pages().await;
+        // The queue is only closed once the writer has stopped
+        _ = self
+            .queue
+            .send(Queued::ReplayEnd(self.number, result))
+            .await;
+    }
+
+    async fn queue_pages(&self) -> AppResult<()> {
+        let (mut after, since) = match self.since {
+            Since::Id(id) => (id, None),
+            Since::Timestamp(timestamp) => (ProcessedAgentId::from(0), Some(timestamp)),
+        };
+        loop {
+            let page = service::fetch_processed_agent_data_after(
+                after,
+                since,
+                REPLAY_PAGE_SIZE,
+                &*self.repo,
+            )
+            .await?;
+            let Some(&(last, _)) = page.last() else {
+                return Ok(());
+            };
+            let is_last_page = (page.len() as i64) < REPLAY_PAGE_SIZE;
+            after = last;
+
+            let page = Queued::Replayed(self.number, Message::replayed(page));
+            if self.queue.send(page).await.is_err() || is_last_page {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use iot_system::domain::{AgentId, Latitude, Longitude, RoadState};
+use serde::{de, Deserialize, Deserializer};
+
+use crate::data::{ProcessedAgent, ProcessedAgentId};
+
+/// Control message sent by a client as a JSON text frame.
+///
+/// ```json
+/// {"type": "subscribe", "filter": {"road_state": ["ROUGH"], "kinds": ["new"]}, "since": 42}
+/// {"type": "subscribe", "filter": {"agent_id": ["AA1234BB"]}}
+/// {"type": "unsubscribe"}
+/// {"type": "pause"}
+/// {"type": "resume"}
+/// ```
+///
+/// Deletion events carry only the ID of the deleted data, so the data criteria
+/// of the filter don't apply to them: a subscriber accepting the `delete` kind
+/// receives every deletion.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlMessage {
+    /// Replace the current filter. An empty filter matches every event.
+    /// If `since` is given, the matching stored data is replayed first
+    Subscribe {
+        #[serde(default)]
+        filter: Filter,
+        since: Option<Since>,
+    },
+    /// Stop receiving events until the next `subscribe`
+    Unsubscribe,
+    /// Temporarily stop receiving events, keeping the filter
+    Pause,
+    /// Continue receiving events after `pause`
+    Resume,
+}
+
+/// Criteria an event must meet to be sent to a subscriber.
+/// Omitted criteria match everything. Deletions are matched by `kinds` only.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Filter {
+    /// The data of the agents not telling their ID never matches
+    agent_id: Option<Vec<AgentId>>,
+    road_state: Option<Vec<RoadState>>,
+    bbox: Option<BoundingBox>,
+    kinds: Option<Vec<MessageKind>>,
+}
+
+/// Geographic rectangle. If `min_longitude` is greater than `max_longitude`,
+/// the box is considered to cross the antimeridian.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoundingBox {
+    min_latitude: Latitude,
+    min_longitude: Longitude,
+    max_latitude: Latitude,
+    max_longitude: Longitude,
+}
+
+/// Point to replay the stored data from: either the last seen ID,
+/// or a timestamp of the data, formatted as RFC 3339
+#[derive(Debug, Clone, Copy)]
+pub enum Since {
+    Id(ProcessedAgentId),
+    Timestamp(DateTime<Utc>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageKind {
+    New,
+    Update,
+    Delete,
+    Restore,
+}
+
+/// Per-subscriber delivery state
+#[derive(Debug)]
+pub struct Subscription {
+    /// `None` if the client has unsubscribed
+    filter: Option<Filter>,
+    paused: bool,
+}
+
+impl Subscription {
+    /// Applies the control message, returning the point to replay the data from, if requested
+    pub fn apply(&mut self, msg: ControlMessage) -> Option<Since> {
+        match msg {
+            ControlMessage::Subscribe { filter, since } => {
+                self.filter = Some(filter);
+                return since;
+            }
+            ControlMessage::Unsubscribe => self.filter = None,
+            ControlMessage::Pause => self.paused = true,
+            ControlMessage::Resume => self.paused = false,
+        }
+        None
+    }
+
+    /// Whether events of the given kind should be delivered at all
+    pub fn accepts(&self, kind: MessageKind) -> bool {
+        match &self.filter {
+            Some(filter) if !self.paused => contains_or_any(&filter.kinds, &kind),
+            _ => false,
+        }
+    }
+
+    /// Whether the given data matches the filter. Does not check [`Self::accepts`]
+    pub fn matches(&self, data: &ProcessedAgent) -> bool {
+        let Some(filter) = &self.filter else {
+            return false;
+        };
+        let bbox_matches = match filter.bbox {
+            Some(bbox) => bbox.contains(data),
+            None => true,
+        };
+        let agent_matches = match &filter.agent_id {
+            Some(agent_ids) => data
+                .agent_data()
+                .agent_id()
+                .is_some_and(|agent_id| agent_ids.contains(agent_id)),
+            None => true,
+        };
+        agent_matches && contains_or_any(&filter.road_state, &data.road_state()) && bbox_matches
+    }
+}
+
+/// Whether `value` is in `allowed`, treating a missing list as allowing anything
+fn contains_or_any<T: PartialEq>(allowed: &Option<Vec<T>>, value: &T) -> bool {
+    match allowed {
+        Some(allowed) => allowed.contains(value),
+        None => true,
+    }
+}
+
+impl Default for Subscription {
+    /// New subscribers receive every event, so that clients without control messages keep working
+    fn default() -> Self {
+        Self {
+            filter: Some(Filter::default()),
+            paused: false,
+        }
+    }
+}
+
+impl BoundingBox {
+    fn contains(&self, data: &ProcessedAgent) -> bool {
+        let gps = data.agent_data().gps();
+        let latitude: f64 = gps.latitude().into();
+        let longitude: f64 = gps.longitude().into();
+        let (min_lon, max_lon): (f64, f64) = (self.min_longitude.into(), self.max_longitude.into());
+
+        let latitude_matches =
+            (f64::from(self.min_latitude)..=f64::from(self.max_latitude)).contains(&latitude);
+        let longitude_matches = if min_lon <= max_lon {
+            (min_lon..=max_lon).contains(&longitude)
+        } else {
+            longitude >= min_lon || longitude <= max_lon
+        };
+        latitude_matches && longitude_matches
+    }
+}
+
+impl MessageKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::New => "new",
+            Self::Update => "update",
+            Self::Delete => "delete",
+            Self::Restore => "restore",
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Since {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct SinceVisitor;
+
+        impl de::Visitor<'_> for SinceVisitor {
+            type Value = Since;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an ID or an RFC 3339 timestamp")
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                Ok(Since::Id(v.into()))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                i64::try_from(v)
+                    .map(|v| Since::Id(v.into()))
+                    .map_err(E::custom)
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                if let Ok(id) = v.parse::<i64>() {
+                    return Ok(Since::Id(id.into()));
+                }
+                DateTime::parse_from_rfc3339(v)
+                    .map(|timestamp| Since::Timestamp(timestamp.to_utc()))
+                    .map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(SinceVisitor)
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    sync::{atomic, Arc},
+};
+
+use actix_web::web::Bytes;
+use actix_ws::{CloseCode, CloseReason};
+use derive_more::Constructor;
+use tokio::sync::mpsc;
+
+use super::{send_error, Message, Queued, SubscriberState};
+use crate::{data::ProcessedAgentId, error::AppResult};
+
+/// Task sending the queued messages to a single subscriber,
+/// so that a slow client only delays its own messages
+#[derive(Constructor)]
+pub(super) struct Writer {
+    id: u64,
+    session: actix_ws::Session,
+    queue: mpsc::Receiver<Queued>,
+    state: Arc<SubscriberState>,
+}
+
+/// Created data sent since the start of a replay, so that the data both replayed and received
+/// live is sent once
+#[derive(Default)]
+struct Sent {
+    ids: HashSet<ProcessedAgentId>,
+    /// The last replayed ID
+    high_water: Option<ProcessedAgentId>,
+    /// Set once the whole stored data is replayed
+    ended: bool,
+}
+
+impl Writer {
+    /// Sends the messages until the queue is closed, the session is closed by the client,
+    /// or the subscriber is disconnected for not keeping up
+    pub async fn run(mut self) {
+        // Number of the latest replay, and the data sent since it started
+        let mut replay: (u64, Option<Sent>) = (0, None);
+        while let Some(queued) = self.queue.recv().await {
+            if self.state.overflowed.load(atomic::Ordering::Relaxed) {
+                break;
+            }
+            let (latest, sent) = &mut replay;
+            let requested = self.state.replay.load(atomic::Ordering::Relaxed);
+            if requested > *latest {
+                *latest = requested;
+                *sent = Some(Sent::default());
+            }
+            let open = match queued {
+                Queued::Live(msg, data) => {
+                    if sent.as_ref().is_some_and(|sent| sent.is_passed_by(&msg)) {
+                        *sent = None;
+                    }
+                    let kept = match sent {
+                        Some(sent) => sent.retain_new(&msg, false),
+                        None => Some(Cow::Borrowed(&*msg)),
+                    };
+                    match kept {
+                        Some(Cow::Borrowed(msg)) => self.send(msg, Some(data)).await,
+                        Some(Cow::Owned(msg)) => self.send(&msg, None).await,
+                        None => Ok(true),
+                    }
+                    .map_err(|err| (err, "Failed to send the data"))
+                }
+                // A page of a replay replaced by a later one
+                Queued::Replayed(number, _) if number < *latest => Ok(true),
+                Queued::Replayed(_, msg) => match sent
+                    .get_or_insert_with(Sent::default)
+                    .retain_new(&msg, true)
+                {
+                    Some(msg) => self.send(&msg, None).await,
+                    None => Ok(true),
+                }
+                .map_err(|err| (err, "Failed to send the data")),
+                Queued::ReplayEnd(number, _) if number < *latest => Ok(true),
+                Queued::ReplayEnd(_, result) => {
+                    if let Some(replay) = sent {
+                        replay.ended = true;
+                        if replay.high_water.is_none() {
+                            *sent = None;
+                        }
+                    }
+                    result
+                        .map(|()| true)
+                        .map_err(|err| (err, "Failed to replay the data"))
+                }
+            };
+            match open {
+                Ok(true) => {}
+                Ok(false) => return, // session closed
+                Err((err, message)) => {
+                    tracing::error!("{message} to subscriber {}: {err}", self.id);
+                    if send_error(&mut self.session, message.to_owned())
+                        .await
+                        .is_err()
+                    {
+                        return; // session closed
+                    }
+                }
+            }
+        }
+
+        if self.state.overflowed.load(atomic::Ordering::Relaxed) {
+            let reason = Some(CloseReason {
+                code: CloseCode::Policy,
+                description: Some("Subscriber can't keep up with the messages".into()),
+            });
+            _ = self.session.close(reason).await;
+        }
+    }
+
+    /// Sends the part of the message matching the subscription, preceded by a lag notice
+    /// if any messages were dropped. `encoded` is the whole serialized message, if available.
+    /// Returns `false` if the session was closed
+    async fn send(&mut self, msg: &Message, encoded: Option<Bytes>) -> AppResult<bool> {
+        let selected = {
+            let subscription = self.state.subscription();
+            if !subscription.accepts(msg.kind()) {
+                return Ok(true);
+            }
+            msg.select(&subscription)
+        };
+        let Some(selected) = selected else {
+            return Ok(true);
+        };
+
+        let missed = self.state.missed.swap(0, atomic::Ordering::Relaxed);
+        if missed > 0 {
+            let notice = serde_json::json!({ "kind": "lagged", "missed": missed });
+            if self.session.text(notice.to_string()).await.is_err() {
+                return Ok(false);
+            }
+        }
+
+        let data = match (selected, encoded) {
+            (Cow::Borrowed(_), Some(encoded)) => encoded,
+            (selected, _) => serde_json::to_vec(&*selected)?.into(),
+        };
+        Ok(self.session.binary(data).await.is_ok())
+    }
+}
+
+impl Sent {
+    /// Keeps the created data not sent yet, remembering it as sent
+    /// while live data may still be replayed
+    fn retain_new<'m>(&mut self, msg: &'m Message, replayed: bool) -> Option<Cow<'m, Message>> {
+        let Self {
+            ids,
+            high_water,
+            ended,
+        } = self;
+        msg.retain_new(|id, _| {
+            if replayed {
+                *high_water = (*high_water).max(Some(id));
+            }
+            if *ended {
+                !ids.contains(&id)
+            } else {
+                ids.insert(id)
+            }
+        })
+    }
+
+    /// Whether the live message only has data created after the replayed one,
+    /// so no more data can be sent twice
+    fn is_passed_by(&self, msg: &Message) -> bool {
+        let mut ids = msg.created_ids().peekable();
+        self.ended && ids.peek().is_some() && ids.all(|id| Some(id) > self.high_water)
+    }
+}
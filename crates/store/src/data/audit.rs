@@ -0,0 +1,29 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{ToResponse, ToSchema};
+
+/// Manual change of a processed agent data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "AUDIT_ACTION")]
+pub enum AuditAction {
+    Update,
+    Delete,
+    Restore,
+}
+
+/// Entry of the audit trail of a processed agent data
+#[derive(Debug, Clone, Serialize, ToResponse, ToSchema)]
+pub struct AuditEntry {
+    pub(super) id: i64,
+    /// Name of the API key or the JWT subject that made the change
+    pub(super) actor: String,
+    pub(super) action: AuditAction,
+    /// The data before the change. Absent for restorations
+    #[schema(value_type = Option<Object>)]
+    pub(super) before: Option<serde_json::Value>,
+    /// The data after the change. Absent for deletions
+    #[schema(value_type = Option<Object>)]
+    pub(super) after: Option<serde_json::Value>,
+    pub(super) timestamp: DateTime<Utc>,
+}
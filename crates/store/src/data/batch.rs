@@ -0,0 +1,18 @@
+use serde::Serialize;
+use utoipa::{ToResponse, ToSchema};
+
+use super::ProcessedAgentId;
+use crate::error::Violation;
+
+/// Outcome of storing a single item of a best-effort batch
+#[derive(Debug, Serialize, ToResponse, ToSchema)]
+#[serde(untagged)]
+pub enum ItemResult {
+    Created {
+        #[schema(value_type = i64)]
+        id: ProcessedAgentId,
+    },
+    Failed {
+        error: Violation,
+    },
+}
@@ -0,0 +1,80 @@
+pub use iot_system::domain::MEAN_EARTH_RADIUS_M;
+
+use super::{Gps, Latitude, Longitude};
+
+/// Great-circle distance between the points on a sphere, in meters
+pub fn haversine_m(from: (Latitude, Longitude), to: (Latitude, Longitude)) -> f64 {
+    distance_m(Gps::new(from.0, from.1), Gps::new(to.0, to.1))
+}
+
+/// Great-circle distance between the GPS positions, in meters
+pub fn distance_m(from: Gps, to: Gps) -> f64 {
+    from.distance_m(to)
+}
+
+/// Simplifies the line with the Ramer-Douglas-Peucker algorithm, dropping the points
+/// that deviate from the simplified line by no more than `tolerance_m` meters.
+/// The first and the last points are always kept
+pub fn simplify(line: &[Gps], tolerance_m: f64) -> Vec<Gps> {
+    let Some(last) = line.len().checked_sub(1) else {
+        return Vec::new();
+    };
+    let mut keep = vec![false; line.len()];
+    keep[0] = true;
+    keep[last] = true;
+
+    let mut ranges = vec![(0, last)];
+    while let Some((first, last)) = ranges.pop() {
+        let farthest = (first + 1..last)
+            .map(|index| {
+                let deviation = distance_to_segment_m(line[index], line[first], line[last]);
+                (index, deviation)
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+        if let Some((index, deviation)) = farthest {
+            if deviation > tolerance_m {
+                keep[index] = true;
+                ranges.push((first, index));
+                ranges.push((index, last));
+            }
+        }
+    }
+
+    line.iter()
+        .zip(keep)
+        .filter_map(|(&point, keep)| keep.then_some(point))
+        .collect()
+}
+
+/// Distance from the point to the segment, in meters. See [`project_onto_segment`]
+fn distance_to_segment_m(point: Gps, start: Gps, end: Gps) -> f64 {
+    project_onto_segment(point, start, end).0
+}
+
+/// Distance from the point to the nearest point of the segment, in meters, and the position
+/// of the nearest point along the segment, from 0 at the start to 1 at the end.
+///
+/// The positions are projected onto the plane tangent at the start of the segment, which is
+/// precise enough for the segments of a route or a road, but not for the ones spanning
+/// hundreds of kilometers
+pub fn project_onto_segment(point: Gps, start: Gps, end: Gps) -> (f64, f64) {
+    let origin_latitude = f64::from(start.latitude()).to_radians();
+    let project = |gps: Gps| {
+        let latitude = f64::from(gps.latitude()) - f64::from(start.latitude());
+        let longitude = f64::from(gps.longitude()) - f64::from(start.longitude());
+        (
+            longitude.to_radians() * origin_latitude.cos() * MEAN_EARTH_RADIUS_M,
+            latitude.to_radians() * MEAN_EARTH_RADIUS_M,
+        )
+    };
+    let (px, py) = project(point);
+    let (ex, ey) = project(end);
+
+    let length_squared = ex * ex + ey * ey;
+    let t = if length_squared == 0.0 {
+        0.0
+    } else {
+        ((px * ex + py * ey) / length_squared).clamp(0.0, 1.0)
+    };
+    ((px - t * ex).hypot(py - t * ey), t)
+}
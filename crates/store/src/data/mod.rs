@@ -1,4 +1,16 @@
+mod audit;
+mod batch;
+pub mod geo;
 mod model;
+mod nearby;
+pub mod partition;
 pub mod repo;
+mod road;
+mod trip;
 
+pub use audit::*;
+pub use batch::*;
 pub use model::*;
+pub use nearby::*;
+pub use road::*;
+pub use trip::*;
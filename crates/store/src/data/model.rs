@@ -1,9 +1,11 @@
 use std::fmt::{Debug, Display, Formatter};
 
 use chrono::{DateTime, Utc};
-use derive_more::Into;
+use derive_more::{Constructor, Into};
 use iot_system::domain::RoadState;
-pub use iot_system::domain::{Accelerometer, Agent, Gps, Latitude, Longitude, ProcessedAgent};
+pub use iot_system::domain::{
+    Accelerometer, Agent, AgentId, Gps, IdempotencyKey, Latitude, Longitude, Motion, ProcessedAgent,
+};
 use serde::{Deserialize, Serialize};
 use utoipa::{IntoParams, ToResponse, ToSchema};
 
@@ -29,7 +31,13 @@ use utoipa::{IntoParams, ToResponse, ToSchema};
 /// ID of the processed agent to read, update, or delete.
 pub struct ProcessedAgentId(i64);
 
-#[derive(Debug, Serialize, ToResponse, ToSchema)]
+/// Version of a processed agent data row, incremented on every update. Exposed as the `ETag`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, sqlx::Type, Into)]
+#[repr(transparent)]
+#[sqlx(transparent)]
+pub struct Version(i64);
+
+#[derive(Debug, Serialize, ToResponse, ToSchema, Constructor)]
 pub struct ProcessedAgentWithId {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[schema(minimum = 1, value_type = i32, nullable = false)]
@@ -37,18 +45,9 @@ pub struct ProcessedAgentWithId {
     #[serde(flatten)]
     #[schema(inline)]
     data: ProcessedAgent,
-}
-
-pub trait Dto {
-    type Id<'a>;
-}
-
-impl Dto for ProcessedAgent {
-    type Id<'a> = ProcessedAgentId;
-}
-
-impl Dto for [ProcessedAgent] {
-    type Id<'a> = &'a [ProcessedAgentId];
+    /// When the data was deleted. Only present for the deleted data, listed with `include_deleted`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -62,6 +61,19 @@ pub struct ProcessedAgentDao {
     pub(super) latitude: Latitude,
     pub(super) longitude: Longitude,
     pub(super) timestamp: DateTime<Utc>,
+    pub(super) idempotency_key: Option<IdempotencyKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) agent_id: Option<AgentId>,
+    /// Either all of the motion columns are set, or none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) motion_speed_mps: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) motion_heading_deg: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) motion_distance_m: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) confidence: Option<f64>,
+    pub(super) deleted_at: Option<DateTime<Utc>>,
 }
 
 impl Display for ProcessedAgentId {
@@ -71,6 +83,27 @@ impl Display for ProcessedAgentId {
     }
 }
 
+impl From<i64> for ProcessedAgentId {
+    #[inline(always)]
+    fn from(value: i64) -> Self {
+        Self(value)
+    }
+}
+
+impl Display for Version {
+    #[inline(always)]
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl From<i64> for Version {
+    #[inline(always)]
+    fn from(value: i64) -> Self {
+        Self(value)
+    }
+}
+
 impl From<ProcessedAgentWithId> for ProcessedAgentDao {
     fn from(agent: ProcessedAgentWithId) -> Self {
         Self {
@@ -82,6 +115,13 @@ impl From<ProcessedAgentWithId> for ProcessedAgentDao {
             latitude: agent.data.agent_data().gps().latitude(),
             longitude: agent.data.agent_data().gps().longitude(),
             timestamp: agent.data.agent_data().timestamp(),
+            idempotency_key: agent.data.agent_data().idempotency_key().cloned(),
+            agent_id: agent.data.agent_data().agent_id().cloned(),
+            motion_speed_mps: agent.data.motion().map(|motion| motion.speed_mps()),
+            motion_heading_deg: agent.data.motion().map(|motion| motion.heading_deg()),
+            motion_distance_m: agent.data.motion().map(|motion| motion.distance_m()),
+            confidence: agent.data.confidence(),
+            deleted_at: agent.deleted_at,
         }
     }
 }
@@ -93,9 +133,17 @@ impl From<ProcessedAgentDao> for ProcessedAgent {
                 Accelerometer::new(dao.x, dao.y, dao.z),
                 Gps::new(dao.latitude, dao.longitude),
                 dao.timestamp,
-            ),
+            )
+            .with_idempotency_key(dao.idempotency_key)
+            .with_agent_id(dao.agent_id),
             dao.road_state,
         )
+        .with_motion(motion(
+            dao.motion_speed_mps,
+            dao.motion_heading_deg,
+            dao.motion_distance_m,
+        ))
+        .with_confidence(dao.confidence)
     }
 }
 
@@ -103,7 +151,17 @@ impl From<ProcessedAgentDao> for ProcessedAgentWithId {
     fn from(dao: ProcessedAgentDao) -> Self {
         Self {
             id: dao.id,
+            deleted_at: dao.deleted_at,
             data: dao.into(),
         }
     }
 }
+
+/// Motion stored in the columns, if all of them are set
+pub(super) fn motion(
+    speed_mps: Option<f64>,
+    heading_deg: Option<f64>,
+    distance_m: Option<f64>,
+) -> Option<Motion> {
+    Some(Motion::new(speed_mps?, heading_deg?, distance_m?))
+}
@@ -0,0 +1,85 @@
+use chrono::{DateTime, Utc};
+use iot_system::domain::{AgentId, IdempotencyKey, Latitude, Longitude, RoadState};
+use serde::Serialize;
+use utoipa::{ToResponse, ToSchema};
+
+use super::{ProcessedAgent, ProcessedAgentDao, ProcessedAgentId, ProcessedAgentWithId};
+
+/// Processed agent data found near a point
+#[derive(Debug, Serialize, ToResponse, ToSchema)]
+pub struct NearbyProcessedAgent {
+    #[serde(flatten)]
+    #[schema(inline)]
+    data: ProcessedAgentWithId,
+    /// Distance from the point, in meters
+    distance_m: f64,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct NearbyDao {
+    pub(super) id: ProcessedAgentId,
+    pub(super) road_state: RoadState,
+    pub(super) x: f64,
+    pub(super) y: f64,
+    pub(super) z: f64,
+    pub(super) latitude: Latitude,
+    pub(super) longitude: Longitude,
+    pub(super) timestamp: DateTime<Utc>,
+    pub(super) idempotency_key: Option<IdempotencyKey>,
+    pub(super) agent_id: Option<AgentId>,
+    pub(super) motion_speed_mps: Option<f64>,
+    pub(super) motion_heading_deg: Option<f64>,
+    pub(super) motion_distance_m: Option<f64>,
+    pub(super) confidence: Option<f64>,
+    /// Distance from the point, in meters
+    pub(super) distance_m: f64,
+}
+
+impl NearbyDao {
+    pub(super) fn new(id: ProcessedAgentId, data: &ProcessedAgent, distance_m: f64) -> Self {
+        let agent = data.agent_data();
+        Self {
+            id,
+            road_state: data.road_state(),
+            x: agent.accelerometer().x(),
+            y: agent.accelerometer().y(),
+            z: agent.accelerometer().z(),
+            latitude: agent.gps().latitude(),
+            longitude: agent.gps().longitude(),
+            timestamp: agent.timestamp(),
+            idempotency_key: agent.idempotency_key().cloned(),
+            agent_id: agent.agent_id().cloned(),
+            motion_speed_mps: data.motion().map(|motion| motion.speed_mps()),
+            motion_heading_deg: data.motion().map(|motion| motion.heading_deg()),
+            motion_distance_m: data.motion().map(|motion| motion.distance_m()),
+            confidence: data.confidence(),
+            distance_m,
+        }
+    }
+}
+
+impl From<NearbyDao> for NearbyProcessedAgent {
+    fn from(dao: NearbyDao) -> Self {
+        Self {
+            data: ProcessedAgentDao {
+                id: Some(dao.id),
+                road_state: dao.road_state,
+                x: dao.x,
+                y: dao.y,
+                z: dao.z,
+                latitude: dao.latitude,
+                longitude: dao.longitude,
+                timestamp: dao.timestamp,
+                idempotency_key: dao.idempotency_key,
+                agent_id: dao.agent_id,
+                motion_speed_mps: dao.motion_speed_mps,
+                motion_heading_deg: dao.motion_heading_deg,
+                motion_distance_m: dao.motion_distance_m,
+                confidence: dao.confidence,
+                deleted_at: None,
+            }
+            .into(),
+            distance_m: dao.distance_m,
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use sqlx::PgConnection;
+
+const TABLE: &str = "processed_agent_data";
+const PARTITION_PREFIX: &str = "processed_agent_data_p";
+
+/// Calendar month in UTC, the range of a partition of the processed agent data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Month {
+    year: i32,
+    /// 1-based
+    month: u32,
+}
+
+impl Month {
+    pub fn containing(timestamp: DateTime<Utc>) -> Self {
+        Self {
+            year: timestamp.year(),
+            month: timestamp.month(),
+        }
+    }
+
+    pub fn start(&self) -> DateTime<Utc> {
+        let date = NaiveDate::from_ymd_opt(self.year, self.month, 1)
+            .expect("Month must be between 1 and 12");
+        Utc.from_utc_datetime(&date.and_time(Default::default()))
+    }
+
+    pub fn next(&self) -> Self {
+        match self.month {
+            12 => Self {
+                year: self.year + 1,
+                month: 1,
+            },
+            month => Self {
+                year: self.year,
+                month: month + 1,
+            },
+        }
+    }
+
+    pub fn end(&self) -> DateTime<Utc> {
+        self.next().start()
+    }
+
+    fn partition_name(&self) -> String {
+        format!("{PARTITION_PREFIX}{:04}{:02}", self.year, self.month)
+    }
+
+    fn from_partition_name(name: &str) -> Option<Self> {
+        let suffix = name.strip_prefix(PARTITION_PREFIX)?;
+        if suffix.len() != 6 || !suffix.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let month = suffix[4..]
+            .parse()
+            .ok()
+            .filter(|month| (1..=12).contains(month))?;
+        Some(Self {
+            year: suffix[..4].parse().ok()?,
+            month,
+        })
+    }
+}
+
+pub async fn is_partitioned(conn: &mut PgConnection) -> sqlx::Result<bool> {
+    let record = sqlx::query!(
+        r#"
+        SELECT relkind = 'p' as "partitioned!"
+        FROM pg_class
+        WHERE oid = 'processed_agent_data'::regclass
+        "#
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(record.partitioned)
+}
+
+/// Replaces the table with one partitioned by month, moving the rows over.
+/// Rows outside of the monthly partitions go to the default partition.
+///
+/// Must run in a transaction. The table is locked until it commits
+pub async fn partition_by_month(conn: &mut PgConnection) -> sqlx::Result<()> {
+    let old = format!("{TABLE}_unpartitioned");
+    for statement in [
+        format!("LOCK TABLE {TABLE} IN ACCESS EXCLUSIVE MODE"),
+        format!("ALTER TABLE {TABLE} RENAME TO {old}"),
+        format!(
+            "CREATE TABLE {TABLE} (LIKE {old} INCLUDING DEFAULTS INCLUDING GENERATED) PARTITION BY RANGE (timestamp)"
+        ),
+        // Unique constraints of a partitioned table must include the partition key.
+        // The idempotency keys are unique by themselves in `idempotency_keys`
+        format!("ALTER TABLE {TABLE} ADD PRIMARY KEY (id, timestamp)"),
+        format!("CREATE UNIQUE INDEX ON {TABLE} (idempotency_key, timestamp)"),
+        format!("CREATE INDEX ON {TABLE} (deleted_at) WHERE deleted_at IS NOT NULL"),
+        format!("CREATE INDEX ON {TABLE} (timestamp, id)"),
+        format!("CREATE INDEX ON {TABLE} (agent_id, timestamp)"),
+        format!("CREATE INDEX ON {TABLE} (id) WHERE NOT map_matched"),
+        format!("CREATE INDEX ON {TABLE} (way_id) WHERE way_id IS NOT NULL"),
+        format!("CREATE INDEX ON {TABLE} (agent_id, timestamp, id) WHERE NOT segmented"),
+        format!("CREATE INDEX ON {TABLE} (trip_id, timestamp) WHERE trip_id IS NOT NULL"),
+        format!("CREATE TABLE {TABLE}_default PARTITION OF {TABLE} DEFAULT"),
+    ] {
+        sqlx::query(&statement).execute(&mut *conn).await?;
+    }
+    #[cfg(feature = "postgis")]
+    sqlx::query(&format!("CREATE INDEX ON {TABLE} USING GIST (location)"))
+        .execute(&mut *conn)
+        .await?;
+
+    let oldest: Option<DateTime<Utc>> =
+        sqlx::query_scalar(&format!("SELECT min(timestamp) FROM {old}"))
+            .fetch_one(&mut *conn)
+            .await?;
+    let now = Month::containing(Utc::now());
+    let first = oldest.map_or(now, |oldest| Month::containing(oldest).min(now));
+    create_partitions(first, now.next(), conn).await?;
+
+    let columns = insertable_columns(&old, conn).await?;
+    for statement in [
+        format!("INSERT INTO {TABLE} ({columns}) SELECT {columns} FROM {old}"),
+        format!("ALTER SEQUENCE {TABLE}_id_seq OWNED BY {TABLE}.id"),
+        format!("DROP TABLE {old}"),
+    ] {
+        sqlx::query(&statement).execute(&mut *conn).await?;
+    }
+
+    Ok(())
+}
+
+/// Columns of the table, other than the generated ones, which are computed again on insert
+async fn insertable_columns(table: &str, conn: &mut PgConnection) -> sqlx::Result<String> {
+    sqlx::query_scalar(
+        "SELECT string_agg(quote_ident(column_name), ', ' ORDER BY ordinal_position)
+        FROM information_schema.columns
+        WHERE table_name = $1 AND is_generated = 'NEVER'",
+    )
+    .bind(table)
+    .fetch_one(conn)
+    .await
+}
+
+/// Creates the missing partitions for the months from `first` to `last`, inclusive.
+///
+/// The rows of a month in the default partition, e.g. timestamped in the future by a skewed
+/// clock of an agent, are moved to the partition of the month, as it can't be created otherwise.
+/// Must run in a transaction
+pub async fn create_partitions(
+    first: Month,
+    last: Month,
+    conn: &mut PgConnection,
+) -> sqlx::Result<()> {
+    let default = format!("{TABLE}_default");
+    let mut month = first;
+    while month <= last {
+        let name = month.partition_name();
+        let start = month.start().to_rfc3339();
+        let end = month.end().to_rfc3339();
+        let exists: bool = sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+            .bind(&name)
+            .fetch_one(&mut *conn)
+            .await?;
+        if !exists {
+            let in_default: bool = sqlx::query_scalar(&format!(
+                "SELECT EXISTS (SELECT FROM {default} WHERE timestamp >= '{start}' AND timestamp < '{end}')"
+            ))
+            .fetch_one(&mut *conn)
+            .await?;
+            let create = format!(
+                "CREATE TABLE {name} PARTITION OF {TABLE} FOR VALUES FROM ('{start}') TO ('{end}')"
+            );
+            if in_default {
+                let columns = insertable_columns(&default, conn).await?;
+                for statement in [
+                    format!("ALTER TABLE {TABLE} DETACH PARTITION {default}"),
+                    create,
+                    format!(
+                        "WITH moved AS (
+                            DELETE FROM {default}
+                            WHERE timestamp >= '{start}' AND timestamp < '{end}'
+                            RETURNING {columns}
+                        )
+                        INSERT INTO {TABLE} ({columns}) SELECT {columns} FROM moved"
+                    ),
+                    format!("ALTER TABLE {TABLE} ATTACH PARTITION {default} DEFAULT"),
+                ] {
+                    sqlx::query(&statement).execute(&mut *conn).await?;
+                }
+            } else {
+                sqlx::query(&create).execute(&mut *conn).await?;
+            }
+        }
+        month = month.next();
+    }
+
+    Ok(())
+}
+
+/// Drops the monthly partitions ending at or before `cutoff`. Returns the names of the dropped ones
+pub async fn drop_partitions_before(
+    cutoff: DateTime<Utc>,
+    conn: &mut PgConnection,
+) -> sqlx::Result<Vec<String>> {
+    let partitions = sqlx::query!(
+        r#"
+        SELECT child.relname as "name!"
+        FROM pg_inherits
+        JOIN pg_class child ON child.oid = pg_inherits.inhrelid
+        WHERE pg_inherits.inhparent = 'processed_agent_data'::regclass
+        "#
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut dropped = Vec::new();
+    for partition in partitions {
+        let expired =
+            Month::from_partition_name(&partition.name).is_some_and(|month| month.end() <= cutoff);
+        if expired {
+            sqlx::query(&format!("DROP TABLE {}", partition.name))
+                .execute(&mut *conn)
+                .await?;
+            dropped.push(partition.name);
+        }
+    }
+
+    Ok(dropped)
+}
@@ -0,0 +1,556 @@
+use std::{
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap},
+    num::{NonZeroU32, NonZeroU8},
+    sync::{Mutex, MutexGuard},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use iot_system::domain::{AgentId, IdempotencyKey, Latitude, Longitude, RoadState};
+
+use super::{
+    aggregate_hourly, check_version, moved, replacement, HourlyAggregate, HourlyTile, Inserted,
+    ProcessedAgentRepository, RetentionOutcome,
+};
+use crate::{
+    data::{
+        geo::haversine_m, AuditAction, AuditEntry, Gps, MapMatched, NearbyDao,
+        NearbyProcessedAgent, ProcessedAgent, ProcessedAgentId, ProcessedAgentWithId,
+        RoadQualityDao, Trip, TripId, TripWithId, Version, WayId, WayMatch,
+    },
+    error::{AppError, AppResult},
+};
+
+/// Storage in the memory of the process, for tests and local development.
+/// The data is lost when the store stops
+#[derive(Debug, Default)]
+pub struct MemoryRepository {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    last_id: i64,
+    rows: BTreeMap<ProcessedAgentId, Row>,
+    idempotency_keys: HashMap<IdempotencyKey, ProcessedAgentId>,
+    audit_log: Vec<(ProcessedAgentId, AuditEntry)>,
+    hourly: HashMap<HourlyTile, HourlyAggregate>,
+    /// The ID of a trip is its index plus one
+    trips: Vec<Trip>,
+}
+
+#[derive(Debug, Clone)]
+struct Row {
+    data: ProcessedAgent,
+    version: Version,
+    deleted_at: Option<DateTime<Utc>>,
+    /// Way the data is matched to, if any
+    way: Option<WayMatch>,
+    map_matched: bool,
+    /// Trip the data is segmented into, if any
+    trip: Option<TripId>,
+    segmented: bool,
+}
+
+impl MemoryRepository {
+    fn state(&self) -> MutexGuard<'_, State> {
+        // The state is consistent between the statements, so it is usable after a panic
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait]
+impl ProcessedAgentRepository for MemoryRepository {
+    async fn insert(&self, data: &ProcessedAgent) -> AppResult<Inserted> {
+        Ok(self.state().insert(data))
+    }
+
+    async fn insert_all(&self, data: &[ProcessedAgent]) -> AppResult<Vec<Inserted>> {
+        let mut state = self.state();
+        Ok(data.iter().map(|data| state.insert(data)).collect())
+    }
+
+    async fn insert_each(&self, data: &[ProcessedAgent]) -> AppResult<Vec<AppResult<Inserted>>> {
+        let mut state = self.state();
+        Ok(data.iter().map(|data| Ok(state.insert(data))).collect())
+    }
+
+    async fn select(
+        &self,
+        id: ProcessedAgentId,
+        include_deleted: bool,
+    ) -> AppResult<Option<(ProcessedAgent, Version)>> {
+        Ok(self
+            .state()
+            .rows
+            .get(&id)
+            .filter(|row| include_deleted || row.deleted_at.is_none())
+            .map(|row| (row.data.clone(), row.version)))
+    }
+
+    async fn select_list(
+        &self,
+        page: NonZeroU32,
+        size: NonZeroU8,
+        include_deleted: bool,
+    ) -> AppResult<Vec<ProcessedAgentWithId>> {
+        let state = self.state();
+        let mut rows: Vec<_> = state
+            .rows
+            .iter()
+            .filter(|(_, row)| include_deleted || row.deleted_at.is_none())
+            .collect();
+        rows.sort_by_key(|(_, row)| std::cmp::Reverse(row.data.agent_data().timestamp()));
+
+        let offset = (page.get() as usize - 1) * size.get() as usize;
+        Ok(rows
+            .into_iter()
+            .skip(offset)
+            .take(size.get().into())
+            .map(|(&id, row)| ProcessedAgentWithId::new(Some(id), row.data.clone(), row.deleted_at))
+            .collect())
+    }
+
+    async fn select_near(
+        &self,
+        latitude: Latitude,
+        longitude: Longitude,
+        radius_m: Option<f64>,
+        limit: i64,
+    ) -> AppResult<Vec<NearbyProcessedAgent>> {
+        let state = self.state();
+        let limit = usize::try_from(limit).unwrap_or_default();
+        // Only the nearest `limit` data found so far are kept, nearest first
+        let mut nearby: Vec<(ProcessedAgentId, &ProcessedAgent, f64)> =
+            Vec::with_capacity(limit + 1);
+        for (id, data) in state.live() {
+            let gps = data.agent_data().gps();
+            let distance_m = haversine_m((latitude, longitude), (gps.latitude(), gps.longitude()));
+            if radius_m.is_some_and(|radius_m| distance_m > radius_m)
+                || nearby.len() == limit
+                    && nearby
+                        .last()
+                        .is_none_or(|&(_, _, farthest_m)| distance_m >= farthest_m)
+            {
+                continue;
+            }
+            let index = nearby.partition_point(|&(_, _, nearer_m)| nearer_m <= distance_m);
+            nearby.insert(index, (id, data, distance_m));
+            nearby.truncate(limit);
+        }
+
+        Ok(nearby
+            .into_iter()
+            .map(|(id, data, distance_m)| NearbyDao::new(id, data, distance_m).into())
+            .collect())
+    }
+
+    async fn select_after(
+        &self,
+        after: ProcessedAgentId,
+        since: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> AppResult<Vec<(ProcessedAgentId, ProcessedAgent)>> {
+        let state = self.state();
+        Ok(state
+            .live()
+            .filter(|&(id, data)| {
+                id > after && since.is_none_or(|since| data.agent_data().timestamp() > since)
+            })
+            .take(usize::try_from(limit).unwrap_or_default())
+            .map(|(id, data)| (id, data.clone()))
+            .collect())
+    }
+
+    async fn update(
+        &self,
+        id: ProcessedAgentId,
+        data: &ProcessedAgent,
+        expected: Option<&[Version]>,
+        actor: &str,
+    ) -> AppResult<(Version, ProcessedAgent)> {
+        let mut state = self.state();
+        let row = state
+            .rows
+            .get_mut(&id)
+            .filter(|row| row.deleted_at.is_none())
+            .ok_or(AppError::NotFound("Processed agent data"))?;
+        check_version(row.version, expected)?;
+
+        let before = serde_json::to_value(&row.data)?;
+        if moved(&row.data, data) {
+            row.trip = None;
+            row.segmented = false;
+        }
+        row.data = replacement(&row.data, data);
+        let after = row.data.clone();
+        row.version = next(row.version);
+        row.way = None;
+        row.map_matched = false;
+        let version = row.version;
+        let audited = serde_json::to_value(&after)?;
+        state.audit(id, actor, AuditAction::Update, Some(before), Some(audited));
+
+        Ok((version, after))
+    }
+
+    async fn delete(
+        &self,
+        id: ProcessedAgentId,
+        expected: Option<&[Version]>,
+        actor: &str,
+    ) -> AppResult<bool> {
+        let mut state = self.state();
+        let Some(row) = state
+            .rows
+            .get_mut(&id)
+            .filter(|row| row.deleted_at.is_none())
+        else {
+            return match expected {
+                None => Ok(false),
+                Some(_) => Err(AppError::PreconditionFailed),
+            };
+        };
+        check_version(row.version, expected)?;
+
+        let before = serde_json::to_value(&row.data)?;
+        row.deleted_at = Some(Utc::now());
+        row.version = next(row.version);
+        state.audit(id, actor, AuditAction::Delete, Some(before), None);
+
+        Ok(true)
+    }
+
+    async fn restore(
+        &self,
+        id: ProcessedAgentId,
+        expected: Option<&[Version]>,
+        actor: &str,
+    ) -> AppResult<(Version, Option<ProcessedAgent>)> {
+        let mut state = self.state();
+        let row = state
+            .rows
+            .get_mut(&id)
+            .ok_or(AppError::NotFound("Processed agent data"))?;
+        check_version(row.version, expected)?;
+        if row.deleted_at.is_none() {
+            return Ok((row.version, None));
+        }
+
+        let after = serde_json::to_value(&row.data)?;
+        row.deleted_at = None;
+        row.version = next(row.version);
+        let (version, data) = (row.version, row.data.clone());
+        state.audit(id, actor, AuditAction::Restore, None, Some(after));
+
+        Ok((version, Some(data)))
+    }
+
+    async fn purge_deleted(&self, cutoff: DateTime<Utc>) -> AppResult<u64> {
+        Ok(self
+            .state()
+            .remove(|row| row.deleted_at.is_some_and(|deleted_at| deleted_at < cutoff)))
+    }
+
+    async fn select_audit_entries(&self, id: ProcessedAgentId) -> AppResult<Vec<AuditEntry>> {
+        Ok(self
+            .state()
+            .audit_log
+            .iter()
+            .filter(|(entry_id, _)| *entry_id == id)
+            .map(|(_, entry)| entry.clone())
+            .collect())
+    }
+
+    async fn select_unsegmented_agents(&self) -> AppResult<Vec<Option<AgentId>>> {
+        let state = self.state();
+        let agents: BTreeSet<_> = state
+            .rows
+            .values()
+            .filter(|row| row.deleted_at.is_none() && !row.segmented)
+            .map(|row| row.data.agent_data().agent_id())
+            .collect();
+        Ok(agents.into_iter().map(Option::<&AgentId>::cloned).collect())
+    }
+
+    async fn select_unsegmented(
+        &self,
+        agent_id: Option<&AgentId>,
+        after: Option<(DateTime<Utc>, ProcessedAgentId)>,
+        limit: i64,
+    ) -> AppResult<Vec<(ProcessedAgentId, ProcessedAgent)>> {
+        let state = self.state();
+        let mut data: Vec<_> = state
+            .rows
+            .iter()
+            .filter(|(_, row)| {
+                row.deleted_at.is_none()
+                    && !row.segmented
+                    && row.data.agent_data().agent_id() == agent_id
+            })
+            .map(|(&id, row)| ((row.data.agent_data().timestamp(), id), &row.data))
+            .filter(|(key, _)| after.is_none_or(|after| *key > after))
+            .collect();
+        data.sort_by_key(|(key, _)| *key);
+
+        Ok(data
+            .into_iter()
+            .take(usize::try_from(limit).unwrap_or_default())
+            .map(|((_, id), data)| (id, data.clone()))
+            .collect())
+    }
+
+    async fn insert_trip(&self, trip: &Trip, data: &[ProcessedAgentId]) -> AppResult<bool> {
+        let mut state = self.state();
+        let stored = state.trips.iter().position(|stored| {
+            stored.agent_id() == trip.agent_id() && stored.start_time() == trip.start_time()
+        });
+        let (index, inserted) = match stored {
+            Some(index) => (index, false),
+            None => {
+                state.trips.push(trip.clone());
+                (state.trips.len() - 1, true)
+            }
+        };
+        let id = TripId::from(index as i64 + 1);
+        for data in data {
+            if let Some(row) = state.rows.get_mut(data) {
+                row.trip = Some(id);
+                row.segmented = true;
+            }
+        }
+        Ok(inserted)
+    }
+
+    async fn mark_segmented(&self, data: &[ProcessedAgentId]) -> AppResult<()> {
+        let mut state = self.state();
+        for data in data {
+            if let Some(row) = state.rows.get_mut(data) {
+                row.segmented = true;
+            }
+        }
+        Ok(())
+    }
+
+    async fn select_trips(&self, page: NonZeroU32, size: NonZeroU8) -> AppResult<Vec<TripWithId>> {
+        let state = self.state();
+        let mut trips: Vec<_> = state.trips().collect();
+        trips.sort_by_key(|trip| std::cmp::Reverse(trip.trip().start_time()));
+
+        let offset = (page.get() as usize - 1) * size.get() as usize;
+        Ok(trips
+            .into_iter()
+            .skip(offset)
+            .take(size.get().into())
+            .collect())
+    }
+
+    async fn select_trip(&self, id: TripId) -> AppResult<Option<TripWithId>> {
+        let state = self.state();
+        Ok(usize::try_from(i64::from(id) - 1)
+            .ok()
+            .and_then(|index| state.trips.get(index))
+            .map(|trip| TripWithId::new(id, trip.clone())))
+    }
+
+    async fn select_trip_data(&self, id: TripId) -> AppResult<Vec<ProcessedAgentWithId>> {
+        let state = self.state();
+        let mut data: Vec<_> = state
+            .rows
+            .iter()
+            .filter(|(_, row)| row.deleted_at.is_none() && row.trip == Some(id))
+            .map(|(&id, row)| (id, &row.data))
+            .collect();
+        data.sort_by_key(|&(id, data)| (data.agent_data().timestamp(), id));
+
+        Ok(data
+            .into_iter()
+            .map(|(id, data)| ProcessedAgentWithId::new(Some(id), data.clone(), None))
+            .collect())
+    }
+
+    async fn select_unmatched(
+        &self,
+        limit: i64,
+    ) -> AppResult<Vec<(ProcessedAgentId, Version, Gps)>> {
+        Ok(self
+            .state()
+            .rows
+            .iter()
+            .filter(|(_, row)| !row.map_matched && row.deleted_at.is_none())
+            .take(limit.try_into().unwrap_or(0))
+            .map(|(&id, row)| (id, row.version, row.data.agent_data().gps()))
+            .collect())
+    }
+
+    async fn update_way_matches(&self, matches: &[MapMatched]) -> AppResult<u64> {
+        let mut state = self.state();
+        let mut updated = 0;
+        for matched in matches {
+            if let Some(row) = state
+                .rows
+                .get_mut(&matched.id)
+                .filter(|row| row.version == matched.version)
+            {
+                row.way = matched.way;
+                row.map_matched = true;
+                updated += 1;
+            }
+        }
+        Ok(updated)
+    }
+
+    async fn select_road_quality(
+        &self,
+        page: NonZeroU32,
+        size: NonZeroU8,
+    ) -> AppResult<Vec<RoadQualityDao>> {
+        let state = self.state();
+        let mut ways: BTreeMap<WayId, RoadQualityDao> = BTreeMap::new();
+        for row in state.rows.values().filter(|row| row.deleted_at.is_none()) {
+            let Some(way) = row.way else {
+                continue;
+            };
+            let quality = ways.entry(way.way_id).or_insert(RoadQualityDao {
+                way_id: way.way_id,
+                samples: 0,
+                rough_samples: 0,
+            });
+            quality.samples += 1;
+            quality.rough_samples += i64::from(row.data.road_state() == RoadState::Rough);
+        }
+        let mut ways: Vec<_> = ways.into_values().collect();
+        // Like the SQL storages, the largest share of rough road first, then the most samples
+        ways.sort_by(|a, b| {
+            let share =
+                |quality: &RoadQualityDao| quality.rough_samples as f64 / quality.samples as f64;
+            share(b)
+                .total_cmp(&share(a))
+                .then(b.samples.cmp(&a.samples))
+        });
+
+        let offset = (page.get() as usize - 1) * size.get() as usize;
+        Ok(ways
+            .into_iter()
+            .skip(offset)
+            .take(size.get().into())
+            .collect())
+    }
+
+    async fn apply_retention(
+        &self,
+        cutoff: DateTime<Utc>,
+        tile_size_deg: f64,
+        dry_run: bool,
+    ) -> AppResult<RetentionOutcome> {
+        let mut state = self.state();
+        let expired = |row: &Row| row.data.agent_data().timestamp() < cutoff;
+        let aggregates = aggregate_hourly(
+            state
+                .rows
+                .values()
+                .filter(|row| expired(row) && row.deleted_at.is_none())
+                .map(|row| &row.data),
+            tile_size_deg,
+        );
+        let outcome = RetentionOutcome {
+            applied: true,
+            expired_rows: state.rows.values().filter(|row| expired(row)).count() as i64,
+            aggregates: aggregates.len() as i64,
+            dropped_partitions: Vec::new(),
+        };
+        if dry_run {
+            return Ok(outcome);
+        }
+
+        for (tile, aggregate) in aggregates {
+            match state.hourly.entry(tile) {
+                Entry::Occupied(mut existing) => {
+                    *existing.get_mut() = existing.get().merge(aggregate);
+                }
+                Entry::Vacant(vacant) => {
+                    vacant.insert(aggregate);
+                }
+            }
+        }
+        state.remove(expired);
+
+        Ok(outcome)
+    }
+}
+
+impl State {
+    fn insert(&mut self, data: &ProcessedAgent) -> Inserted {
+        let key = data.agent_data().idempotency_key();
+        if let Some(&id) = key.and_then(|key| self.idempotency_keys.get(key)) {
+            return Inserted { id, created: false };
+        }
+
+        self.last_id += 1;
+        let id = ProcessedAgentId::from(self.last_id);
+        if let Some(key) = key {
+            self.idempotency_keys.insert(key.clone(), id);
+        }
+        self.rows.insert(
+            id,
+            Row {
+                data: data.clone(),
+                version: Version::from(1),
+                deleted_at: None,
+                way: None,
+                map_matched: false,
+                trip: None,
+                segmented: false,
+            },
+        );
+        Inserted { id, created: true }
+    }
+
+    fn trips(&self) -> impl Iterator<Item = TripWithId> + '_ {
+        self.trips
+            .iter()
+            .enumerate()
+            .map(|(index, trip)| TripWithId::new(TripId::from(index as i64 + 1), trip.clone()))
+    }
+
+    fn live(&self) -> impl Iterator<Item = (ProcessedAgentId, &ProcessedAgent)> {
+        self.rows
+            .iter()
+            .filter(|(_, row)| row.deleted_at.is_none())
+            .map(|(&id, row)| (id, &row.data))
+    }
+
+    /// Removes the rows matching the predicate. Returns the number of removed rows
+    fn remove(&mut self, predicate: impl Fn(&Row) -> bool) -> u64 {
+        let before = self.rows.len();
+        self.rows.retain(|_, row| !predicate(row));
+        let rows = &self.rows;
+        self.idempotency_keys.retain(|_, id| rows.contains_key(id));
+        (before - self.rows.len()) as u64
+    }
+
+    fn audit(
+        &mut self,
+        id: ProcessedAgentId,
+        actor: &str,
+        action: AuditAction,
+        before: Option<serde_json::Value>,
+        after: Option<serde_json::Value>,
+    ) {
+        let entry = AuditEntry {
+            id: self.audit_log.len() as i64 + 1,
+            actor: actor.to_owned(),
+            action,
+            before,
+            after,
+            timestamp: Utc::now(),
+        };
+        self.audit_log.push((id, entry));
+    }
+}
+
+fn next(version: Version) -> Version {
+    Version::from(i64::from(version) + 1)
+}
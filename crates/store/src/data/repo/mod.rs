@@ -0,0 +1,285 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    num::{NonZeroU32, NonZeroU8},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use iot_system::domain::{AgentId, RoadState};
+
+pub use self::{memory::MemoryRepository, postgres::PgRepository, sqlite::SqliteRepository};
+use super::{
+    AuditEntry, Gps, Latitude, Longitude, MapMatched, NearbyProcessedAgent, ProcessedAgent,
+    ProcessedAgentId, ProcessedAgentWithId, RoadQualityDao, Trip, TripId, TripWithId, Version,
+};
+use crate::error::{AppError, AppResult};
+
+mod memory;
+pub mod postgres;
+mod sqlite;
+#[cfg(test)]
+mod tests;
+
+/// Storage of the processed agent data, of its audit trail, of the trips segmented from it,
+/// and of the ways of the road network it is matched to.
+///
+/// Every method is atomic: a failing method leaves the storage unchanged
+#[async_trait]
+pub trait ProcessedAgentRepository: Debug + Send + Sync {
+    /// Inserts the data unless data with the same idempotency key is stored
+    async fn insert(&self, data: &ProcessedAgent) -> AppResult<Inserted>;
+
+    /// Inserts either all of the data, or none of it
+    async fn insert_all(&self, data: &[ProcessedAgent]) -> AppResult<Vec<Inserted>>;
+
+    /// Inserts each of the data independently, so that a failing item doesn't prevent
+    /// the others from being stored
+    async fn insert_each(&self, data: &[ProcessedAgent]) -> AppResult<Vec<AppResult<Inserted>>>;
+
+    async fn select(
+        &self,
+        id: ProcessedAgentId,
+        include_deleted: bool,
+    ) -> AppResult<Option<(ProcessedAgent, Version)>>;
+
+    /// Selects a page of the data, latest first
+    async fn select_list(
+        &self,
+        page: NonZeroU32,
+        size: NonZeroU8,
+        include_deleted: bool,
+    ) -> AppResult<Vec<ProcessedAgentWithId>>;
+
+    /// Selects up to `limit` live data nearest to the point and, if given,
+    /// within `radius_m` meters of it, ordered by the distance
+    async fn select_near(
+        &self,
+        latitude: Latitude,
+        longitude: Longitude,
+        radius_m: Option<f64>,
+        limit: i64,
+    ) -> AppResult<Vec<NearbyProcessedAgent>>;
+
+    /// Selects up to `limit` live data with IDs greater than `after` and, if given,
+    /// timestamps later than `since`, ordered by ID
+    async fn select_after(
+        &self,
+        after: ProcessedAgentId,
+        since: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> AppResult<Vec<(ProcessedAgentId, ProcessedAgent)>>;
+
+    /// Replaces the live data if its version is one of `expected`, or unconditionally
+    /// if `expected` is `None`, recording the change in the audit trail. The idempotency key
+    /// and the agent are kept, see [`replacement`]. The replaced data is matched to the road
+    /// network again. If its timestamp or location changes, it is segmented into trips again,
+    /// but the stored trips are not re-derived. Returns the new version and the data as stored
+    async fn update(
+        &self,
+        id: ProcessedAgentId,
+        data: &ProcessedAgent,
+        expected: Option<&[Version]>,
+        actor: &str,
+    ) -> AppResult<(Version, ProcessedAgent)>;
+
+    /// Soft-deletes the data if its version is one of `expected`, or unconditionally
+    /// if `expected` is `None`, recording the deletion in the audit trail.
+    /// Returns `false` if the data is absent or already deleted, and no version is expected
+    async fn delete(
+        &self,
+        id: ProcessedAgentId,
+        expected: Option<&[Version]>,
+        actor: &str,
+    ) -> AppResult<bool>;
+
+    /// Restores the soft-deleted data if its version is one of `expected`, or unconditionally
+    /// if `expected` is `None`, recording the restoration in the audit trail.
+    /// Returns the new version, and the data if it was deleted
+    async fn restore(
+        &self,
+        id: ProcessedAgentId,
+        expected: Option<&[Version]>,
+        actor: &str,
+    ) -> AppResult<(Version, Option<ProcessedAgent>)>;
+
+    /// Permanently deletes the data soft-deleted before `cutoff`. Returns the number of purged data
+    async fn purge_deleted(&self, cutoff: DateTime<Utc>) -> AppResult<u64>;
+
+    /// Selects the audit trail of the data, oldest first
+    async fn select_audit_entries(&self, id: ProcessedAgentId) -> AppResult<Vec<AuditEntry>>;
+
+    /// Rolls the data older than `cutoff` into hourly per-tile aggregates, then deletes it.
+    /// Nothing is changed in the dry run, but the outcome is reported as if it was
+    async fn apply_retention(
+        &self,
+        cutoff: DateTime<Utc>,
+        tile_size_deg: f64,
+        dry_run: bool,
+    ) -> AppResult<RetentionOutcome>;
+
+    /// Selects the IDs of the agents with live data not segmented into trips yet.
+    /// `None` stands for the data without an agent ID
+    async fn select_unsegmented_agents(&self) -> AppResult<Vec<Option<AgentId>>>;
+
+    /// Selects up to `limit` live data of the agent not segmented into trips yet,
+    /// following `after` in the order of the timestamps, then of the IDs.
+    /// Starts from the earliest data if `after` is `None`
+    async fn select_unsegmented(
+        &self,
+        agent_id: Option<&AgentId>,
+        after: Option<(DateTime<Utc>, ProcessedAgentId)>,
+        limit: i64,
+    ) -> AppResult<Vec<(ProcessedAgentId, ProcessedAgent)>>;
+
+    /// Inserts the trip, unless a trip of the agent starting at the same time is stored,
+    /// and marks the data segmented into the stored one. Returns whether the trip was inserted
+    async fn insert_trip(&self, trip: &Trip, data: &[ProcessedAgentId]) -> AppResult<bool>;
+
+    /// Marks the data segmented into no trip
+    async fn mark_segmented(&self, data: &[ProcessedAgentId]) -> AppResult<()>;
+
+    /// Selects a page of the trips, latest first
+    async fn select_trips(&self, page: NonZeroU32, size: NonZeroU8) -> AppResult<Vec<TripWithId>>;
+
+    async fn select_trip(&self, id: TripId) -> AppResult<Option<TripWithId>>;
+
+    /// Selects the live data segmented into the trip, oldest first
+    async fn select_trip_data(&self, id: TripId) -> AppResult<Vec<ProcessedAgentWithId>>;
+
+    /// Selects the positions and the versions of up to `limit` live data not matched
+    /// to the road network yet, ordered by ID
+    async fn select_unmatched(
+        &self,
+        limit: i64,
+    ) -> AppResult<Vec<(ProcessedAgentId, Version, Gps)>>;
+
+    /// Stores the ways the data is matched to, skipping the data changed since it was matched.
+    /// Returns the number of stored matches
+    async fn update_way_matches(&self, matches: &[MapMatched]) -> AppResult<u64>;
+
+    /// Selects a page of the statistics of the live data matched to each way,
+    /// the largest share of rough road first
+    async fn select_road_quality(
+        &self,
+        page: NonZeroU32,
+        size: NonZeroU8,
+    ) -> AppResult<Vec<RoadQualityDao>>;
+
+    /// Partitions the data by month, so that the retention drops the expired months at once.
+    /// Storages without partitioning do nothing
+    async fn prepare_partitions(&self) -> AppResult<()> {
+        Ok(())
+    }
+}
+
+/// ID of the inserted data, or of the data previously inserted with the same idempotency key
+#[derive(Debug, Clone, Copy)]
+pub struct Inserted {
+    pub id: ProcessedAgentId,
+    /// `false` if the data already existed
+    pub created: bool,
+}
+
+/// Outcome of applying the retention
+#[derive(Debug, Default)]
+pub struct RetentionOutcome {
+    /// `false` if another store instance was applying the retention
+    pub applied: bool,
+    /// Data older than the cutoff, deleted or not
+    pub expired_rows: i64,
+    /// Hourly per-tile aggregates the live expired data was rolled into
+    pub aggregates: i64,
+    pub dropped_partitions: Vec<String>,
+}
+
+/// Hour and the indices of the tile, counted in tiles from the equator and the prime meridian
+type HourlyTile = (DateTime<Utc>, i64, i64);
+
+/// Statistics of the data of a tile within an hour
+#[derive(Debug, Clone, Copy)]
+struct HourlyAggregate {
+    samples: i64,
+    rough_samples: i64,
+    mean_z: f64,
+    min_z: f64,
+    max_z: f64,
+}
+
+impl HourlyAggregate {
+    fn of(data: &ProcessedAgent) -> Self {
+        let z = data.agent_data().accelerometer().z();
+        Self {
+            samples: 1,
+            rough_samples: (data.road_state() == RoadState::Rough).into(),
+            mean_z: z,
+            min_z: z,
+            max_z: z,
+        }
+    }
+
+    fn merge(self, other: Self) -> Self {
+        let samples = self.samples + other.samples;
+        Self {
+            samples,
+            rough_samples: self.rough_samples + other.rough_samples,
+            mean_z: (self.mean_z * self.samples as f64 + other.mean_z * other.samples as f64)
+                / samples as f64,
+            min_z: self.min_z.min(other.min_z),
+            max_z: self.max_z.max(other.max_z),
+        }
+    }
+}
+
+/// Groups the data by hour and tile, like the Postgres retention does in SQL
+fn aggregate_hourly<'a>(
+    data: impl IntoIterator<Item = &'a ProcessedAgent>,
+    tile_size_deg: f64,
+) -> HashMap<HourlyTile, HourlyAggregate> {
+    let mut aggregates = HashMap::new();
+    for data in data {
+        let gps = data.agent_data().gps();
+        let tile = (
+            data.agent_data()
+                .timestamp()
+                .duration_trunc(TimeDelta::hours(1))
+                .expect("An hour fits any timestamp"),
+            (f64::from(gps.latitude()) / tile_size_deg).floor() as i64,
+            (f64::from(gps.longitude()) / tile_size_deg).floor() as i64,
+        );
+        let aggregate = HourlyAggregate::of(data);
+        aggregates
+            .entry(tile)
+            .and_modify(|existing: &mut HourlyAggregate| *existing = existing.merge(aggregate))
+            .or_insert(aggregate);
+    }
+    aggregates
+}
+
+/// Whether the replacing data changes the timestamp or the location of the stored one,
+/// which the trips are segmented by
+fn moved(stored: &ProcessedAgent, data: &ProcessedAgent) -> bool {
+    stored.agent_data().timestamp() != data.agent_data().timestamp()
+        || stored.agent_data().gps() != data.agent_data().gps()
+}
+
+/// The data replacing the stored one. The idempotency key and the agent identify
+/// the original sample, so they are kept
+fn replacement(stored: &ProcessedAgent, data: &ProcessedAgent) -> ProcessedAgent {
+    ProcessedAgent::new(
+        data.agent_data()
+            .clone()
+            .with_idempotency_key(stored.agent_data().idempotency_key().cloned())
+            .with_agent_id(stored.agent_data().agent_id().cloned()),
+        data.road_state(),
+    )
+    .with_motion(data.motion())
+    .with_confidence(data.confidence())
+}
+
+fn check_version(version: Version, expected: Option<&[Version]>) -> AppResult<()> {
+    match expected {
+        Some(expected) if !expected.contains(&version) => Err(AppError::PreconditionFailed),
+        _ => Ok(()),
+    }
+}
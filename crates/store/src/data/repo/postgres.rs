@@ -0,0 +1,1327 @@
+use std::num::{NonZeroU32, NonZeroU8};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use iot_system::domain::{AgentId, IdempotencyKey, Latitude, Longitude, RoadState};
+use sqlx::{postgres::PgConnectOptions, types::Json, Connection, PgConnection, PgPool};
+
+use super::{check_version, replacement, Inserted, ProcessedAgentRepository, RetentionOutcome};
+#[cfg(not(feature = "postgis"))]
+use crate::data::geo::MEAN_EARTH_RADIUS_M;
+use crate::{
+    data::{
+        partition::{self, Month},
+        AuditAction, AuditEntry, Gps, MapMatched, NearbyDao, NearbyProcessedAgent, ProcessedAgent,
+        ProcessedAgentDao, ProcessedAgentId, ProcessedAgentWithId, RoadQualityDao, Trip, TripDao,
+        TripId, TripWithId, Version, WayId,
+    },
+    error::{AppError, AppResult},
+};
+
+/// Storage in the Postgres database
+#[derive(Debug, Clone)]
+pub struct PgRepository {
+    pool: PgPool,
+}
+
+/// Row of the processed agent data with its version
+struct VersionedDao {
+    road_state: RoadState,
+    x: f64,
+    y: f64,
+    z: f64,
+    latitude: Latitude,
+    longitude: Longitude,
+    timestamp: DateTime<Utc>,
+    idempotency_key: Option<IdempotencyKey>,
+    agent_id: Option<AgentId>,
+    motion_speed_mps: Option<f64>,
+    motion_heading_deg: Option<f64>,
+    motion_distance_m: Option<f64>,
+    confidence: Option<f64>,
+    version: Version,
+    deleted_at: Option<DateTime<Utc>>,
+}
+
+/// Row locked until the end of the transaction
+struct Locked {
+    data: ProcessedAgent,
+    version: Version,
+    deleted: bool,
+}
+
+impl PgRepository {
+    /// Connects to the database and applies the migrations
+    pub async fn connect(options: PgConnectOptions) -> sqlx::Result<Self> {
+        let pool = PgPool::connect_with(options).await?;
+        let mut migrator = sqlx::migrate!("./migrations");
+        // The feature specific migrations are recorded alongside the common ones
+        migrator.set_ignore_missing(true);
+        migrator.run(&pool).await?;
+        #[cfg(feature = "postgis")]
+        sqlx::migrate!("./migrations-postgis")
+            .set_ignore_missing(true)
+            .run(&pool)
+            .await?;
+
+        Ok(Self { pool })
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+}
+
+#[async_trait]
+impl ProcessedAgentRepository for PgRepository {
+    async fn insert(&self, data: &ProcessedAgent) -> AppResult<Inserted> {
+        Ok(insert_processed_agent_data(data, &self.pool).await?)
+    }
+
+    async fn insert_all(&self, data: &[ProcessedAgent]) -> AppResult<Vec<Inserted>> {
+        Ok(insert_processed_agent_data_list(data, &self.pool).await?)
+    }
+
+    async fn insert_each(&self, data: &[ProcessedAgent]) -> AppResult<Vec<AppResult<Inserted>>> {
+        let results = insert_processed_agent_data_each(data, &self.pool).await?;
+        Ok(results
+            .into_iter()
+            .map(|result| result.map_err(Into::into))
+            .collect())
+    }
+
+    async fn select(
+        &self,
+        id: ProcessedAgentId,
+        include_deleted: bool,
+    ) -> AppResult<Option<(ProcessedAgent, Version)>> {
+        Ok(select_processed_agent_data(id, include_deleted, &self.pool).await?)
+    }
+
+    async fn select_list(
+        &self,
+        page: NonZeroU32,
+        size: NonZeroU8,
+        include_deleted: bool,
+    ) -> AppResult<Vec<ProcessedAgentWithId>> {
+        Ok(select_processed_agent_data_list(page, size, include_deleted, &self.pool).await?)
+    }
+
+    async fn select_near(
+        &self,
+        latitude: Latitude,
+        longitude: Longitude,
+        radius_m: Option<f64>,
+        limit: i64,
+    ) -> AppResult<Vec<NearbyProcessedAgent>> {
+        Ok(
+            select_processed_agent_data_near(latitude, longitude, radius_m, limit, &self.pool)
+                .await?,
+        )
+    }
+
+    async fn select_after(
+        &self,
+        after: ProcessedAgentId,
+        since: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> AppResult<Vec<(ProcessedAgentId, ProcessedAgent)>> {
+        Ok(select_processed_agent_data_after(after, since, limit, &self.pool).await?)
+    }
+
+    async fn update(
+        &self,
+        id: ProcessedAgentId,
+        data: &ProcessedAgent,
+        expected: Option<&[Version]>,
+        actor: &str,
+    ) -> AppResult<(Version, ProcessedAgent)> {
+        let mut tx = self.pool.begin().await?;
+        let Locked {
+            data: before,
+            version,
+            deleted: false,
+        } = lock(id, &mut tx).await?
+        else {
+            return Err(AppError::NotFound("Processed agent data"));
+        };
+        check_version(version, expected)?;
+
+        let after = replacement(&before, data);
+        let version = update_processed_agent_data(id, &after, &mut tx).await?;
+        insert_audit_entry(
+            id,
+            actor,
+            AuditAction::Update,
+            Some(&serde_json::to_value(&before)?),
+            Some(&serde_json::to_value(&after)?),
+            &mut tx,
+        )
+        .await?;
+        tx.commit().await?;
+
+        Ok((version, after))
+    }
+
+    async fn delete(
+        &self,
+        id: ProcessedAgentId,
+        expected: Option<&[Version]>,
+        actor: &str,
+    ) -> AppResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let Some(Locked {
+            data: before,
+            version,
+            deleted: false,
+        }) = lock_processed_agent_data(id, &mut tx).await?
+        else {
+            return match expected {
+                None => Ok(false),
+                Some(_) => Err(AppError::PreconditionFailed),
+            };
+        };
+        check_version(version, expected)?;
+
+        delete_processed_agent_data(id, &mut tx).await?;
+        insert_audit_entry(
+            id,
+            actor,
+            AuditAction::Delete,
+            Some(&serde_json::to_value(&before)?),
+            None,
+            &mut tx,
+        )
+        .await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    async fn restore(
+        &self,
+        id: ProcessedAgentId,
+        expected: Option<&[Version]>,
+        actor: &str,
+    ) -> AppResult<(Version, Option<ProcessedAgent>)> {
+        let mut tx = self.pool.begin().await?;
+        let Locked {
+            data,
+            version,
+            deleted,
+        } = lock(id, &mut tx).await?;
+        check_version(version, expected)?;
+        if !deleted {
+            return Ok((version, None));
+        }
+
+        let version = restore_processed_agent_data(id, &mut tx).await?;
+        insert_audit_entry(
+            id,
+            actor,
+            AuditAction::Restore,
+            None,
+            Some(&serde_json::to_value(&data)?),
+            &mut tx,
+        )
+        .await?;
+        tx.commit().await?;
+
+        Ok((version, Some(data)))
+    }
+
+    async fn purge_deleted(&self, cutoff: DateTime<Utc>) -> AppResult<u64> {
+        Ok(purge_deleted_processed_agent_data(cutoff, &self.pool).await?)
+    }
+
+    async fn select_audit_entries(&self, id: ProcessedAgentId) -> AppResult<Vec<AuditEntry>> {
+        Ok(select_audit_entries(id, &self.pool).await?)
+    }
+
+    async fn select_unsegmented_agents(&self) -> AppResult<Vec<Option<AgentId>>> {
+        Ok(select_unsegmented_agents(&self.pool).await?)
+    }
+
+    async fn select_unsegmented(
+        &self,
+        agent_id: Option<&AgentId>,
+        after: Option<(DateTime<Utc>, ProcessedAgentId)>,
+        limit: i64,
+    ) -> AppResult<Vec<(ProcessedAgentId, ProcessedAgent)>> {
+        Ok(select_unsegmented_processed_agent_data(agent_id, after, limit, &self.pool).await?)
+    }
+
+    async fn insert_trip(&self, trip: &Trip, data: &[ProcessedAgentId]) -> AppResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let (id, inserted) = insert_trip(trip, &mut tx).await?;
+        mark_segmented(data, Some(id), &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(inserted)
+    }
+
+    async fn mark_segmented(&self, data: &[ProcessedAgentId]) -> AppResult<()> {
+        Ok(mark_segmented(data, None, &mut *self.pool.acquire().await?).await?)
+    }
+
+    async fn select_trips(&self, page: NonZeroU32, size: NonZeroU8) -> AppResult<Vec<TripWithId>> {
+        Ok(select_trips(page, size, &self.pool).await?)
+    }
+
+    async fn select_trip(&self, id: TripId) -> AppResult<Option<TripWithId>> {
+        Ok(select_trip(id, &self.pool).await?)
+    }
+
+    async fn select_trip_data(&self, id: TripId) -> AppResult<Vec<ProcessedAgentWithId>> {
+        Ok(select_trip_processed_agent_data(id, &self.pool).await?)
+    }
+
+    async fn select_unmatched(
+        &self,
+        limit: i64,
+    ) -> AppResult<Vec<(ProcessedAgentId, Version, Gps)>> {
+        Ok(select_unmatched_processed_agent_data(limit, &self.pool).await?)
+    }
+
+    async fn update_way_matches(&self, matches: &[MapMatched]) -> AppResult<u64> {
+        Ok(update_way_matches(matches, &self.pool).await?)
+    }
+
+    async fn select_road_quality(
+        &self,
+        page: NonZeroU32,
+        size: NonZeroU8,
+    ) -> AppResult<Vec<RoadQualityDao>> {
+        Ok(select_road_quality(page, size, &self.pool).await?)
+    }
+
+    /// Drops the expired monthly partitions if the table is partitioned.
+    /// Only one store instance applies the retention at a time
+    async fn apply_retention(
+        &self,
+        cutoff: DateTime<Utc>,
+        tile_size_deg: f64,
+        dry_run: bool,
+    ) -> AppResult<RetentionOutcome> {
+        let mut tx = self.pool.begin().await?;
+        if !try_lock_retention(&mut tx).await? {
+            return Ok(RetentionOutcome::default());
+        }
+
+        let aggregates = aggregate_hourly_before(cutoff, tile_size_deg, &mut tx).await?;
+        let expired_rows = count_processed_agent_data_before(cutoff, &mut tx).await?;
+        let dropped_partitions = if partition::is_partitioned(&mut tx).await? {
+            partition::drop_partitions_before(cutoff, &mut tx).await?
+        } else {
+            Vec::new()
+        };
+        delete_processed_agent_data_before(cutoff, &mut tx).await?;
+        if dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+
+        Ok(RetentionOutcome {
+            applied: true,
+            expired_rows,
+            aggregates,
+            dropped_partitions,
+        })
+    }
+
+    /// Partitions the table by month unless it is already, and creates the partitions
+    /// for the current and the next month
+    async fn prepare_partitions(&self) -> AppResult<()> {
+        let mut tx = self.pool.begin().await?;
+        if !partition::is_partitioned(&mut tx).await? {
+            tracing::info!("Partitioning the processed agent data by month");
+            partition::partition_by_month(&mut tx).await?;
+        }
+        let now = Month::containing(Utc::now());
+        partition::create_partitions(now, now.next(), &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+async fn lock(id: ProcessedAgentId, conn: &mut PgConnection) -> AppResult<Locked> {
+    lock_processed_agent_data(id, conn)
+        .await?
+        .ok_or(AppError::NotFound("Processed agent data"))
+}
+
+async fn insert_processed_agent_data_list(
+    agents: &[ProcessedAgent],
+    pool: &PgPool,
+) -> sqlx::Result<Vec<Inserted>> {
+    let mut tx = pool.begin().await?;
+
+    let mut inserted = Vec::with_capacity(agents.len());
+    for agent in agents {
+        inserted.push(insert(agent, &mut tx).await?);
+    }
+
+    tx.commit().await?;
+
+    Ok(inserted)
+}
+
+/// Inserts each row in its own savepoint, so that a failing row doesn't prevent
+/// the others from being stored
+async fn insert_processed_agent_data_each(
+    agents: &[ProcessedAgent],
+    pool: &PgPool,
+) -> sqlx::Result<Vec<sqlx::Result<Inserted>>> {
+    let mut tx = pool.begin().await?;
+
+    let mut results = Vec::with_capacity(agents.len());
+    for agent in agents {
+        let mut savepoint = (*tx).begin().await?;
+        let result = insert(agent, &mut savepoint).await;
+        match result {
+            Ok(_) => savepoint.commit().await?,
+            Err(_) => savepoint.rollback().await?,
+        }
+        results.push(result);
+    }
+
+    tx.commit().await?;
+
+    Ok(results)
+}
+
+async fn insert_processed_agent_data(
+    agent: &ProcessedAgent,
+    pool: &PgPool,
+) -> sqlx::Result<Inserted> {
+    insert(agent, &mut *pool.acquire().await?).await
+}
+
+/// Inserts the row unless a row with the same idempotency key exists.
+///
+/// The key is reserved in `idempotency_keys` first, in the same transaction,
+/// as the unique index of the rows includes the timestamp once the table is partitioned.
+/// A concurrent insert of the same key waits for the reserving transaction to finish
+async fn insert(agent: &ProcessedAgent, conn: &mut PgConnection) -> sqlx::Result<Inserted> {
+    let mut tx = conn.begin().await?;
+    let idempotency_key = agent
+        .agent_data()
+        .idempotency_key()
+        .map(IdempotencyKey::as_str);
+    let reserved_id = match idempotency_key {
+        Some(key) => {
+            let reserved = sqlx::query_scalar!(
+                r#"
+                INSERT INTO idempotency_keys (key, id, timestamp)
+                VALUES ($1, nextval('processed_agent_data_id_seq'), $2)
+                ON CONFLICT (key) DO NOTHING
+                RETURNING id as "id: ProcessedAgentId"
+                "#,
+                key,
+                agent.agent_data().timestamp()
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
+            let Some(id) = reserved else {
+                let id = sqlx::query_scalar!(
+                    r#"
+                    SELECT id as "id: ProcessedAgentId"
+                    FROM idempotency_keys
+                    WHERE key = $1
+                    "#,
+                    key
+                )
+                .fetch_one(&mut *tx)
+                .await?;
+                tx.commit().await?;
+                return Ok(Inserted { id, created: false });
+            };
+            Some(id)
+        }
+        None => None,
+    };
+
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO processed_agent_data (
+            id, road_state, x, y, z, latitude, longitude, timestamp, idempotency_key,
+            motion_speed_mps, motion_heading_deg, motion_distance_m, confidence, agent_id
+        )
+        VALUES (
+            COALESCE($1, nextval('processed_agent_data_id_seq')),
+            $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14
+        )
+        ON CONFLICT (idempotency_key, timestamp) DO NOTHING
+        RETURNING id as "id: ProcessedAgentId"
+        "#,
+        reserved_id as Option<ProcessedAgentId>,
+        agent.road_state() as RoadState,
+        agent.agent_data().accelerometer().x(),
+        agent.agent_data().accelerometer().y(),
+        agent.agent_data().accelerometer().z(),
+        agent.agent_data().gps().latitude() as Latitude,
+        agent.agent_data().gps().longitude() as Longitude,
+        agent.agent_data().timestamp(),
+        idempotency_key,
+        agent.motion().map(|motion| motion.speed_mps()),
+        agent.motion().map(|motion| motion.heading_deg()),
+        agent.motion().map(|motion| motion.distance_m()),
+        agent.confidence(),
+        agent.agent_data().agent_id().map(AgentId::as_str)
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Inserted { id, created: true })
+}
+
+async fn select_processed_agent_data(
+    id: ProcessedAgentId,
+    include_deleted: bool,
+    pool: &PgPool,
+) -> sqlx::Result<Option<(ProcessedAgent, Version)>> {
+    let record = sqlx::query_as!(
+        VersionedDao,
+        r#"
+        SELECT
+            road_state as "road_state!: RoadState",
+            x, y, z,
+            latitude as "latitude!: Latitude",
+            longitude as "longitude!: Longitude",
+            timestamp,
+            idempotency_key as "idempotency_key: IdempotencyKey",
+            agent_id as "agent_id: AgentId",
+            motion_speed_mps, motion_heading_deg, motion_distance_m, confidence,
+            version as "version!: Version",
+            deleted_at
+        FROM processed_agent_data
+        WHERE id = $1 AND ($2 OR deleted_at IS NULL)
+        "#,
+        id as ProcessedAgentId,
+        include_deleted
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(Into::into))
+}
+
+/// Selects the row, deleted or not, locking it until the end of the transaction
+async fn lock_processed_agent_data(
+    id: ProcessedAgentId,
+    conn: &mut PgConnection,
+) -> sqlx::Result<Option<Locked>> {
+    let record = sqlx::query_as!(
+        VersionedDao,
+        r#"
+        SELECT
+            road_state as "road_state!: RoadState",
+            x, y, z,
+            latitude as "latitude!: Latitude",
+            longitude as "longitude!: Longitude",
+            timestamp,
+            idempotency_key as "idempotency_key: IdempotencyKey",
+            agent_id as "agent_id: AgentId",
+            motion_speed_mps, motion_heading_deg, motion_distance_m, confidence,
+            version as "version!: Version",
+            deleted_at
+        FROM processed_agent_data
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        id as ProcessedAgentId
+    )
+    .fetch_optional(conn)
+    .await?;
+
+    Ok(record.map(Into::into))
+}
+
+async fn select_processed_agent_data_list(
+    page: NonZeroU32,
+    size: NonZeroU8,
+    include_deleted: bool,
+    pool: &PgPool,
+) -> sqlx::Result<Vec<ProcessedAgentWithId>> {
+    let offset = (page.get() - 1) * size.get() as u32;
+
+    let records = sqlx::query_as!(
+        ProcessedAgentDao,
+        r#"
+        SELECT
+            id as "id!: ProcessedAgentId",
+            road_state as "road_state!: RoadState",
+            x, y, z,
+            latitude as "latitude!: Latitude",
+            longitude as "longitude!: Longitude",
+            timestamp,
+            idempotency_key as "idempotency_key: IdempotencyKey",
+            agent_id as "agent_id: AgentId",
+            motion_speed_mps, motion_heading_deg, motion_distance_m, confidence,
+            deleted_at
+        FROM processed_agent_data
+        WHERE $3 OR deleted_at IS NULL
+        ORDER BY timestamp DESC
+        LIMIT $1 OFFSET $2
+        "#,
+        size.get() as i32,
+        offset as i32,
+        include_deleted
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records.into_iter().map(Into::into).collect())
+}
+
+/// Selects up to `limit` live rows nearest to the point and, if given, within `radius_m` meters of it,
+/// ordered by the distance. Uses the GiST index on the `location` column
+#[cfg(feature = "postgis")]
+async fn select_processed_agent_data_near(
+    latitude: Latitude,
+    longitude: Longitude,
+    radius_m: Option<f64>,
+    limit: i64,
+    pool: &PgPool,
+) -> sqlx::Result<Vec<NearbyProcessedAgent>> {
+    // Not checked at compile time, as the `location` column exists only with PostGIS installed
+    let records = sqlx::query_as::<_, NearbyDao>(
+        r#"
+        SELECT
+            id, road_state, x, y, z, latitude, longitude, timestamp, idempotency_key, agent_id,
+            motion_speed_mps, motion_heading_deg, motion_distance_m, confidence,
+            ST_Distance(location, point) as distance_m
+        FROM processed_agent_data,
+            ST_SetSRID(ST_MakePoint($2, $1), 4326)::GEOGRAPHY as point
+        WHERE deleted_at IS NULL
+            AND ($3::FLOAT IS NULL OR ST_DWithin(location, point, $3))
+        ORDER BY location <-> point
+        LIMIT $4
+        "#,
+    )
+    .bind(latitude)
+    .bind(longitude)
+    .bind(radius_m)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records.into_iter().map(Into::into).collect())
+}
+
+/// Selects up to `limit` live rows nearest to the point and, if given, within `radius_m` meters of it,
+/// ordered by the distance. The distance is approximated with the haversine formula on a sphere,
+/// scanning the rows in the latitude band of the radius
+#[cfg(not(feature = "postgis"))]
+async fn select_processed_agent_data_near(
+    latitude: Latitude,
+    longitude: Longitude,
+    radius_m: Option<f64>,
+    limit: i64,
+    pool: &PgPool,
+) -> sqlx::Result<Vec<NearbyProcessedAgent>> {
+    let latitude_delta = radius_m.map(|radius_m| (radius_m / MEAN_EARTH_RADIUS_M).to_degrees());
+    let records = sqlx::query_as!(
+        NearbyDao,
+        r#"
+        WITH nearby AS (
+            SELECT
+                *,
+                2 * $5::FLOAT * asin(least(1, sqrt(
+                    power(sin(radians(latitude - $1) / 2), 2)
+                    + cos(radians($1)) * cos(radians(latitude))
+                        * power(sin(radians(longitude - $2) / 2), 2)
+                ))) as distance_m
+            FROM processed_agent_data
+            WHERE deleted_at IS NULL
+                AND ($4::FLOAT IS NULL OR latitude BETWEEN $1 - $4 AND $1 + $4)
+        )
+        SELECT
+            id as "id!: ProcessedAgentId",
+            road_state as "road_state!: RoadState",
+            x as "x!", y as "y!", z as "z!",
+            latitude as "latitude!: Latitude",
+            longitude as "longitude!: Longitude",
+            timestamp as "timestamp!",
+            idempotency_key as "idempotency_key: IdempotencyKey",
+            agent_id as "agent_id: AgentId",
+            motion_speed_mps, motion_heading_deg, motion_distance_m, confidence,
+            distance_m as "distance_m!"
+        FROM nearby
+        WHERE $3::FLOAT IS NULL OR distance_m <= $3
+        ORDER BY distance_m
+        LIMIT $6
+        "#,
+        f64::from(latitude),
+        f64::from(longitude),
+        radius_m,
+        latitude_delta,
+        MEAN_EARTH_RADIUS_M,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records.into_iter().map(Into::into).collect())
+}
+
+/// Selects up to `limit` rows with IDs greater than `after` and, if given,
+/// timestamps later than `since`, ordered by ID. Deleted rows are skipped
+async fn select_processed_agent_data_after(
+    after: ProcessedAgentId,
+    since: Option<DateTime<Utc>>,
+    limit: i64,
+    pool: &PgPool,
+) -> sqlx::Result<Vec<(ProcessedAgentId, ProcessedAgent)>> {
+    let records = sqlx::query_as!(
+        ProcessedAgentDao,
+        r#"
+        SELECT
+            id as "id!: ProcessedAgentId",
+            road_state as "road_state!: RoadState",
+            x, y, z,
+            latitude as "latitude!: Latitude",
+            longitude as "longitude!: Longitude",
+            timestamp,
+            idempotency_key as "idempotency_key: IdempotencyKey",
+            agent_id as "agent_id: AgentId",
+            motion_speed_mps, motion_heading_deg, motion_distance_m, confidence,
+            NULL as "deleted_at?: DateTime<Utc>"
+        FROM processed_agent_data
+        WHERE id > $1 AND ($2::TIMESTAMPTZ IS NULL OR timestamp > $2) AND deleted_at IS NULL
+        ORDER BY id
+        LIMIT $3
+        "#,
+        after as ProcessedAgentId,
+        since,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .filter_map(|dao| dao.id.map(|id| (id, dao.into())))
+        .collect())
+}
+
+async fn select_unsegmented_agents(pool: &PgPool) -> sqlx::Result<Vec<Option<AgentId>>> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT agent_id as "agent_id: AgentId"
+        FROM processed_agent_data
+        WHERE NOT segmented AND deleted_at IS NULL
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Selects up to `limit` live rows of the agent not segmented into trips yet, following `after`
+/// in the order of the timestamps, then of the IDs
+async fn select_unsegmented_processed_agent_data(
+    agent_id: Option<&AgentId>,
+    after: Option<(DateTime<Utc>, ProcessedAgentId)>,
+    limit: i64,
+    pool: &PgPool,
+) -> sqlx::Result<Vec<(ProcessedAgentId, ProcessedAgent)>> {
+    let (after_timestamp, after_id) = after.unzip();
+    let records = sqlx::query_as!(
+        ProcessedAgentDao,
+        r#"
+        SELECT
+            id as "id!: ProcessedAgentId",
+            road_state as "road_state!: RoadState",
+            x, y, z,
+            latitude as "latitude!: Latitude",
+            longitude as "longitude!: Longitude",
+            timestamp,
+            idempotency_key as "idempotency_key: IdempotencyKey",
+            agent_id as "agent_id: AgentId",
+            motion_speed_mps, motion_heading_deg, motion_distance_m, confidence,
+            NULL as "deleted_at?: DateTime<Utc>"
+        FROM processed_agent_data
+        WHERE agent_id IS NOT DISTINCT FROM $1 AND NOT segmented AND deleted_at IS NULL
+            AND ($2::TIMESTAMPTZ IS NULL OR (timestamp, id) > ($2, $3))
+        ORDER BY timestamp, id
+        LIMIT $4
+        "#,
+        agent_id as Option<&AgentId>,
+        after_timestamp,
+        after_id as Option<ProcessedAgentId>,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .filter_map(|dao| dao.id.map(|id| (id, dao.into())))
+        .collect())
+}
+
+/// Selects the live rows segmented into the trip, oldest first
+async fn select_trip_processed_agent_data(
+    id: TripId,
+    pool: &PgPool,
+) -> sqlx::Result<Vec<ProcessedAgentWithId>> {
+    let records = sqlx::query_as!(
+        ProcessedAgentDao,
+        r#"
+        SELECT
+            id as "id!: ProcessedAgentId",
+            road_state as "road_state!: RoadState",
+            x, y, z,
+            latitude as "latitude!: Latitude",
+            longitude as "longitude!: Longitude",
+            timestamp,
+            idempotency_key as "idempotency_key: IdempotencyKey",
+            agent_id as "agent_id: AgentId",
+            motion_speed_mps, motion_heading_deg, motion_distance_m, confidence,
+            deleted_at
+        FROM processed_agent_data
+        WHERE trip_id = $1 AND deleted_at IS NULL
+        ORDER BY timestamp, id
+        "#,
+        id as TripId
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records.into_iter().map(Into::into).collect())
+}
+
+/// Returns the new version of the row
+async fn update_processed_agent_data(
+    id: ProcessedAgentId,
+    data: &ProcessedAgent,
+    conn: &mut PgConnection,
+) -> sqlx::Result<Version> {
+    let record = sqlx::query!(
+        r#"
+        WITH updated AS (
+            UPDATE processed_agent_data
+            SET road_state = $1, x = $2, y = $3, z = $4, latitude = $5, longitude = $6, timestamp = $7,
+                motion_speed_mps = $9, motion_heading_deg = $10, motion_distance_m = $11, confidence = $12,
+                version = version + 1, way_id = NULL, way_offset_m = NULL, map_matched = FALSE,
+                -- The moved data is segmented again, the values on the right are the stored ones
+                trip_id = CASE WHEN timestamp = $7 AND latitude = $5 AND longitude = $6 THEN trip_id END,
+                segmented = segmented AND timestamp = $7 AND latitude = $5 AND longitude = $6
+            WHERE id = $8
+            RETURNING id, timestamp, version
+        ), keys AS (
+            -- The key is freed by the retention along with the row, so it follows the timestamp
+            UPDATE idempotency_keys
+            SET timestamp = updated.timestamp
+            FROM updated
+            WHERE idempotency_keys.id = updated.id
+        )
+        SELECT version as "version!: Version" FROM updated
+        "#,
+        data.road_state() as RoadState,
+        data.agent_data().accelerometer().x(),
+        data.agent_data().accelerometer().y(),
+        data.agent_data().accelerometer().z(),
+        data.agent_data().gps().latitude() as Latitude,
+        data.agent_data().gps().longitude() as Longitude,
+        data.agent_data().timestamp(),
+        id as ProcessedAgentId,
+        data.motion().map(|motion| motion.speed_mps()),
+        data.motion().map(|motion| motion.heading_deg()),
+        data.motion().map(|motion| motion.distance_m()),
+        data.confidence()
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(record.version)
+}
+
+/// Marks the row as deleted
+async fn delete_processed_agent_data(
+    id: ProcessedAgentId,
+    conn: &mut PgConnection,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE processed_agent_data
+        SET deleted_at = now(), version = version + 1
+        WHERE id = $1
+        "#,
+        id as ProcessedAgentId
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Unmarks the deleted row. Returns the new version of the row
+async fn restore_processed_agent_data(
+    id: ProcessedAgentId,
+    conn: &mut PgConnection,
+) -> sqlx::Result<Version> {
+    let record = sqlx::query!(
+        r#"
+        UPDATE processed_agent_data
+        SET deleted_at = NULL, version = version + 1
+        WHERE id = $1
+        RETURNING version as "version: Version"
+        "#,
+        id as ProcessedAgentId
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(record.version)
+}
+
+/// Permanently deletes the rows deleted before `cutoff`, freeing their idempotency keys.
+/// Returns the number of purged rows
+async fn purge_deleted_processed_agent_data(
+    cutoff: DateTime<Utc>,
+    pool: &PgPool,
+) -> sqlx::Result<u64> {
+    let record = sqlx::query!(
+        r#"
+        WITH purged AS (
+            DELETE FROM processed_agent_data
+            WHERE deleted_at < $1
+            RETURNING idempotency_key
+        ), freed AS (
+            DELETE FROM idempotency_keys
+            WHERE key IN (SELECT idempotency_key FROM purged)
+        )
+        SELECT count(*) as "count!" FROM purged
+        "#,
+        cutoff
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(record.count as u64)
+}
+
+async fn insert_audit_entry(
+    id: ProcessedAgentId,
+    actor: &str,
+    action: AuditAction,
+    before: Option<&serde_json::Value>,
+    after: Option<&serde_json::Value>,
+    conn: &mut PgConnection,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (processed_agent_data_id, actor, action, before, after)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        id as ProcessedAgentId,
+        actor,
+        action as AuditAction,
+        before,
+        after
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Selects the audit trail of the row, oldest first
+async fn select_audit_entries(
+    id: ProcessedAgentId,
+    pool: &PgPool,
+) -> sqlx::Result<Vec<AuditEntry>> {
+    sqlx::query_as!(
+        AuditEntry,
+        r#"
+        SELECT
+            id,
+            actor,
+            action as "action: AuditAction",
+            before,
+            after,
+            timestamp
+        FROM audit_log
+        WHERE processed_agent_data_id = $1
+        ORDER BY id
+        "#,
+        id as ProcessedAgentId
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Inserts the trip unless a trip of the agent starting at the same time exists.
+/// Returns the ID of the stored trip, and whether it was inserted
+async fn insert_trip(trip: &Trip, conn: &mut PgConnection) -> sqlx::Result<(TripId, bool)> {
+    let inserted = sqlx::query_scalar!(
+        r#"
+        INSERT INTO trips (agent_id, start_time, end_time, distance_m, samples, rough_percentage, polyline)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT ((COALESCE(agent_id, '')), start_time) DO NOTHING
+        RETURNING id as "id: TripId"
+        "#,
+        trip.agent_id.as_ref() as Option<&AgentId>,
+        trip.start_time,
+        trip.end_time,
+        trip.distance_m,
+        trip.samples,
+        trip.rough_percentage,
+        Json(&trip.polyline) as _
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(id) = inserted {
+        return Ok((id, true));
+    }
+
+    let stored = sqlx::query_scalar!(
+        r#"
+        SELECT id as "id: TripId"
+        FROM trips
+        WHERE COALESCE(agent_id, '') = COALESCE($1, '') AND start_time = $2
+        "#,
+        trip.agent_id.as_ref() as Option<&AgentId>,
+        trip.start_time
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok((stored, false))
+}
+
+/// Marks the rows segmented into the trip, or into none
+async fn mark_segmented(
+    ids: &[ProcessedAgentId],
+    trip: Option<TripId>,
+    conn: &mut PgConnection,
+) -> sqlx::Result<()> {
+    let ids: Vec<i64> = ids.iter().map(|&id| id.into()).collect();
+    sqlx::query!(
+        r#"
+        UPDATE processed_agent_data
+        SET segmented = TRUE, trip_id = $2
+        WHERE id = ANY($1)
+        "#,
+        &ids,
+        trip as Option<TripId>
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+async fn select_trips(
+    page: NonZeroU32,
+    size: NonZeroU8,
+    pool: &PgPool,
+) -> sqlx::Result<Vec<TripWithId>> {
+    let offset = (page.get() - 1) * size.get() as u32;
+
+    let records = sqlx::query_as!(
+        TripDao,
+        r#"
+        SELECT
+            id as "id!: TripId",
+            agent_id as "agent_id: AgentId",
+            start_time,
+            end_time,
+            distance_m,
+            samples,
+            rough_percentage,
+            polyline as "polyline!: Json<Vec<Gps>>"
+        FROM trips
+        ORDER BY start_time DESC
+        LIMIT $1 OFFSET $2
+        "#,
+        size.get() as i32,
+        offset as i32
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records.into_iter().map(Into::into).collect())
+}
+
+async fn select_trip(id: TripId, pool: &PgPool) -> sqlx::Result<Option<TripWithId>> {
+    let record = sqlx::query_as!(
+        TripDao,
+        r#"
+        SELECT
+            id as "id!: TripId",
+            agent_id as "agent_id: AgentId",
+            start_time,
+            end_time,
+            distance_m,
+            samples,
+            rough_percentage,
+            polyline as "polyline!: Json<Vec<Gps>>"
+        FROM trips
+        WHERE id = $1
+        "#,
+        id as TripId
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(Into::into))
+}
+
+/// Selects up to `limit` live rows not matched to the road network, ordered by ID
+async fn select_unmatched_processed_agent_data(
+    limit: i64,
+    pool: &PgPool,
+) -> sqlx::Result<Vec<(ProcessedAgentId, Version, Gps)>> {
+    let records = sqlx::query!(
+        r#"
+        SELECT
+            id as "id: ProcessedAgentId",
+            version as "version: Version",
+            latitude as "latitude: Latitude",
+            longitude as "longitude: Longitude"
+        FROM processed_agent_data
+        WHERE NOT map_matched AND deleted_at IS NULL
+        ORDER BY id
+        LIMIT $1
+        "#,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| {
+            (
+                record.id,
+                record.version,
+                Gps::new(record.latitude, record.longitude),
+            )
+        })
+        .collect())
+}
+
+/// Stores the matches of the rows whose version didn't change. Returns the number of updated rows
+async fn update_way_matches(matches: &[MapMatched], pool: &PgPool) -> sqlx::Result<u64> {
+    let ids: Vec<i64> = matches.iter().map(|matched| matched.id.into()).collect();
+    let versions: Vec<i64> = matches
+        .iter()
+        .map(|matched| matched.version.into())
+        .collect();
+    let way_ids: Vec<Option<i64>> = matches
+        .iter()
+        .map(|matched| matched.way.map(|way| way.way_id.into()))
+        .collect();
+    let offsets: Vec<Option<f64>> = matches
+        .iter()
+        .map(|matched| matched.way.map(|way| way.offset_m))
+        .collect();
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE processed_agent_data AS data
+        SET way_id = matched.way_id, way_offset_m = matched.offset_m, map_matched = TRUE
+        FROM UNNEST($1::BIGINT[], $2::BIGINT[], $3::BIGINT[], $4::FLOAT[])
+            AS matched(id, version, way_id, offset_m)
+        WHERE data.id = matched.id AND data.version = matched.version
+        "#,
+        &ids,
+        &versions,
+        &way_ids as &[Option<i64>],
+        &offsets as &[Option<f64>]
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Counts the live rows matched to each way, and the rough ones among them
+async fn select_road_quality(
+    page: NonZeroU32,
+    size: NonZeroU8,
+    pool: &PgPool,
+) -> sqlx::Result<Vec<RoadQualityDao>> {
+    let offset = (page.get() - 1) * size.get() as u32;
+
+    sqlx::query_as!(
+        RoadQualityDao,
+        r#"
+        SELECT
+            way_id as "way_id!: WayId",
+            count(*) as "samples!",
+            count(*) FILTER (WHERE road_state = 'Rough') as "rough_samples!"
+        FROM processed_agent_data
+        WHERE way_id IS NOT NULL AND deleted_at IS NULL
+        GROUP BY way_id
+        ORDER BY
+            count(*) FILTER (WHERE road_state = 'Rough')::FLOAT / count(*) DESC,
+            count(*) DESC,
+            way_id
+        LIMIT $1 OFFSET $2
+        "#,
+        size.get() as i32,
+        offset as i32
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Takes a lock held until the end of the transaction, so that a single store instance
+/// applies the retention at a time. Returns `false` if another instance holds it
+async fn try_lock_retention(conn: &mut PgConnection) -> sqlx::Result<bool> {
+    let record = sqlx::query!(
+        r#"
+        SELECT pg_try_advisory_xact_lock(hashtext('processed_agent_data_retention')) as "locked!"
+        "#
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(record.locked)
+}
+
+/// Upserts the hourly per-tile aggregates of the live rows older than `cutoff`.
+/// Returns the number of upserted aggregates
+async fn aggregate_hourly_before(
+    cutoff: DateTime<Utc>,
+    tile_size_deg: f64,
+    conn: &mut PgConnection,
+) -> sqlx::Result<i64> {
+    let record = sqlx::query!(
+        r#"
+        WITH upserted AS (
+            INSERT INTO processed_agent_data_hourly AS hourly
+                (hour, tile_latitude, tile_longitude, samples, rough_samples, mean_z, min_z, max_z)
+            SELECT
+                date_trunc('hour', timestamp),
+                floor(latitude / $2) * $2,
+                floor(longitude / $2) * $2,
+                count(*),
+                count(*) FILTER (WHERE road_state = 'Rough'),
+                avg(z),
+                min(z),
+                max(z)
+            FROM processed_agent_data
+            WHERE timestamp < $1 AND deleted_at IS NULL
+            GROUP BY 1, 2, 3
+            ON CONFLICT (hour, tile_latitude, tile_longitude) DO UPDATE
+            SET samples = hourly.samples + EXCLUDED.samples,
+                rough_samples = hourly.rough_samples + EXCLUDED.rough_samples,
+                mean_z = (hourly.mean_z * hourly.samples + EXCLUDED.mean_z * EXCLUDED.samples)
+                    / (hourly.samples + EXCLUDED.samples),
+                min_z = least(hourly.min_z, EXCLUDED.min_z),
+                max_z = greatest(hourly.max_z, EXCLUDED.max_z)
+            RETURNING 1
+        )
+        SELECT count(*) as "count!" FROM upserted
+        "#,
+        cutoff,
+        tile_size_deg
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(record.count)
+}
+
+/// Counts the rows, deleted or not, older than `cutoff`
+async fn count_processed_agent_data_before(
+    cutoff: DateTime<Utc>,
+    conn: &mut PgConnection,
+) -> sqlx::Result<i64> {
+    let record = sqlx::query!(
+        r#"
+        SELECT count(*) as "count!"
+        FROM processed_agent_data
+        WHERE timestamp < $1
+        "#,
+        cutoff
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(record.count)
+}
+
+/// Permanently deletes the rows, deleted or not, older than `cutoff`, and frees their
+/// idempotency keys, including those of the rows of the dropped partitions
+async fn delete_processed_agent_data_before(
+    cutoff: DateTime<Utc>,
+    conn: &mut PgConnection,
+) -> sqlx::Result<u64> {
+    sqlx::query!(
+        r#"
+        DELETE FROM idempotency_keys
+        WHERE timestamp < $1
+        "#,
+        cutoff
+    )
+    .execute(&mut *conn)
+    .await?;
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM processed_agent_data
+        WHERE timestamp < $1
+        "#,
+        cutoff
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+pub async fn notify(channel: &str, payload: &str, pool: &PgPool) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+        SELECT pg_notify($1, $2)
+        "#,
+        channel,
+        payload
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+impl From<VersionedDao> for (ProcessedAgent, Version) {
+    fn from(dao: VersionedDao) -> Self {
+        let data = ProcessedAgentDao {
+            id: None,
+            road_state: dao.road_state,
+            x: dao.x,
+            y: dao.y,
+            z: dao.z,
+            latitude: dao.latitude,
+            longitude: dao.longitude,
+            timestamp: dao.timestamp,
+            idempotency_key: dao.idempotency_key,
+            agent_id: dao.agent_id,
+            motion_speed_mps: dao.motion_speed_mps,
+            motion_heading_deg: dao.motion_heading_deg,
+            motion_distance_m: dao.motion_distance_m,
+            confidence: dao.confidence,
+            deleted_at: dao.deleted_at,
+        };
+        (data.into(), dao.version)
+    }
+}
+
+impl From<VersionedDao> for Locked {
+    fn from(dao: VersionedDao) -> Self {
+        let deleted = dao.deleted_at.is_some();
+        let (data, version): (ProcessedAgent, Version) = dao.into();
+        Self {
+            data,
+            version,
+            deleted,
+        }
+    }
+}
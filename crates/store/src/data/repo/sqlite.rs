@@ -0,0 +1,819 @@
+use std::{
+    num::{NonZeroU32, NonZeroU8},
+    path::Path,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use iot_system::domain::{AgentId, IdempotencyKey, Latitude, Longitude, RoadState};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    types::Json,
+    Connection, SqliteConnection, SqlitePool,
+};
+
+use super::{
+    aggregate_hourly, check_version, replacement, Inserted, ProcessedAgentRepository,
+    RetentionOutcome,
+};
+use crate::{
+    data::{
+        geo::{haversine_m, MEAN_EARTH_RADIUS_M},
+        AuditAction, AuditEntry, Gps, MapMatched, NearbyDao, NearbyProcessedAgent, ProcessedAgent,
+        ProcessedAgentDao, ProcessedAgentId, ProcessedAgentWithId, RoadQualityDao, Trip, TripDao,
+        TripId, TripWithId, Version,
+    },
+    error::{AppError, AppResult},
+};
+
+/// Storage in a SQLite database file, for running the store without external services.
+///
+/// The queries are checked at runtime only, as the compile-time checks are done against Postgres
+#[derive(Debug, Clone)]
+pub struct SqliteRepository {
+    pool: SqlitePool,
+}
+
+/// Row of the processed agent data
+#[derive(sqlx::FromRow)]
+struct Row {
+    id: ProcessedAgentId,
+    road_state: RoadState,
+    x: f64,
+    y: f64,
+    z: f64,
+    latitude: Latitude,
+    longitude: Longitude,
+    timestamp: DateTime<Utc>,
+    idempotency_key: Option<IdempotencyKey>,
+    agent_id: Option<AgentId>,
+    motion_speed_mps: Option<f64>,
+    motion_heading_deg: Option<f64>,
+    motion_distance_m: Option<f64>,
+    confidence: Option<f64>,
+    version: Version,
+    deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(sqlx::FromRow)]
+struct AuditRow {
+    id: i64,
+    actor: String,
+    action: AuditAction,
+    before: Option<Json<serde_json::Value>>,
+    after: Option<Json<serde_json::Value>>,
+    timestamp: DateTime<Utc>,
+}
+
+const SELECT_ROW: &str = r#"
+    SELECT
+        id, road_state, x, y, z, latitude, longitude, timestamp, idempotency_key, agent_id,
+        motion_speed_mps, motion_heading_deg, motion_distance_m, confidence, version, deleted_at
+    FROM processed_agent_data
+"#;
+
+/// Half of the width of the first latitude band searched for the nearest data, about 1 km
+const NEAREST_BAND_DEG: f64 = 0.01;
+
+const SELECT_TRIP: &str = r#"
+    SELECT id, agent_id, start_time, end_time, distance_m, samples, rough_percentage, polyline
+    FROM trips
+"#;
+
+impl SqliteRepository {
+    /// Opens the database, creating it if missing, and applies the migrations
+    pub async fn connect(path: &Path) -> sqlx::Result<Self> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true);
+        // SQLite allows a single writer at a time. A single connection serializes
+        // the transactions, instead of failing the concurrent ones with `SQLITE_BUSY`
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await?;
+        sqlx::migrate!("./migrations-sqlite").run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl ProcessedAgentRepository for SqliteRepository {
+    async fn insert(&self, data: &ProcessedAgent) -> AppResult<Inserted> {
+        Ok(insert(data, &mut *self.pool.acquire().await?).await?)
+    }
+
+    async fn insert_all(&self, data: &[ProcessedAgent]) -> AppResult<Vec<Inserted>> {
+        let mut tx = self.pool.begin().await?;
+        let mut inserted = Vec::with_capacity(data.len());
+        for data in data {
+            inserted.push(insert(data, &mut tx).await?);
+        }
+        tx.commit().await?;
+
+        Ok(inserted)
+    }
+
+    async fn insert_each(&self, data: &[ProcessedAgent]) -> AppResult<Vec<AppResult<Inserted>>> {
+        let mut tx = self.pool.begin().await?;
+        let mut results = Vec::with_capacity(data.len());
+        for data in data {
+            let mut savepoint = (*tx).begin().await?;
+            let result = insert(data, &mut savepoint).await;
+            match result {
+                Ok(_) => savepoint.commit().await?,
+                Err(_) => savepoint.rollback().await?,
+            }
+            results.push(result.map_err(Into::into));
+        }
+        tx.commit().await?;
+
+        Ok(results)
+    }
+
+    async fn select(
+        &self,
+        id: ProcessedAgentId,
+        include_deleted: bool,
+    ) -> AppResult<Option<(ProcessedAgent, Version)>> {
+        let row = select_row(id, &mut *self.pool.acquire().await?).await?;
+        Ok(row
+            .filter(|row| include_deleted || row.deleted_at.is_none())
+            .map(|row| {
+                let version = row.version;
+                (ProcessedAgentDao::from(row).into(), version)
+            }))
+    }
+
+    async fn select_list(
+        &self,
+        page: NonZeroU32,
+        size: NonZeroU8,
+        include_deleted: bool,
+    ) -> AppResult<Vec<ProcessedAgentWithId>> {
+        let offset = (page.get() - 1) * size.get() as u32;
+        let rows = sqlx::query_as::<_, Row>(&format!(
+            "{SELECT_ROW} WHERE $3 OR deleted_at IS NULL ORDER BY timestamp DESC LIMIT $1 OFFSET $2"
+        ))
+        .bind(size.get())
+        .bind(offset)
+        .bind(include_deleted)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| ProcessedAgentDao::from(row).into())
+            .collect())
+    }
+
+    /// Scans the rows in the latitude band of the radius, computing the distances in the store
+    async fn select_near(
+        &self,
+        latitude: Latitude,
+        longitude: Longitude,
+        radius_m: Option<f64>,
+        limit: i64,
+    ) -> AppResult<Vec<NearbyProcessedAgent>> {
+        let limit = usize::try_from(limit).unwrap_or_default();
+        // The data is read by latitude bands, so that the index is used. Without a radius,
+        // the band widens until the nearest data found in it are nearer than its edges,
+        // as the data outside of it is farther than them
+        let mut band_deg = radius_m.map_or(NEAREST_BAND_DEG, |radius_m| {
+            (radius_m / MEAN_EARTH_RADIUS_M).to_degrees()
+        });
+        loop {
+            let rows = sqlx::query_as::<_, Row>(&format!(
+                "{SELECT_ROW} WHERE deleted_at IS NULL AND latitude BETWEEN $1 - $2 AND $1 + $2"
+            ))
+            .bind(latitude)
+            .bind(band_deg)
+            .fetch_all(&self.pool)
+            .await?;
+
+            let mut nearby: Vec<_> = rows
+                .into_iter()
+                .map(|row| {
+                    let distance_m =
+                        haversine_m((latitude, longitude), (row.latitude, row.longitude));
+                    (row, distance_m)
+                })
+                .filter(|&(_, distance_m)| radius_m.is_none_or(|radius_m| distance_m <= radius_m))
+                .collect();
+            nearby.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+            nearby.truncate(limit);
+
+            let band_m = band_deg.to_radians() * MEAN_EARTH_RADIUS_M;
+            let complete = radius_m.is_some()
+                || band_deg >= 180.0
+                || nearby.len() == limit
+                    && nearby
+                        .last()
+                        .is_none_or(|&(_, farthest_m)| farthest_m <= band_m);
+            if complete {
+                return Ok(nearby
+                    .into_iter()
+                    .map(|(row, distance_m)| NearbyDao::from((row, distance_m)).into())
+                    .collect());
+            }
+            band_deg *= 4.0;
+        }
+    }
+
+    async fn select_after(
+        &self,
+        after: ProcessedAgentId,
+        since: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> AppResult<Vec<(ProcessedAgentId, ProcessedAgent)>> {
+        let rows = sqlx::query_as::<_, Row>(&format!(
+            "{SELECT_ROW}
+            WHERE id > $1 AND ($2 IS NULL OR timestamp > $2) AND deleted_at IS NULL
+            ORDER BY id
+            LIMIT $3"
+        ))
+        .bind(after)
+        .bind(since)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.id, ProcessedAgentDao::from(row).into()))
+            .collect())
+    }
+
+    async fn update(
+        &self,
+        id: ProcessedAgentId,
+        data: &ProcessedAgent,
+        expected: Option<&[Version]>,
+        actor: &str,
+    ) -> AppResult<(Version, ProcessedAgent)> {
+        let mut tx = self.pool.begin().await?;
+        let row = select_row(id, &mut tx)
+            .await?
+            .filter(|row| row.deleted_at.is_none())
+            .ok_or(AppError::NotFound("Processed agent data"))?;
+        check_version(row.version, expected)?;
+        let before = ProcessedAgent::from(ProcessedAgentDao::from(row));
+        let after = replacement(&before, data);
+        let data = &after;
+
+        let version = sqlx::query_scalar(
+            r#"
+            UPDATE processed_agent_data
+            SET road_state = $1, x = $2, y = $3, z = $4, latitude = $5, longitude = $6, timestamp = $7,
+                motion_speed_mps = $9, motion_heading_deg = $10, motion_distance_m = $11, confidence = $12,
+                version = version + 1, way_id = NULL, way_offset_m = NULL, map_matched = FALSE,
+                -- The moved data is segmented again, the values on the right are the stored ones
+                trip_id = CASE WHEN timestamp = $7 AND latitude = $5 AND longitude = $6 THEN trip_id END,
+                segmented = segmented AND timestamp = $7 AND latitude = $5 AND longitude = $6
+            WHERE id = $8
+            RETURNING version
+            "#,
+        )
+        .bind(data.road_state())
+        .bind(data.agent_data().accelerometer().x())
+        .bind(data.agent_data().accelerometer().y())
+        .bind(data.agent_data().accelerometer().z())
+        .bind(data.agent_data().gps().latitude())
+        .bind(data.agent_data().gps().longitude())
+        .bind(data.agent_data().timestamp())
+        .bind(id)
+        .bind(data.motion().map(|motion| motion.speed_mps()))
+        .bind(data.motion().map(|motion| motion.heading_deg()))
+        .bind(data.motion().map(|motion| motion.distance_m()))
+        .bind(data.confidence())
+        .fetch_one(&mut *tx)
+        .await?;
+        insert_audit_entry(
+            id,
+            actor,
+            AuditAction::Update,
+            Some(serde_json::to_value(&before)?),
+            Some(serde_json::to_value(data)?),
+            &mut tx,
+        )
+        .await?;
+        tx.commit().await?;
+
+        Ok((version, after))
+    }
+
+    async fn delete(
+        &self,
+        id: ProcessedAgentId,
+        expected: Option<&[Version]>,
+        actor: &str,
+    ) -> AppResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let Some(row) = select_row(id, &mut tx)
+            .await?
+            .filter(|row| row.deleted_at.is_none())
+        else {
+            return match expected {
+                None => Ok(false),
+                Some(_) => Err(AppError::PreconditionFailed),
+            };
+        };
+        check_version(row.version, expected)?;
+
+        sqlx::query(
+            r#"
+            UPDATE processed_agent_data
+            SET deleted_at = $1, version = version + 1
+            WHERE id = $2
+            "#,
+        )
+        .bind(Utc::now())
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        let before = ProcessedAgent::from(ProcessedAgentDao::from(row));
+        insert_audit_entry(
+            id,
+            actor,
+            AuditAction::Delete,
+            Some(serde_json::to_value(&before)?),
+            None,
+            &mut tx,
+        )
+        .await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    async fn restore(
+        &self,
+        id: ProcessedAgentId,
+        expected: Option<&[Version]>,
+        actor: &str,
+    ) -> AppResult<(Version, Option<ProcessedAgent>)> {
+        let mut tx = self.pool.begin().await?;
+        let row = select_row(id, &mut tx)
+            .await?
+            .ok_or(AppError::NotFound("Processed agent data"))?;
+        check_version(row.version, expected)?;
+        if row.deleted_at.is_none() {
+            return Ok((row.version, None));
+        }
+
+        let version = sqlx::query_scalar(
+            r#"
+            UPDATE processed_agent_data
+            SET deleted_at = NULL, version = version + 1
+            WHERE id = $1
+            RETURNING version
+            "#,
+        )
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        let data = ProcessedAgent::from(ProcessedAgentDao::from(row));
+        insert_audit_entry(
+            id,
+            actor,
+            AuditAction::Restore,
+            None,
+            Some(serde_json::to_value(&data)?),
+            &mut tx,
+        )
+        .await?;
+        tx.commit().await?;
+
+        Ok((version, Some(data)))
+    }
+
+    async fn purge_deleted(&self, cutoff: DateTime<Utc>) -> AppResult<u64> {
+        let result = sqlx::query("DELETE FROM processed_agent_data WHERE deleted_at < $1")
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn select_audit_entries(&self, id: ProcessedAgentId) -> AppResult<Vec<AuditEntry>> {
+        let rows = sqlx::query_as::<_, AuditRow>(
+            r#"
+            SELECT id, actor, action, before, after, timestamp
+            FROM audit_log
+            WHERE processed_agent_data_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    async fn select_unsegmented_agents(&self) -> AppResult<Vec<Option<AgentId>>> {
+        Ok(sqlx::query_scalar(
+            "SELECT DISTINCT agent_id FROM processed_agent_data
+            WHERE NOT segmented AND deleted_at IS NULL",
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn select_unsegmented(
+        &self,
+        agent_id: Option<&AgentId>,
+        after: Option<(DateTime<Utc>, ProcessedAgentId)>,
+        limit: i64,
+    ) -> AppResult<Vec<(ProcessedAgentId, ProcessedAgent)>> {
+        let (after_timestamp, after_id) = after.unzip();
+        let rows = sqlx::query_as::<_, Row>(&format!(
+            "{SELECT_ROW}
+            WHERE agent_id IS $1 AND NOT segmented AND deleted_at IS NULL
+                AND ($2 IS NULL OR (timestamp, id) > ($2, $3))
+            ORDER BY timestamp, id
+            LIMIT $4"
+        ))
+        .bind(agent_id)
+        .bind(after_timestamp)
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.id, ProcessedAgentDao::from(row).into()))
+            .collect())
+    }
+
+    async fn insert_trip(&self, trip: &Trip, data: &[ProcessedAgentId]) -> AppResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let inserted: Option<TripId> = sqlx::query_scalar(
+            r#"
+            INSERT INTO trips (agent_id, start_time, end_time, distance_m, samples, rough_percentage, polyline)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT DO NOTHING
+            RETURNING id
+            "#,
+        )
+        .bind(&trip.agent_id)
+        .bind(trip.start_time)
+        .bind(trip.end_time)
+        .bind(trip.distance_m)
+        .bind(trip.samples)
+        .bind(trip.rough_percentage)
+        .bind(Json(&trip.polyline))
+        .fetch_optional(&mut *tx)
+        .await?;
+        let id = match inserted {
+            Some(id) => id,
+            None => {
+                sqlx::query_scalar("SELECT id FROM trips WHERE agent_id IS $1 AND start_time = $2")
+                    .bind(&trip.agent_id)
+                    .bind(trip.start_time)
+                    .fetch_one(&mut *tx)
+                    .await?
+            }
+        };
+        mark_segmented(data, Some(id), &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(inserted.is_some())
+    }
+
+    async fn mark_segmented(&self, data: &[ProcessedAgentId]) -> AppResult<()> {
+        let mut tx = self.pool.begin().await?;
+        mark_segmented(data, None, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn select_trips(&self, page: NonZeroU32, size: NonZeroU8) -> AppResult<Vec<TripWithId>> {
+        let offset = (page.get() - 1) * size.get() as u32;
+        let rows = sqlx::query_as::<_, TripDao>(&format!(
+            "{SELECT_TRIP} ORDER BY start_time DESC LIMIT $1 OFFSET $2"
+        ))
+        .bind(size.get())
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    async fn select_trip(&self, id: TripId) -> AppResult<Option<TripWithId>> {
+        let row = sqlx::query_as::<_, TripDao>(&format!("{SELECT_TRIP} WHERE id = $1"))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(Into::into))
+    }
+
+    async fn select_trip_data(&self, id: TripId) -> AppResult<Vec<ProcessedAgentWithId>> {
+        let rows = sqlx::query_as::<_, Row>(&format!(
+            "{SELECT_ROW}
+            WHERE trip_id = $1 AND deleted_at IS NULL
+            ORDER BY timestamp, id"
+        ))
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| ProcessedAgentDao::from(row).into())
+            .collect())
+    }
+
+    async fn select_unmatched(
+        &self,
+        limit: i64,
+    ) -> AppResult<Vec<(ProcessedAgentId, Version, Gps)>> {
+        let rows = sqlx::query_as::<_, Row>(&format!(
+            "{SELECT_ROW}
+            WHERE NOT map_matched AND deleted_at IS NULL
+            ORDER BY id
+            LIMIT $1"
+        ))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.id, row.version, Gps::new(row.latitude, row.longitude)))
+            .collect())
+    }
+
+    async fn update_way_matches(&self, matches: &[MapMatched]) -> AppResult<u64> {
+        let mut tx = self.pool.begin().await?;
+        let mut updated = 0;
+        for matched in matches {
+            let result = sqlx::query(
+                r#"
+                UPDATE processed_agent_data
+                SET way_id = $1, way_offset_m = $2, map_matched = TRUE
+                WHERE id = $3 AND version = $4
+                "#,
+            )
+            .bind(matched.way.map(|way| way.way_id))
+            .bind(matched.way.map(|way| way.offset_m))
+            .bind(matched.id)
+            .bind(matched.version)
+            .execute(&mut *tx)
+            .await?;
+            updated += result.rows_affected();
+        }
+        tx.commit().await?;
+
+        Ok(updated)
+    }
+
+    async fn select_road_quality(
+        &self,
+        page: NonZeroU32,
+        size: NonZeroU8,
+    ) -> AppResult<Vec<RoadQualityDao>> {
+        let offset = (page.get() - 1) * size.get() as u32;
+        Ok(sqlx::query_as::<_, RoadQualityDao>(
+            r#"
+            SELECT
+                way_id,
+                count(*) as samples,
+                count(*) FILTER (WHERE road_state = 'Rough') as rough_samples
+            FROM processed_agent_data
+            WHERE way_id IS NOT NULL AND deleted_at IS NULL
+            GROUP BY way_id
+            ORDER BY CAST(rough_samples AS REAL) / samples DESC, samples DESC, way_id
+            LIMIT $1 OFFSET $2
+            "#,
+        )
+        .bind(size.get())
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Aggregates the expired rows in the store, as SQLite lacks the math functions
+    async fn apply_retention(
+        &self,
+        cutoff: DateTime<Utc>,
+        tile_size_deg: f64,
+        dry_run: bool,
+    ) -> AppResult<RetentionOutcome> {
+        let mut tx = self.pool.begin().await?;
+        let live = sqlx::query_as::<_, Row>(&format!(
+            "{SELECT_ROW} WHERE timestamp < $1 AND deleted_at IS NULL"
+        ))
+        .bind(cutoff)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| ProcessedAgent::from(ProcessedAgentDao::from(row)))
+        .collect::<Vec<_>>();
+
+        let aggregates = aggregate_hourly(&live, tile_size_deg);
+        for (&(hour, tile_latitude, tile_longitude), aggregate) in &aggregates {
+            sqlx::query(
+                r#"
+                INSERT INTO processed_agent_data_hourly
+                    (hour, tile_latitude, tile_longitude, samples, rough_samples, mean_z, min_z, max_z)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                ON CONFLICT (hour, tile_latitude, tile_longitude) DO UPDATE
+                SET samples = samples + excluded.samples,
+                    rough_samples = rough_samples + excluded.rough_samples,
+                    mean_z = (mean_z * samples + excluded.mean_z * excluded.samples)
+                        / (samples + excluded.samples),
+                    min_z = min(min_z, excluded.min_z),
+                    max_z = max(max_z, excluded.max_z)
+                "#,
+            )
+            .bind(hour)
+            .bind(tile_latitude as f64 * tile_size_deg)
+            .bind(tile_longitude as f64 * tile_size_deg)
+            .bind(aggregate.samples)
+            .bind(aggregate.rough_samples)
+            .bind(aggregate.mean_z)
+            .bind(aggregate.min_z)
+            .bind(aggregate.max_z)
+            .execute(&mut *tx)
+            .await?;
+        }
+        let expired = sqlx::query("DELETE FROM processed_agent_data WHERE timestamp < $1")
+            .bind(cutoff)
+            .execute(&mut *tx)
+            .await?;
+        if dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+
+        Ok(RetentionOutcome {
+            applied: true,
+            expired_rows: expired.rows_affected() as i64,
+            aggregates: aggregates.len() as i64,
+            dropped_partitions: Vec::new(),
+        })
+    }
+}
+
+/// Inserts the row unless a row with the same idempotency key exists
+async fn insert(agent: &ProcessedAgent, conn: &mut SqliteConnection) -> sqlx::Result<Inserted> {
+    let idempotency_key = agent
+        .agent_data()
+        .idempotency_key()
+        .map(IdempotencyKey::as_str);
+    let id = sqlx::query_scalar(
+        r#"
+        INSERT INTO processed_agent_data (
+            road_state, x, y, z, latitude, longitude, timestamp, idempotency_key,
+            motion_speed_mps, motion_heading_deg, motion_distance_m, confidence, agent_id
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+        ON CONFLICT (idempotency_key) DO NOTHING
+        RETURNING id
+        "#,
+    )
+    .bind(agent.road_state())
+    .bind(agent.agent_data().accelerometer().x())
+    .bind(agent.agent_data().accelerometer().y())
+    .bind(agent.agent_data().accelerometer().z())
+    .bind(agent.agent_data().gps().latitude())
+    .bind(agent.agent_data().gps().longitude())
+    .bind(agent.agent_data().timestamp())
+    .bind(idempotency_key)
+    .bind(agent.motion().map(|motion| motion.speed_mps()))
+    .bind(agent.motion().map(|motion| motion.heading_deg()))
+    .bind(agent.motion().map(|motion| motion.distance_m()))
+    .bind(agent.confidence())
+    .bind(agent.agent_data().agent_id())
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(id) = id {
+        return Ok(Inserted { id, created: true });
+    }
+
+    let id = sqlx::query_scalar("SELECT id FROM processed_agent_data WHERE idempotency_key = $1")
+        .bind(idempotency_key)
+        .fetch_one(conn)
+        .await?;
+
+    Ok(Inserted { id, created: false })
+}
+
+/// Selects the row, deleted or not
+async fn select_row(
+    id: ProcessedAgentId,
+    conn: &mut SqliteConnection,
+) -> sqlx::Result<Option<Row>> {
+    sqlx::query_as::<_, Row>(&format!("{SELECT_ROW} WHERE id = $1"))
+        .bind(id)
+        .fetch_optional(conn)
+        .await
+}
+
+async fn insert_audit_entry(
+    id: ProcessedAgentId,
+    actor: &str,
+    action: AuditAction,
+    before: Option<serde_json::Value>,
+    after: Option<serde_json::Value>,
+    conn: &mut SqliteConnection,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO audit_log (processed_agent_data_id, actor, action, before, after, timestamp)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+    )
+    .bind(id)
+    .bind(actor)
+    .bind(action)
+    .bind(before.map(Json))
+    .bind(after.map(Json))
+    .bind(Utc::now())
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Marks the rows segmented into the trip, or into none
+async fn mark_segmented(
+    ids: &[ProcessedAgentId],
+    trip: Option<TripId>,
+    conn: &mut SqliteConnection,
+) -> sqlx::Result<()> {
+    for &id in ids {
+        sqlx::query("UPDATE processed_agent_data SET segmented = TRUE, trip_id = $2 WHERE id = $1")
+            .bind(id)
+            .bind(trip)
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    Ok(())
+}
+
+impl From<Row> for ProcessedAgentDao {
+    fn from(row: Row) -> Self {
+        Self {
+            id: Some(row.id),
+            road_state: row.road_state,
+            x: row.x,
+            y: row.y,
+            z: row.z,
+            latitude: row.latitude,
+            longitude: row.longitude,
+            timestamp: row.timestamp,
+            idempotency_key: row.idempotency_key,
+            agent_id: row.agent_id,
+            motion_speed_mps: row.motion_speed_mps,
+            motion_heading_deg: row.motion_heading_deg,
+            motion_distance_m: row.motion_distance_m,
+            confidence: row.confidence,
+            deleted_at: row.deleted_at,
+        }
+    }
+}
+
+impl From<(Row, f64)> for NearbyDao {
+    fn from((row, distance_m): (Row, f64)) -> Self {
+        Self {
+            id: row.id,
+            road_state: row.road_state,
+            x: row.x,
+            y: row.y,
+            z: row.z,
+            latitude: row.latitude,
+            longitude: row.longitude,
+            timestamp: row.timestamp,
+            idempotency_key: row.idempotency_key,
+            agent_id: row.agent_id,
+            motion_speed_mps: row.motion_speed_mps,
+            motion_heading_deg: row.motion_heading_deg,
+            motion_distance_m: row.motion_distance_m,
+            confidence: row.confidence,
+            distance_m,
+        }
+    }
+}
+
+impl From<AuditRow> for AuditEntry {
+    fn from(row: AuditRow) -> Self {
+        Self {
+            id: row.id,
+            actor: row.actor,
+            action: row.action,
+            before: row.before.map(|Json(before)| before),
+            after: row.after.map(|Json(after)| after),
+            timestamp: row.timestamp,
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use std::path::Path;
+
+use serde_json::json;
+
+use super::{MemoryRepository, ProcessedAgentRepository, SqliteRepository};
+use crate::{
+    data::{AuditAction, ProcessedAgent, Version},
+    error::AppError,
+};
+
+/// Runs each of the tests against every repository not needing an external service
+macro_rules! repository_tests {
+    ($($test:ident),* $(,)?) => {
+        mod memory {
+            $(
+                #[tokio::test]
+                async fn $test() {
+                    super::$test(&super::MemoryRepository::default()).await;
+                }
+            )*
+        }
+
+        mod sqlite {
+            $(
+                #[tokio::test]
+                async fn $test() {
+                    super::$test(&super::sqlite().await).await;
+                }
+            )*
+        }
+    };
+}
+
+repository_tests!(
+    insert_returns_the_stored_data_for_a_repeated_idempotency_key,
+    insert_all_deduplicates_within_the_batch,
+    update_and_delete_check_the_expected_version,
+    update_keeps_the_idempotency_key_and_the_agent,
+    update_segments_the_moved_data_again,
+    delete_hides_the_data_until_restored,
+    select_near_finds_the_nearest_data_without_a_radius,
+);
+
+async fn sqlite() -> SqliteRepository {
+    // A single connection is kept, so the in-memory database lives as long as the repository
+    SqliteRepository::connect(Path::new(":memory:"))
+        .await
+        .expect("The in-memory database must open")
+}
+
+fn data(latitude: f64, longitude: f64, idempotency_key: Option<&str>) -> ProcessedAgent {
+    serde_json::from_value(json!({
+        "road_state": "SMOOTH",
+        "accelerometer": { "x": 0.0, "y": 0.0, "z": 1.0 },
+        "gps": { "latitude": latitude, "longitude": longitude },
+        "timestamp": "2024-06-01T12:00:00Z",
+        "idempotency_key": idempotency_key,
+    }))
+    .expect("The data must be valid")
+}
+
+async fn insert_returns_the_stored_data_for_a_repeated_idempotency_key(
+    repo: &dyn ProcessedAgentRepository,
+) {
+    let first = repo.insert(&data(50.45, 30.52, Some("a:1"))).await.unwrap();
+    let repeated = repo.insert(&data(50.46, 30.53, Some("a:1"))).await.unwrap();
+    let other = repo.insert(&data(50.45, 30.52, Some("a:2"))).await.unwrap();
+    let unkeyed = repo.insert(&data(50.45, 30.52, None)).await.unwrap();
+
+    assert!(first.created);
+    assert!(!repeated.created);
+    assert_eq!(repeated.id, first.id);
+    assert!(other.created && unkeyed.created);
+    assert_ne!(other.id, first.id);
+
+    // The repeated data is not stored
+    let (stored, version) = repo.select(first.id, false).await.unwrap().unwrap();
+    assert_eq!(f64::from(stored.agent_data().gps().latitude()), 50.45);
+    assert_eq!(version, Version::from(1));
+}
+
+async fn insert_all_deduplicates_within_the_batch(repo: &dyn ProcessedAgentRepository) {
+    let inserted = repo
+        .insert_all(&[
+            data(50.45, 30.52, Some("b:1")),
+            data(50.45, 30.52, Some("b:1")),
+            data(50.45, 30.52, Some("b:2")),
+        ])
+        .await
+        .unwrap();
+
+    let created: Vec<_> = inserted.iter().map(|inserted| inserted.created).collect();
+    assert_eq!(created, [true, false, true]);
+    assert_eq!(inserted[0].id, inserted[1].id);
+}
+
+async fn update_and_delete_check_the_expected_version(repo: &dyn ProcessedAgentRepository) {
+    let id = repo.insert(&data(50.45, 30.52, None)).await.unwrap().id;
+
+    let stale = repo
+        .update(
+            id,
+            &data(50.46, 30.52, None),
+            Some(&[Version::from(2)]),
+            "test",
+        )
+        .await;
+    assert!(matches!(stale, Err(AppError::PreconditionFailed)));
+    let (version, _) = repo
+        .update(
+            id,
+            &data(50.46, 30.52, None),
+            Some(&[Version::from(1)]),
+            "test",
+        )
+        .await
+        .unwrap();
+    assert_eq!(version, Version::from(2));
+    let (version, _) = repo
+        .update(id, &data(50.47, 30.52, None), None, "test")
+        .await
+        .unwrap();
+    assert_eq!(version, Version::from(3));
+
+    let (stored, _) = repo.select(id, false).await.unwrap().unwrap();
+    assert_eq!(f64::from(stored.agent_data().gps().latitude()), 50.47);
+
+    let stale = repo.delete(id, Some(&[Version::from(2)]), "test").await;
+    assert!(matches!(stale, Err(AppError::PreconditionFailed)));
+    assert!(repo
+        .delete(id, Some(&[Version::from(3)]), "test")
+        .await
+        .unwrap());
+    assert!(!repo.delete(id, None, "test").await.unwrap());
+    let deleted = repo
+        .update(id, &data(50.48, 30.52, None), None, "test")
+        .await;
+    assert!(matches!(deleted, Err(AppError::NotFound(_))));
+
+    let actions: Vec<_> = repo
+        .select_audit_entries(id)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.action)
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            AuditAction::Update,
+            AuditAction::Update,
+            AuditAction::Delete
+        ]
+    );
+}
+
+async fn update_keeps_the_idempotency_key_and_the_agent(repo: &dyn ProcessedAgentRepository) {
+    let mut original = serde_json::to_value(data(50.45, 30.52, Some("c:1"))).unwrap();
+    original["agent_id"] = json!("AA1234BB");
+    let original: ProcessedAgent = serde_json::from_value(original).unwrap();
+    let id = repo.insert(&original).await.unwrap().id;
+
+    let (_, updated) = repo
+        .update(id, &data(50.46, 30.52, None), None, "test")
+        .await
+        .unwrap();
+
+    let (stored, _) = repo.select(id, false).await.unwrap().unwrap();
+    for data in [&updated, &stored] {
+        assert_eq!(f64::from(data.agent_data().gps().latitude()), 50.46);
+        assert_eq!(
+            data.agent_data().idempotency_key(),
+            original.agent_data().idempotency_key()
+        );
+        assert_eq!(
+            data.agent_data().agent_id(),
+            original.agent_data().agent_id()
+        );
+    }
+}
+
+async fn update_segments_the_moved_data_again(repo: &dyn ProcessedAgentRepository) {
+    let kept = repo.insert(&data(50.45, 30.52, None)).await.unwrap().id;
+    let moved = repo.insert(&data(50.46, 30.52, None)).await.unwrap().id;
+    repo.mark_segmented(&[kept, moved]).await.unwrap();
+
+    repo.update(kept, &data(50.45, 30.52, None), None, "test")
+        .await
+        .unwrap();
+    repo.update(moved, &data(50.47, 30.52, None), None, "test")
+        .await
+        .unwrap();
+
+    let unsegmented = repo.select_unsegmented(None, None, 10).await.unwrap();
+    let ids: Vec<_> = unsegmented.iter().map(|&(id, _)| id).collect();
+    assert_eq!(ids, [moved]);
+}
+
+async fn delete_hides_the_data_until_restored(repo: &dyn ProcessedAgentRepository) {
+    let id = repo.insert(&data(50.45, 30.52, None)).await.unwrap().id;
+    assert!(repo.delete(id, None, "test").await.unwrap());
+
+    assert!(repo.select(id, false).await.unwrap().is_none());
+    assert!(repo.select(id, true).await.unwrap().is_some());
+    assert!(repo
+        .select_near(
+            50.45.try_into().unwrap(),
+            30.52.try_into().unwrap(),
+            None,
+            10
+        )
+        .await
+        .unwrap()
+        .is_empty());
+
+    let (version, restored) = repo.restore(id, None, "test").await.unwrap();
+    assert!(restored.is_some());
+    let (_, stored_version) = repo.select(id, false).await.unwrap().unwrap();
+    assert_eq!(stored_version, version);
+
+    // Restoring the live data changes nothing
+    let (_, restored) = repo.restore(id, None, "test").await.unwrap();
+    assert!(restored.is_none());
+}
+
+async fn select_near_finds_the_nearest_data_without_a_radius(repo: &dyn ProcessedAgentRepository) {
+    // About 100 m, 5 km and 50 km to the north of the point, and one on the other side of the globe
+    for latitude in [50.451, 50.495, 50.9, -50.45] {
+        repo.insert(&data(latitude, 30.52, None)).await.unwrap();
+    }
+    let (latitude, longitude) = (50.45.try_into().unwrap(), 30.52.try_into().unwrap());
+
+    let distances = |nearby: Vec<_>| -> Vec<f64> {
+        nearby
+            .iter()
+            .map(|nearby| {
+                serde_json::to_value(nearby).unwrap()["distance_m"]
+                    .as_f64()
+                    .unwrap()
+            })
+            .collect()
+    };
+    let nearest = distances(
+        repo.select_near(latitude, longitude, None, 2)
+            .await
+            .unwrap(),
+    );
+    assert_eq!(nearest.len(), 2);
+    assert!((100.0..120.0).contains(&nearest[0]));
+    assert!((4_900.0..5_100.0).contains(&nearest[1]));
+
+    let all = distances(
+        repo.select_near(latitude, longitude, None, 10)
+            .await
+            .unwrap(),
+    );
+    assert_eq!(all.len(), 4);
+    assert!(all.windows(2).all(|pair| pair[0] <= pair[1]));
+
+    let within = distances(
+        repo.select_near(latitude, longitude, Some(1_000.0), 10)
+            .await
+            .unwrap(),
+    );
+    assert_eq!(within.len(), 1);
+}
@@ -0,0 +1,98 @@
+use std::fmt::{Display, Formatter};
+
+use derive_more::Into;
+use serde::{Deserialize, Serialize};
+use utoipa::{ToResponse, ToSchema};
+
+use super::{ProcessedAgentId, Version};
+
+/// ID of the OpenStreetMap way
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Ord,
+    PartialOrd,
+    Eq,
+    PartialEq,
+    Hash,
+    Deserialize,
+    Serialize,
+    sqlx::Type,
+    Into,
+)]
+#[repr(transparent)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct WayId(i64);
+
+/// Position of the data on the road network
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WayMatch {
+    pub way_id: WayId,
+    /// Distance along the way from its first node to the matched position, in meters
+    pub offset_m: f64,
+}
+
+/// Outcome of matching the data to the road network
+#[derive(Debug, Clone, Copy)]
+pub struct MapMatched {
+    pub id: ProcessedAgentId,
+    /// Version of the data that was matched. Data changed since is matched again
+    pub version: Version,
+    /// `None` if no way is near the data
+    pub way: Option<WayMatch>,
+}
+
+/// Quality of the road along a way, summarized from the live data matched to it
+#[derive(Debug, Serialize, ToResponse, ToSchema)]
+pub struct RoadQuality {
+    #[schema(value_type = i64)]
+    way_id: WayId,
+    /// Name of the way in the road network
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    samples: i64,
+    rough_samples: i64,
+    /// Share of the samples on rough road, in percent
+    rough_percentage: f64,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct RoadQualityDao {
+    pub(super) way_id: WayId,
+    pub(super) samples: i64,
+    pub(super) rough_samples: i64,
+}
+
+impl RoadQuality {
+    pub fn new(dao: RoadQualityDao, name: Option<String>) -> Self {
+        Self {
+            way_id: dao.way_id,
+            name,
+            samples: dao.samples,
+            rough_samples: dao.rough_samples,
+            rough_percentage: dao.rough_samples as f64 * 100.0 / dao.samples as f64,
+        }
+    }
+}
+
+impl RoadQualityDao {
+    pub fn way_id(&self) -> WayId {
+        self.way_id
+    }
+}
+
+impl Display for WayId {
+    #[inline(always)]
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl From<i64> for WayId {
+    #[inline(always)]
+    fn from(value: i64) -> Self {
+        Self(value)
+    }
+}
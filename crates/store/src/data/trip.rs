@@ -0,0 +1,265 @@
+use std::fmt::{Display, Formatter};
+
+use chrono::{DateTime, Utc};
+use derive_more::{Constructor, Into};
+use iot_system::domain::{AgentId, RoadState};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use utoipa::{IntoParams, ToResponse, ToSchema};
+
+use super::{geo, Gps, ProcessedAgent, ProcessedAgentId};
+use crate::config::Trips;
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Ord,
+    PartialOrd,
+    Eq,
+    PartialEq,
+    Hash,
+    Deserialize,
+    Serialize,
+    sqlx::Type,
+    IntoParams,
+    Into,
+)]
+#[repr(transparent)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+#[into_params(names("id"))]
+/// ID of the trip to read.
+pub struct TripId(i64);
+
+/// Drive of the vehicle, from the moment it starts moving until it stops,
+/// or until its data stops coming
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Trip {
+    /// Agent of the vehicle. Absent for the data of the agents not telling their ID,
+    /// which is segmented as the data of a single vehicle
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub(super) agent_id: Option<AgentId>,
+    pub(super) start_time: DateTime<Utc>,
+    pub(super) end_time: DateTime<Utc>,
+    /// Distance travelled, summed over the consecutive samples, in meters
+    pub(super) distance_m: f64,
+    pub(super) samples: i64,
+    /// Share of the samples on rough road, in percent
+    pub(super) rough_percentage: f64,
+    /// Route of the trip, simplified to the positions deviating from a straight line
+    pub(super) polyline: Vec<Gps>,
+}
+
+#[derive(Debug, Clone, Serialize, ToResponse, ToSchema, Constructor)]
+pub struct TripWithId {
+    #[schema(minimum = 1, value_type = i64)]
+    id: TripId,
+    #[serde(flatten)]
+    #[schema(inline)]
+    trip: Trip,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct TripDao {
+    pub(super) id: TripId,
+    pub(super) agent_id: Option<AgentId>,
+    pub(super) start_time: DateTime<Utc>,
+    pub(super) end_time: DateTime<Utc>,
+    pub(super) distance_m: f64,
+    pub(super) samples: i64,
+    pub(super) rough_percentage: f64,
+    pub(super) polyline: Json<Vec<Gps>>,
+}
+
+/// Splits the data of an agent, pushed in the order of the timestamps, into trips
+#[derive(Debug)]
+pub struct Segmenter<'a> {
+    config: &'a Trips,
+    agent_id: Option<AgentId>,
+    /// Data of the trip in progress
+    data: Vec<(ProcessedAgentId, ProcessedAgent)>,
+    /// Index of the first of the latest data staying within the stop radius of it
+    stay_start: usize,
+    /// Where the vehicle stopped, until it moves out of the stop radius
+    stopped_at: Option<Gps>,
+    segmented: Segmented,
+}
+
+/// Data segmented by [`Segmenter`]. The data of the trip in progress is in neither of the fields
+#[derive(Debug, Default)]
+pub struct Segmented {
+    /// Ended trips, with the IDs of their data
+    pub trips: Vec<(Trip, Vec<ProcessedAgentId>)>,
+    /// IDs of the data in no trip, like the data of a parked vehicle
+    pub dropped: Vec<ProcessedAgentId>,
+}
+
+impl Trip {
+    /// Summarizes the data of the trip. Returns `None` if the data never leaves
+    /// the stop radius, as the position of a parked vehicle drifts too
+    fn of(agent_id: Option<AgentId>, data: &[ProcessedAgent], config: &Trips) -> Option<Self> {
+        let (first, last) = (data.first()?, data.last()?);
+        let positions: Vec<_> = data.iter().map(|data| data.agent_data().gps()).collect();
+        let start = first.agent_data().gps();
+        if positions
+            .iter()
+            .all(|&position| geo::distance_m(start, position) <= config.stop_radius_m())
+        {
+            return None;
+        }
+
+        let rough_samples = data
+            .iter()
+            .filter(|data| data.road_state() == RoadState::Rough)
+            .count();
+        Some(Self {
+            agent_id,
+            start_time: first.agent_data().timestamp(),
+            end_time: last.agent_data().timestamp(),
+            distance_m: positions
+                .windows(2)
+                .map(|pair| geo::distance_m(pair[0], pair[1]))
+                .sum(),
+            samples: data.len() as i64,
+            rough_percentage: rough_samples as f64 * 100.0 / data.len() as f64,
+            polyline: geo::simplify(&positions, config.simplify_tolerance_m()),
+        })
+    }
+}
+
+impl<'a> Segmenter<'a> {
+    pub fn new(config: &'a Trips, agent_id: Option<AgentId>) -> Self {
+        Self {
+            config,
+            agent_id,
+            data: Vec::new(),
+            stay_start: 0,
+            stopped_at: None,
+            segmented: Segmented::default(),
+        }
+    }
+
+    /// Adds the data following the previously pushed one
+    pub fn push(&mut self, id: ProcessedAgentId, data: ProcessedAgent) {
+        let timestamp = data.agent_data().timestamp();
+        let position = data.agent_data().gps();
+
+        if self.data.last().is_some_and(|(_, last)| {
+            timestamp - last.agent_data().timestamp() > self.config.max_gap()
+        }) {
+            self.end(self.data.len());
+            self.stopped_at = None;
+        }
+        if let Some(stop) = self.stopped_at {
+            if self.within_stop(stop, position) {
+                // The next trip starts from the last position of the stopped vehicle
+                let parked = self.data.drain(..).map(|(id, _)| id);
+                self.segmented.dropped.extend(parked);
+                self.data.push((id, data));
+                return;
+            }
+            self.stopped_at = None;
+        }
+
+        if !self
+            .data
+            .get(self.stay_start)
+            .is_some_and(|(_, stay)| self.within_stop(stay.agent_data().gps(), position))
+        {
+            self.stay_start = self.data.len();
+        }
+        self.data.push((id, data));
+
+        let stay = self.data[self.stay_start].1.agent_data();
+        if timestamp - stay.timestamp() >= self.config.min_stop() {
+            // The trip ends where the vehicle stopped
+            let stop = stay.gps();
+            let latest = self.data.pop().expect("The data was just pushed");
+            self.end(self.stay_start + 1);
+            self.data.push(latest);
+            self.stopped_at = Some(stop);
+        }
+    }
+
+    /// Ends the trip in progress if no data came for longer than the maximum gap before `now`.
+    /// Returns the segmented data
+    pub fn finish(mut self, now: DateTime<Utc>) -> Segmented {
+        if self
+            .data
+            .last()
+            .is_some_and(|(_, last)| now - last.agent_data().timestamp() > self.config.max_gap())
+        {
+            self.end(self.data.len());
+        }
+        self.segmented
+    }
+
+    /// Ends the trip in progress with its first `len` data, dropping the rest
+    fn end(&mut self, len: usize) {
+        let mut data = std::mem::take(&mut self.data);
+        let rest = data.split_off(len.min(data.len()));
+        self.segmented
+            .dropped
+            .extend(rest.into_iter().map(|(id, _)| id));
+        self.stay_start = 0;
+
+        let (ids, data): (Vec<_>, Vec<_>) = data.into_iter().unzip();
+        match Trip::of(self.agent_id.clone(), &data, self.config) {
+            Some(trip) => self.segmented.trips.push((trip, ids)),
+            None => self.segmented.dropped.extend(ids),
+        }
+    }
+
+    fn within_stop(&self, stop: Gps, position: Gps) -> bool {
+        geo::distance_m(stop, position) <= self.config.stop_radius_m()
+    }
+}
+
+impl Trip {
+    pub fn agent_id(&self) -> Option<&AgentId> {
+        self.agent_id.as_ref()
+    }
+
+    pub fn start_time(&self) -> DateTime<Utc> {
+        self.start_time
+    }
+}
+
+impl TripWithId {
+    pub fn trip(&self) -> &Trip {
+        &self.trip
+    }
+}
+
+impl Display for TripId {
+    #[inline(always)]
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl From<i64> for TripId {
+    #[inline(always)]
+    fn from(value: i64) -> Self {
+        Self(value)
+    }
+}
+
+impl From<TripDao> for TripWithId {
+    fn from(dao: TripDao) -> Self {
+        Self {
+            id: dao.id,
+            trip: Trip {
+                agent_id: dao.agent_id,
+                start_time: dao.start_time,
+                end_time: dao.end_time,
+                distance_m: dao.distance_m,
+                samples: dao.samples,
+                rough_percentage: dao.rough_percentage,
+                polyline: dao.polyline.0,
+            },
+        }
+    }
+}
@@ -1,9 +1,21 @@
 use std::io;
 
-use actix_web::ResponseError;
+use actix_web::{
+    error::{JsonPayloadError, PathError, QueryPayloadError},
+    http::StatusCode,
+    HttpRequest, HttpResponse, ResponseError,
+};
+use iot_system::domain::{
+    InvalidAgentDataError, InvalidGpsDataError, InvalidProcessedAgentDataError,
+};
+use serde::Serialize;
+use utoipa::{ToResponse, ToSchema};
 
 pub type AppResult<T> = Result<T, AppError>;
 
+/// Media type of [`Problem`] responses
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
 #[derive(thiserror::Error, Debug)]
 pub enum AppError {
     #[error("SQL error: {0}")]
@@ -12,6 +24,345 @@ pub enum AppError {
     Io(#[from] io::Error),
     #[error("Serde error: {0}")]
     Serde(#[from] serde_json::Error),
+    #[error("{0} not found")]
+    NotFound(&'static str),
+    #[error("{}", .0.message())]
+    Invalid(Violation),
+    #[error("The version of the resource doesn't match `If-Match`")]
+    PreconditionFailed,
+    #[error("{0}")]
+    Request(String, ErrorCode),
+}
+
+/// Machine-readable error code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request body is not valid JSON
+    MalformedBody,
+    /// A path or query parameter is invalid
+    InvalidParameter,
+    MissingField,
+    InvalidValue,
+    UnsupportedMediaType,
+    PayloadTooLarge,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    /// The request conflicts with the stored data
+    Conflict,
+    /// The resource was modified since the version given in `If-Match`
+    PreconditionFailed,
+    /// A valid item of a batch could not be stored
+    StorageError,
+    Internal,
+}
+
+/// Invalid part of a request
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Violation {
+    code: ErrorCode,
+    /// Path of the offending field in the JSON representation of the data, e.g. `gps.latitude`,
+    /// or `[0].gps.latitude` for an item of a list
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<String>,
+    message: String,
+}
+
+/// Error response body as defined by RFC 7807, served as `application/problem+json`
+#[derive(Debug, Serialize, ToSchema, ToResponse)]
+#[schema(example = json!({
+    "type": "about:blank",
+    "title": "Unprocessable Entity",
+    "status": 422,
+    "detail": "latitude must be in range -90..90",
+    "code": "invalid_value",
+    "field": "gps.latitude"
+}))]
+pub struct Problem {
+    /// URI identifying the problem type. `about:blank` means that the status code describes it
+    #[serde(rename = "type")]
+    type_: &'static str,
+    /// Reason phrase of the status code
+    title: &'static str,
+    status: u16,
+    detail: String,
+    code: ErrorCode,
+    /// Path of the offending field, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<String>,
 }
 
-impl ResponseError for AppError {}
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::MalformedBody => "malformed_body",
+            Self::InvalidParameter => "invalid_parameter",
+            Self::MissingField => "missing_field",
+            Self::InvalidValue => "invalid_value",
+            Self::UnsupportedMediaType => "unsupported_media_type",
+            Self::PayloadTooLarge => "payload_too_large",
+            Self::Unauthorized => "unauthorized",
+            Self::Forbidden => "forbidden",
+            Self::NotFound => "not_found",
+            Self::Conflict => "conflict",
+            Self::PreconditionFailed => "precondition_failed",
+            Self::StorageError => "storage_error",
+            Self::Internal => "internal",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::MalformedBody | Self::InvalidParameter => StatusCode::BAD_REQUEST,
+            Self::MissingField | Self::InvalidValue => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Conflict => StatusCode::CONFLICT,
+            Self::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            Self::StorageError | Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl Violation {
+    pub fn new(code: ErrorCode, field: Option<String>, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            field,
+            message: message.into(),
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    pub fn field(&self) -> Option<&str> {
+        self.field.as_deref()
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The item was valid, but could not be stored
+    pub fn storage() -> Self {
+        Self::new(ErrorCode::StorageError, None, "Failed to store the item")
+    }
+
+    /// Describes a JSON value that failed to deserialize, at the tracked path
+    pub fn from_json(err: serde_path_to_error::Error<serde_json::Error>) -> Self {
+        let path = err.path().to_string();
+        Self::from_serde_json(err.inner(), (path != ".").then_some(path))
+    }
+
+    /// Describes a JSON value that failed to deserialize. `parent` is the path of the object
+    /// the error occurred in, if known
+    pub fn from_serde_json(err: &serde_json::Error, parent: Option<String>) -> Self {
+        let message = err.to_string();
+        if !err.is_data() {
+            return Self::new(ErrorCode::MalformedBody, None, message);
+        }
+        // serde reports missing fields at the containing object
+        match missing_field(&message) {
+            Some(name) => {
+                let field = match parent {
+                    Some(parent) => format!("{parent}.{name}"),
+                    None => name.to_owned(),
+                };
+                Self::new(ErrorCode::MissingField, Some(field), message)
+            }
+            None => Self::new(ErrorCode::InvalidValue, parent, message),
+        }
+    }
+
+    /// Prepends `prefix` to the field path, e.g. to point at an item of a list
+    pub fn within(self, prefix: &str) -> Self {
+        let field = match self.field {
+            Some(field) => format!("{prefix}.{field}"),
+            None => prefix.to_owned(),
+        };
+        Self {
+            field: Some(field),
+            ..self
+        }
+    }
+}
+
+/// Extracts the field name from the message of `serde::de::Error::missing_field`.
+/// `serde_json` appends the position to the message
+fn missing_field(message: &str) -> Option<&str> {
+    let rest = message.strip_prefix("missing field `")?;
+    rest.split('`').next()
+}
+
+impl From<&InvalidProcessedAgentDataError> for Violation {
+    fn from(err: &InvalidProcessedAgentDataError) -> Self {
+        use InvalidAgentDataError as Agent;
+        use InvalidGpsDataError as Gps;
+        use InvalidProcessedAgentDataError as ProcessedAgent;
+
+        // The paths are the ones of the JSON representation, with the agent data flattened,
+        // so that both APIs report the same ones. Only the agent message of gRPC can be missing
+        let (code, field) = match err {
+            ProcessedAgent::MissingAgentData => (ErrorCode::MissingField, "agent"),
+            ProcessedAgent::InvalidAgentData(err) => match err {
+                Agent::MissingGps => (ErrorCode::MissingField, "gps"),
+                Agent::MissingAccelerometer => (ErrorCode::MissingField, "accelerometer"),
+                Agent::MissingTimestamp => (ErrorCode::MissingField, "timestamp"),
+                Agent::InvalidTimestamp(_) => (ErrorCode::InvalidValue, "timestamp"),
+                Agent::InvalidGpsData(Gps::InvalidLatitude(_)) => {
+                    (ErrorCode::InvalidValue, "gps.latitude")
+                }
+                Agent::InvalidGpsData(Gps::InvalidLongitude(_)) => {
+                    (ErrorCode::InvalidValue, "gps.longitude")
+                }
+                Agent::InvalidGpsData(Gps::Both(..)) => (ErrorCode::InvalidValue, "gps"),
+                Agent::InvalidIdempotencyKey(_) => (ErrorCode::InvalidValue, "idempotency_key"),
+                Agent::InvalidAgentId(_) => (ErrorCode::InvalidValue, "agent_id"),
+            },
+        };
+        Self::new(code, Some(field.to_owned()), err.to_string())
+    }
+}
+
+impl AppError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::Sql(err) if is_unique_violation(err) => ErrorCode::Conflict,
+            Self::NotFound(_) => ErrorCode::NotFound,
+            Self::PreconditionFailed => ErrorCode::PreconditionFailed,
+            Self::Invalid(violation) => violation.code(),
+            Self::Request(_, code) => *code,
+            Self::Sql(_) | Self::Io(_) | Self::Serde(_) => ErrorCode::Internal,
+        }
+    }
+
+    /// Message safe to be shown to the client
+    pub fn detail(&self) -> String {
+        match self.code() {
+            ErrorCode::Internal => "Internal server error".to_owned(),
+            ErrorCode::Conflict => "The request conflicts with the stored data".to_owned(),
+            _ => self.to_string(),
+        }
+    }
+
+    pub fn field(&self) -> Option<&str> {
+        match self {
+            Self::Invalid(violation) => violation.field(),
+            _ => None,
+        }
+    }
+}
+
+fn is_unique_violation(err: &sqlx::Error) -> bool {
+    err.as_database_error()
+        .is_some_and(|err| err.is_unique_violation())
+}
+
+impl Problem {
+    pub fn new(code: ErrorCode, detail: String, field: Option<String>) -> Self {
+        let status = code.status();
+        Self {
+            type_: "about:blank",
+            title: status.canonical_reason().unwrap_or_default(),
+            status: status.as_u16(),
+            detail,
+            code,
+            field,
+        }
+    }
+
+    pub fn response(&self) -> HttpResponse {
+        HttpResponse::build(self.code.status())
+            .content_type(PROBLEM_JSON)
+            .json(self)
+    }
+}
+
+impl From<&AppError> for Problem {
+    fn from(err: &AppError) -> Self {
+        Self::new(err.code(), err.detail(), err.field().map(ToOwned::to_owned))
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        self.code().status()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if self.code() == ErrorCode::Internal {
+            tracing::error!("{self}");
+        }
+        Problem::from(self).response()
+    }
+}
+
+impl From<AppError> for tonic::Status {
+    fn from(err: AppError) -> Self {
+        match err.code() {
+            ErrorCode::Internal | ErrorCode::StorageError => {
+                tracing::error!("{err}");
+                tonic::Status::internal(err.detail())
+            }
+            ErrorCode::NotFound => tonic::Status::not_found(err.detail()),
+            ErrorCode::Conflict => tonic::Status::already_exists(err.detail()),
+            ErrorCode::PreconditionFailed => tonic::Status::failed_precondition(err.detail()),
+            ErrorCode::Unauthorized => tonic::Status::unauthenticated(err.detail()),
+            ErrorCode::Forbidden => tonic::Status::permission_denied(err.detail()),
+            _ => match err {
+                AppError::Invalid(violation) => crate::control::grpc::invalid_argument(
+                    violation.message().to_owned(),
+                    [violation],
+                ),
+                err => tonic::Status::invalid_argument(err.detail()),
+            },
+        }
+    }
+}
+
+impl From<JsonPayloadError> for AppError {
+    fn from(err: JsonPayloadError) -> Self {
+        match err {
+            JsonPayloadError::Deserialize(err) => {
+                Self::Invalid(Violation::from_serde_json(&err, None))
+            }
+            JsonPayloadError::ContentType => {
+                Self::Request(err.to_string(), ErrorCode::UnsupportedMediaType)
+            }
+            JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+                Self::Request(err.to_string(), ErrorCode::PayloadTooLarge)
+            }
+            JsonPayloadError::Serialize(err) => Self::Serde(err),
+            err => Self::Request(err.to_string(), ErrorCode::MalformedBody),
+        }
+    }
+}
+
+/// Fallback handler of the unknown routes
+pub async fn not_found() -> AppResult<HttpResponse> {
+    Err(AppError::Request(
+        "No such route".to_owned(),
+        ErrorCode::NotFound,
+    ))
+}
+
+/// Error handler of the JSON body extractor
+pub fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    AppError::from(err).into()
+}
+
+/// Error handler of the query string extractor
+pub fn query_error_handler(err: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    AppError::Request(err.to_string(), ErrorCode::InvalidParameter).into()
+}
+
+/// Error handler of the path parameters extractor
+pub fn path_error_handler(err: PathError, _req: &HttpRequest) -> actix_web::Error {
+    AppError::Request(err.to_string(), ErrorCode::InvalidParameter).into()
+}
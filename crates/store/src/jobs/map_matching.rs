@@ -0,0 +1,34 @@
+use std::sync::Arc;
+
+use tokio::time::MissedTickBehavior;
+
+use crate::{
+    config::MapMatching, data::repo::ProcessedAgentRepository, map_matching::RoadNetwork, service,
+};
+
+/// Matches the new and the changed data to the road network, once per interval.
+/// Every store instance runs the job, which is harmless, as the data is matched to the same way
+pub async fn match_to_roads(
+    config: MapMatching,
+    network: Arc<RoadNetwork>,
+    repo: Arc<dyn ProcessedAgentRepository>,
+) {
+    let max_distance_m = config.max_distance_m();
+    if max_distance_m.is_nan() || max_distance_m < 0.0 {
+        tracing::error!(
+            "Map matching is disabled: the maximum distance must not be negative, got {max_distance_m}"
+        );
+        return;
+    }
+
+    let mut interval = tokio::time::interval(config.interval());
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        match service::match_to_roads(&network, max_distance_m, &*repo).await {
+            Ok(0) => {}
+            Ok(matched) => tracing::info!("Matched {matched} data to the road network"),
+            Err(err) => tracing::error!("Failed to match the data to the road network: {err}"),
+        }
+    }
+}
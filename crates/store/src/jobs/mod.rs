@@ -0,0 +1,9 @@
+mod map_matching;
+mod purge;
+mod retention;
+mod trips;
+
+pub use map_matching::match_to_roads;
+pub use purge::purge_deleted;
+pub use retention::{apply_retention, RetentionMetrics, RetentionRun, RetentionStatus};
+pub use trips::segment_trips;
@@ -0,0 +1,26 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use tokio::time::MissedTickBehavior;
+
+use crate::{data::repo::ProcessedAgentRepository, service};
+
+/// Permanently deletes the soft-deleted data older than the retention, once per purge interval.
+/// Every store instance runs the job, which is harmless, as the purge is idempotent
+pub async fn purge_deleted(
+    retention: chrono::Duration,
+    interval: Duration,
+    repo: Arc<dyn ProcessedAgentRepository>,
+) {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let cutoff = Utc::now() - retention;
+        match service::purge_deleted_processed_agent_data(cutoff, &*repo).await {
+            Ok(0) => {}
+            Ok(purged) => tracing::info!("Purged {purged} deleted processed agent data"),
+            Err(err) => tracing::error!("Failed to purge the deleted processed agent data: {err}"),
+        }
+    }
+}
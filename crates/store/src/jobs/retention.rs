@@ -0,0 +1,151 @@
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::Instant,
+};
+
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use serde::Serialize;
+use tokio::time::MissedTickBehavior;
+use utoipa::ToSchema;
+
+use crate::{config::Retention, data::repo::ProcessedAgentRepository, service};
+
+/// Counters of the retention job since the store started
+#[derive(Debug, Default, Clone, Serialize, ToSchema)]
+pub struct RetentionMetrics {
+    /// `false` if the retention is not configured
+    enabled: bool,
+    dry_run: bool,
+    runs: u64,
+    failed_runs: u64,
+    /// Totals of the applied runs. Dry runs are not counted
+    expired_rows: i64,
+    aggregates: i64,
+    dropped_partitions: u64,
+    last_run: Option<RetentionRun>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RetentionRun {
+    started_at: DateTime<Utc>,
+    duration_ms: u64,
+    /// Raw data older than this was rolled up
+    cutoff: DateTime<Utc>,
+    /// `false` if another store instance was applying the retention
+    applied: bool,
+    expired_rows: i64,
+    aggregates: i64,
+    dropped_partitions: Vec<String>,
+    error: Option<String>,
+}
+
+/// Metrics of the retention job, shared with the HTTP handlers
+#[derive(Debug, Default)]
+pub struct RetentionStatus(Mutex<RetentionMetrics>);
+
+impl RetentionStatus {
+    pub fn new(config: Option<&Retention>) -> Self {
+        Self(Mutex::new(RetentionMetrics {
+            enabled: config.is_some(),
+            dry_run: config.is_some_and(Retention::dry_run),
+            ..Default::default()
+        }))
+    }
+
+    pub fn metrics(&self) -> RetentionMetrics {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn record(&self, run: RetentionRun, dry_run: bool) {
+        let mut metrics = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        metrics.runs += 1;
+        if run.error.is_some() {
+            metrics.failed_runs += 1;
+        } else if !dry_run {
+            metrics.expired_rows += run.expired_rows;
+            metrics.aggregates += run.aggregates;
+            metrics.dropped_partitions += run.dropped_partitions.len() as u64;
+        }
+        metrics.last_run = Some(run);
+    }
+}
+
+/// Rolls the raw data older than the retention into hourly per-tile aggregates and deletes it,
+/// once per interval. Only one store instance applies the retention at a time
+pub async fn apply_retention(
+    config: Retention,
+    status: Arc<RetentionStatus>,
+    repo: Arc<dyn ProcessedAgentRepository>,
+) {
+    if config.tile_size_deg().is_nan() || config.tile_size_deg() <= 0.0 {
+        tracing::error!(
+            "Retention is disabled: the tile size must be positive, got {}",
+            config.tile_size_deg()
+        );
+        return;
+    }
+
+    let mut interval = tokio::time::interval(config.interval());
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        if config.partition_by_month() && !config.dry_run() {
+            if let Err(err) = service::prepare_partitions(&*repo).await {
+                tracing::error!("Failed to prepare the monthly partitions: {err}");
+            }
+        }
+
+        let started_at = Utc::now();
+        let start = Instant::now();
+        // Whole hours, so that an hour is never aggregated partially
+        let cutoff = (started_at - config.raw_retention())
+            .duration_trunc(TimeDelta::hours(1))
+            .expect("An hour fits any timestamp");
+        let result =
+            service::apply_retention(cutoff, config.tile_size_deg(), config.dry_run(), &*repo)
+                .await;
+        let duration_ms = u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX);
+
+        let run = match result {
+            Ok(outcome) => {
+                if outcome.applied && outcome.expired_rows > 0 {
+                    tracing::info!(
+                        dry_run = config.dry_run(),
+                        "Rolled {} processed agent data older than {cutoff} into {} hourly aggregates, \
+                         dropped partitions: {:?}",
+                        outcome.expired_rows,
+                        outcome.aggregates,
+                        outcome.dropped_partitions,
+                    );
+                }
+                RetentionRun {
+                    started_at,
+                    duration_ms,
+                    cutoff,
+                    applied: outcome.applied,
+                    expired_rows: outcome.expired_rows,
+                    aggregates: outcome.aggregates,
+                    dropped_partitions: outcome.dropped_partitions,
+                    error: None,
+                }
+            }
+            Err(err) => {
+                tracing::error!("Failed to apply the retention: {err}");
+                RetentionRun {
+                    started_at,
+                    duration_ms,
+                    cutoff,
+                    applied: false,
+                    expired_rows: 0,
+                    aggregates: 0,
+                    dropped_partitions: Vec::new(),
+                    error: Some(err.to_string()),
+                }
+            }
+        };
+        status.record(run, config.dry_run());
+    }
+}
@@ -0,0 +1,33 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use tokio::time::MissedTickBehavior;
+
+use crate::{config::Trips, data::repo::ProcessedAgentRepository, service};
+
+/// Segments the new data into trips, once per interval. Every store instance runs the job,
+/// which is harmless, as a trip segmented by several instances is stored once
+pub async fn segment_trips(config: Trips, repo: Arc<dyn ProcessedAgentRepository>) {
+    for (name, value) in [
+        ("stop radius", config.stop_radius_m()),
+        ("simplification tolerance", config.simplify_tolerance_m()),
+    ] {
+        if value.is_nan() || value < 0.0 {
+            tracing::error!(
+                "Trip segmentation is disabled: the {name} must not be negative, got {value}"
+            );
+            return;
+        }
+    }
+
+    let mut interval = tokio::time::interval(config.interval());
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        match service::segment_trips(&config, Utc::now(), &*repo).await {
+            Ok(0) => {}
+            Ok(stored) => tracing::info!("Stored {stored} new trips"),
+            Err(err) => tracing::error!("Failed to segment the trips: {err}"),
+        }
+    }
+}
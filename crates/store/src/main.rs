@@ -5,21 +5,33 @@ use actix_web::{
     web, App, HttpServer,
 };
 use color_eyre::eyre::Result;
-use iot_system::{config::TryRead, proto, setup_tracing, KtConvenience};
+use iot_system::{config::TryRead, proto, reclone, setup_tracing, KtConvenience};
 use sqlx::PgPool;
 use tracing_actix_web::TracingLogger;
-use utoipa::OpenApi;
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
 use utoipa_swagger_ui::SwaggerUi;
 
 use crate::{
-    config::Configuration,
+    auth::{AuthInterceptor, Authenticator, Scope},
+    bus::EventBus,
+    config::{Configuration, Storage},
     control::{grpc, ws::Subscribers},
+    data::repo::{MemoryRepository, PgRepository, ProcessedAgentRepository, SqliteRepository},
+    jobs::RetentionStatus,
+    map_matching::RoadNetwork,
 };
 
+mod auth;
+mod bus;
 mod config;
 mod control;
 mod data;
 mod error;
+mod jobs;
+mod map_matching;
 mod service;
 
 #[tokio::main]
@@ -30,15 +42,58 @@ async fn main() -> Result<()> {
     let config = Configuration::try_read()?;
     tracing::debug!("Configuration: {:#?}", config);
 
-    let pool: PgPool = PgPool::connect_with(config.database().connect_options()).await?;
-    tracing::info!("Connected to database");
-
-    sqlx::migrate!("./migrations").run(&pool).await?;
-    tracing::info!("Migrations successfully applied");
+    // The pool is shared with the Postgres event bus
+    let (repo, pool): (Arc<dyn ProcessedAgentRepository>, Option<PgPool>) = match config.storage() {
+        Storage::Postgres => {
+            let repo = PgRepository::connect(config.database().connect_options()).await?;
+            let pool = repo.pool().clone();
+            (Arc::new(repo), Some(pool))
+        }
+        Storage::Sqlite { path } => (Arc::new(SqliteRepository::connect(path).await?), None),
+        Storage::Memory => (Arc::new(MemoryRepository::default()), None),
+    };
+    tracing::info!("Storage ready, migrations applied: {:?}", config.storage());
 
-    let subs = Arc::new(Subscribers::new());
+    let (subs, events) = Subscribers::new(config.websocket());
+    let subs = Arc::new(subs);
+    tokio::spawn(subs.clone().dispatch(events));
+    let bus = Arc::new(EventBus::start(config.event_bus(), subs.clone(), pool.as_ref()).await?);
+    let authenticator = Arc::new(Authenticator::new(config.auth())?);
+    tokio::spawn(jobs::purge_deleted(
+        config.soft_delete().retention(),
+        config.soft_delete().purge_interval(),
+        repo.clone(),
+    ));
+    let retention = Arc::new(RetentionStatus::new(config.retention()));
+    if let Some(config) = config.retention() {
+        tokio::spawn(jobs::apply_retention(
+            config.clone(),
+            retention.clone(),
+            repo.clone(),
+        ));
+    }
+    if let Some(config) = config.trips() {
+        tokio::spawn(jobs::segment_trips(config.clone(), repo.clone()));
+    }
+    let road_network = match config.map_matching() {
+        Some(config) => {
+            let network = Arc::new(RoadNetwork::load(config.road_network_path())?);
+            tracing::info!(
+                "Road network loaded from {}: {} ways",
+                config.road_network_path().display(),
+                network.len()
+            );
+            tokio::spawn(jobs::match_to_roads(
+                config.clone(),
+                network.clone(),
+                repo.clone(),
+            ));
+            Some(network)
+        }
+        None => None,
+    };
 
-    let store_service = grpc::StoreService::new(subs.clone(), pool.clone());
+    let store_service = grpc::StoreService::new(bus.clone(), repo.clone());
     let reflection_service = tonic_reflection::server::Builder::configure()
         .register_encoded_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
         .build()?;
@@ -46,11 +101,15 @@ async fn main() -> Result<()> {
     let openapi = ApiDocs::openapi();
 
     tokio::spawn({
+        reclone!(authenticator);
         let address = config.grpc_server().try_into()?;
         async move {
             tonic::transport::Server::builder()
                 .add_service(reflection_service)
-                .add_service(proto::store_server::StoreServer::new(store_service))
+                .add_service(proto::store_server::StoreServer::with_interceptor(
+                    store_service,
+                    AuthInterceptor::new(authenticator.clone(), Scope::Write),
+                ))
                 .serve(address)
                 .await?;
             Ok::<(), tonic::transport::Error>(())
@@ -65,12 +124,34 @@ async fn main() -> Result<()> {
                     .wrap(NormalizePath::new(TrailingSlash::Trim))
                     .service(control::ws::ws_endpoint)
                     .service(control::http::create_processed_agent_data)
+                    .service(control::http::create_processed_agent_data_best_effort)
+                    // Before the `{id}` route, which would match `near` and `nearest` otherwise
+                    .service(control::http::read_processed_agent_data_near)
+                    .service(control::http::read_processed_agent_data_nearest)
                     .service(control::http::read_processed_agent_data)
                     .service(control::http::read_processed_agent_data_list)
+                    .service(control::http::read_processed_agent_data_history)
                     .service(control::http::update_processed_agent_data)
+                    .service(control::http::patch_processed_agent_data)
                     .service(control::http::delete_processed_agent_data)
-                    .app_data(web::Data::new(pool.clone()))
-                    .app_data(web::Data::from(subs.clone())),
+                    .service(control::http::restore_processed_agent_data)
+                    .service(control::http::read_trips)
+                    .service(control::http::read_trip_points)
+                    .service(control::http::read_road_quality)
+                    .service(control::http::read_retention_metrics)
+                    .app_data(web::Data::from(repo.clone()))
+                    .app_data(web::Data::from(subs.clone()))
+                    .app_data(web::Data::from(bus.clone()))
+                    .app_data(web::Data::from(authenticator.clone()))
+                    .app_data(web::Data::from(retention.clone()))
+                    .r#let(|scope| match &road_network {
+                        Some(network) => scope.app_data(web::Data::from(network.clone())),
+                        None => scope,
+                    })
+                    .app_data(web::JsonConfig::default().error_handler(error::json_error_handler))
+                    .app_data(web::QueryConfig::default().error_handler(error::query_error_handler))
+                    .app_data(web::PathConfig::default().error_handler(error::path_error_handler))
+                    .default_service(web::to(error::not_found)),
             )
             .service(web::redirect("/swagger-ui", "/swagger-ui/"))
             .service(
@@ -89,26 +170,78 @@ async fn main() -> Result<()> {
 #[openapi(
     paths(
         control::http::create_processed_agent_data,
+        control::http::create_processed_agent_data_best_effort,
         control::http::read_processed_agent_data,
         control::http::read_processed_agent_data_list,
+        control::http::read_processed_agent_data_near,
+        control::http::read_processed_agent_data_nearest,
+        control::http::read_processed_agent_data_history,
         control::http::update_processed_agent_data,
+        control::http::patch_processed_agent_data,
         control::http::delete_processed_agent_data,
+        control::http::restore_processed_agent_data,
+        control::http::read_trips,
+        control::http::read_trip_points,
+        control::http::read_road_quality,
+        control::http::read_retention_metrics,
     ),
     components(
         schemas(
             data::Accelerometer,
             data::Gps,
             data::Agent,
+            data::Motion,
             data::ProcessedAgent,
-            data::ProcessedAgentWithId
+            data::ProcessedAgentWithId,
+            data::ItemResult,
+            data::NearbyProcessedAgent,
+            data::AuditEntry,
+            data::AuditAction,
+            data::Trip,
+            data::TripWithId,
+            data::RoadQuality,
+            jobs::RetentionMetrics,
+            jobs::RetentionRun,
+            error::Violation,
+            error::ErrorCode,
+            error::Problem
         ),
         responses(
             data::Accelerometer,
             data::Gps,
             data::Agent,
             data::ProcessedAgent,
-            data::ProcessedAgentWithId
+            data::ProcessedAgentWithId,
+            error::Problem
         ),
-    )
+    ),
+    modifiers(&SecurityAddon)
 )]
 struct ApiDocs;
+
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "X-API-Key",
+                "Static API key. Scopes are assigned to the key in the store configuration",
+            ))),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .description(Some(
+                        "HS256 or RS256 signed JWT. Scopes are taken from the space-separated `scope` claim",
+                    ))
+                    .build(),
+            ),
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use serde::{de::Error as _, Deserialize, Deserializer};
+use serde_json::{Map, Value};
+
+use crate::data::{Gps, Latitude, Longitude, WayId};
+
+/// The subset of GeoJSON describing a road network
+#[derive(Debug, Deserialize)]
+pub struct FeatureCollection {
+    pub features: Vec<Feature>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Feature {
+    #[serde(default)]
+    id: Option<Value>,
+    pub geometry: Option<Geometry>,
+    #[serde(default)]
+    properties: Option<Map<String, Value>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum Geometry {
+    LineString {
+        coordinates: Vec<Position>,
+    },
+    MultiLineString {
+        coordinates: Vec<Vec<Position>>,
+    },
+    /// Points and areas are not roads
+    #[serde(other)]
+    Other,
+}
+
+/// `[longitude, latitude]`, optionally followed by the altitude
+#[derive(Debug, Clone, Copy)]
+pub struct Position(pub Gps);
+
+impl Feature {
+    /// OpenStreetMap ID of the way. Taken from the numeric feature ID, the feature ID written
+    /// by `osmium export -u type_id`, like `w123`, or the `osm_id` property
+    pub fn way_id(&self) -> Option<WayId> {
+        let parse = |value: &Value| match value {
+            Value::Number(number) => number.as_i64(),
+            Value::String(id) => id
+                .strip_prefix("way/")
+                .or_else(|| id.strip_prefix('w'))
+                .unwrap_or(id)
+                .parse()
+                .ok(),
+            _ => None,
+        };
+        self.id
+            .as_ref()
+            .and_then(parse)
+            .or_else(|| self.properties.as_ref()?.get("osm_id").and_then(parse))
+            .map(WayId::from)
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.properties.as_ref()?.get("name")?.as_str()
+    }
+}
+
+impl<'de> Deserialize<'de> for Position {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let coordinates = Vec::<f64>::deserialize(deserializer)?;
+        let [longitude, latitude, ..] = coordinates[..] else {
+            return Err(D::Error::invalid_length(
+                coordinates.len(),
+                &"a position of at least 2 coordinates",
+            ));
+        };
+        Ok(Self(Gps::new(
+            Latitude::try_from(latitude).map_err(D::Error::custom)?,
+            Longitude::try_from(longitude).map_err(D::Error::custom)?,
+        )))
+    }
+}
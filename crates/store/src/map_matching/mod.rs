@@ -0,0 +1,183 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use self::geojson::{FeatureCollection, Geometry, Position};
+use crate::data::{
+    geo::{self, MEAN_EARTH_RADIUS_M},
+    Gps, WayId, WayMatch,
+};
+
+mod geojson;
+
+/// Side of the square cells of the grid indexing the segments of the ways, in degrees
+const CELL_SIZE_DEG: f64 = 0.01;
+
+/// Ways of the road network, indexed for finding the one nearest to a position
+#[derive(Debug)]
+pub struct RoadNetwork {
+    lines: Vec<Line>,
+    names: HashMap<WayId, String>,
+    /// Segments crossing each cell of the grid, as the indices of the line and of the first node
+    cells: HashMap<(i64, i64), Vec<(usize, usize)>>,
+}
+
+/// Continuous part of a way
+#[derive(Debug)]
+struct Line {
+    way_id: WayId,
+    nodes: Vec<Gps>,
+    /// Distance along the way from its first node to each node of the line, in meters
+    offsets: Vec<f64>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RoadNetworkError {
+    #[error("Failed to read the road network: {0}")]
+    Io(#[from] io::Error),
+    #[error("Failed to parse the road network: {0}")]
+    Serde(#[from] serde_json::Error),
+}
+
+impl RoadNetwork {
+    /// Reads the ways from a GeoJSON feature collection of `LineString` and `MultiLineString`
+    /// features. Features of other geometries, or without an OpenStreetMap way ID, are skipped
+    pub fn load(path: &Path) -> Result<Self, RoadNetworkError> {
+        let collection: FeatureCollection = serde_json::from_slice(&fs::read(path)?)?;
+
+        let mut network = Self {
+            lines: Vec::new(),
+            names: HashMap::new(),
+            cells: HashMap::new(),
+        };
+        let mut skipped = 0;
+        for feature in collection.features {
+            let (Some(way_id), Some(geometry)) = (feature.way_id(), &feature.geometry) else {
+                skipped += 1;
+                continue;
+            };
+            let lines = match geometry {
+                Geometry::LineString { coordinates } => vec![coordinates.as_slice()],
+                Geometry::MultiLineString { coordinates } => {
+                    coordinates.iter().map(Vec::as_slice).collect()
+                }
+                Geometry::Other => {
+                    skipped += 1;
+                    continue;
+                }
+            };
+            if let Some(name) = feature.name() {
+                network.names.insert(way_id, name.to_owned());
+            }
+            // The parts of a way are measured one after another
+            let mut offset_m = 0.0;
+            for line in lines {
+                offset_m = network.add_line(way_id, line, offset_m);
+            }
+        }
+        if skipped > 0 {
+            tracing::warn!("Skipped {skipped} features of the road network that are not ways");
+        }
+
+        Ok(network)
+    }
+
+    /// Number of the ways
+    pub fn len(&self) -> usize {
+        let mut ways: Vec<_> = self.lines.iter().map(|line| line.way_id).collect();
+        ways.sort_unstable();
+        ways.dedup();
+        ways.len()
+    }
+
+    pub fn name(&self, way_id: WayId) -> Option<&str> {
+        self.names.get(&way_id).map(String::as_str)
+    }
+
+    /// Finds the position on the way nearest to the given one, within `max_distance_m` meters of it
+    pub fn nearest(&self, position: Gps, max_distance_m: f64) -> Option<WayMatch> {
+        let latitude = f64::from(position.latitude());
+        let longitude = f64::from(position.longitude());
+        let latitude_delta = (max_distance_m / MEAN_EARTH_RADIUS_M).to_degrees();
+        // The meridians converge towards the poles, where the cells cover less longitude
+        let longitude_delta = latitude_delta / latitude.to_radians().cos().max(0.01);
+        let (south, west) = cell(latitude - latitude_delta, longitude - longitude_delta);
+        let (north, east) = cell(latitude + latitude_delta, longitude + longitude_delta);
+
+        let mut nearest: Option<(f64, WayMatch)> = None;
+        for row in south..=north {
+            for column in west..=east {
+                let Some(segments) = self.cells.get(&(row, column)) else {
+                    continue;
+                };
+                for &(line, node) in segments {
+                    let line = &self.lines[line];
+                    let (distance_m, t) =
+                        geo::project_onto_segment(position, line.nodes[node], line.nodes[node + 1]);
+                    if distance_m > max_distance_m
+                        || nearest.is_some_and(|(nearest_m, _)| nearest_m <= distance_m)
+                    {
+                        continue;
+                    }
+                    let offset_m =
+                        line.offsets[node] + t * (line.offsets[node + 1] - line.offsets[node]);
+                    nearest = Some((
+                        distance_m,
+                        WayMatch {
+                            way_id: line.way_id,
+                            offset_m,
+                        },
+                    ));
+                }
+            }
+        }
+        nearest.map(|(_, way)| way)
+    }
+
+    /// Indexes the line of the way starting `offset_m` meters along it.
+    /// Returns the offset of its end
+    fn add_line(&mut self, way_id: WayId, positions: &[Position], offset_m: f64) -> f64 {
+        let nodes: Vec<_> = positions.iter().map(|&Position(gps)| gps).collect();
+        let mut offsets = Vec::with_capacity(nodes.len());
+        let mut offset_m = offset_m;
+        for (index, &node) in nodes.iter().enumerate() {
+            if let Some(&previous) = index.checked_sub(1).and_then(|index| nodes.get(index)) {
+                offset_m += geo::distance_m(previous, node);
+            }
+            offsets.push(offset_m);
+        }
+
+        let line = self.lines.len();
+        for (node, pair) in nodes.windows(2).enumerate() {
+            let (a_row, a_column) = cell_of(pair[0]);
+            let (b_row, b_column) = cell_of(pair[1]);
+            for row in a_row.min(b_row)..=a_row.max(b_row) {
+                for column in a_column.min(b_column)..=a_column.max(b_column) {
+                    self.cells
+                        .entry((row, column))
+                        .or_default()
+                        .push((line, node));
+                }
+            }
+        }
+        self.lines.push(Line {
+            way_id,
+            nodes,
+            offsets,
+        });
+
+        offset_m
+    }
+}
+
+fn cell_of(position: Gps) -> (i64, i64) {
+    cell(
+        f64::from(position.latitude()),
+        f64::from(position.longitude()),
+    )
+}
+
+fn cell(latitude: f64, longitude: f64) -> (i64, i64) {
+    (
+        (latitude / CELL_SIZE_DEG).floor() as i64,
+        (longitude / CELL_SIZE_DEG).floor() as i64,
+    )
+}
@@ -1,85 +1,352 @@
 use std::num::{NonZeroU32, NonZeroU8};
 
-use sqlx::PgPool;
+use chrono::{DateTime, Utc};
 use tracing::instrument;
 
 use crate::{
-    control::ws::{Message, Subscribers},
-    data::{repo, ProcessedAgent, ProcessedAgentId, ProcessedAgentWithId},
-    error::AppResult,
+    bus::EventBus,
+    config::Trips,
+    control::ws::Event,
+    data::{
+        repo::{ProcessedAgentRepository, RetentionOutcome},
+        AuditEntry, ItemResult, Latitude, Longitude, MapMatched, NearbyProcessedAgent,
+        ProcessedAgent, ProcessedAgentId, ProcessedAgentWithId, RoadQuality, Segmenter, TripId,
+        TripWithId, Version,
+    },
+    error::{AppResult, Violation},
+    map_matching::RoadNetwork,
 };
 
-#[instrument(skip(subs, pool))]
+/// Number of the data read at once while segmenting the trips
+const SEGMENTATION_BATCH_SIZE: i64 = 1000;
+
+/// Number of the data matched to the road network at once
+const MAP_MATCHING_BATCH_SIZE: i64 = 1000;
+
+#[instrument(skip(bus, repo))]
 pub async fn create_processed_agent_data(
     data: ProcessedAgent,
-    subs: &Subscribers,
-    pool: &PgPool,
+    bus: &EventBus,
+    repo: &dyn ProcessedAgentRepository,
 ) -> AppResult<ProcessedAgentId> {
-    let id = repo::insert_processed_agent_data(&data, pool).await?;
-    subs.broadcast(Message::New { id, data: &data }).await?;
+    let inserted = repo.insert(&data).await?;
+    if inserted.created {
+        bus.publish(Event::created(inserted.id, data));
+    }
 
-    Ok(id)
+    Ok(inserted.id)
 }
 
-#[instrument(skip(subs, pool))]
+#[instrument(skip(bus, repo))]
 pub async fn create_processed_agent_data_list(
     data: Vec<ProcessedAgent>,
-    subs: &Subscribers,
-    pool: &PgPool,
+    bus: &EventBus,
+    repo: &dyn ProcessedAgentRepository,
 ) -> AppResult<Vec<ProcessedAgentId>> {
-    let ids = repo::insert_processed_agent_data_list(&data, pool).await?;
-    subs.broadcast(Message::New {
-        id: ids.as_slice(),
-        data: data.as_slice(),
-    })
-    .await?;
+    let inserted = repo.insert_all(&data).await?;
+    let ids = inserted.iter().map(|inserted| inserted.id).collect();
+
+    // Samples submitted again are not broadcast again
+    let (created_ids, created_data) = inserted
+        .iter()
+        .zip(data)
+        .filter(|(inserted, _)| inserted.created)
+        .map(|(inserted, data)| (inserted.id, data))
+        .unzip::<_, _, Vec<_>, Vec<_>>();
+    if !created_ids.is_empty() {
+        bus.publish(Event::created_list(created_ids, created_data));
+    }
 
     Ok(ids)
 }
 
-#[instrument(skip(pool))]
+/// Stores the valid items, skipping the invalid ones. Returns the outcome of each item
+/// at its original index
+#[instrument(skip_all, fields(len = items.len()))]
+pub async fn create_processed_agent_data_best_effort(
+    items: Vec<Result<ProcessedAgent, Violation>>,
+    bus: &EventBus,
+    repo: &dyn ProcessedAgentRepository,
+) -> AppResult<Vec<ItemResult>> {
+    let mut results = Vec::with_capacity(items.len());
+    let mut indices = Vec::new();
+    let mut data = Vec::new();
+    for (index, item) in items.into_iter().enumerate() {
+        match item {
+            Ok(item) => {
+                indices.push(index);
+                data.push(item);
+                // Replaced once the item is stored
+                results.push(ItemResult::Failed {
+                    error: Violation::storage(),
+                });
+            }
+            Err(error) => results.push(ItemResult::Failed { error }),
+        }
+    }
+    let inserted = repo.insert_each(&data).await?;
+
+    let mut created_ids = Vec::new();
+    let mut created_data = Vec::new();
+    for ((index, data), inserted) in indices.into_iter().zip(data).zip(inserted) {
+        match inserted {
+            Ok(inserted) => {
+                results[index] = ItemResult::Created { id: inserted.id };
+                if inserted.created {
+                    created_ids.push(inserted.id);
+                    created_data.push(data);
+                }
+            }
+            Err(err) => tracing::error!("Failed to store item {index}: {err}"),
+        }
+    }
+    if !created_ids.is_empty() {
+        bus.publish(Event::created_list(created_ids, created_data));
+    }
+
+    Ok(results)
+}
+
+#[instrument(skip(repo))]
 pub async fn fetch_processed_agent_data(
     id: ProcessedAgentId,
-    pool: &PgPool,
-) -> AppResult<Option<ProcessedAgent>> {
-    Ok(repo::select_processed_agent_data(id, pool).await?)
+    include_deleted: bool,
+    repo: &dyn ProcessedAgentRepository,
+) -> AppResult<Option<(ProcessedAgent, Version)>> {
+    repo.select(id, include_deleted).await
 }
 
-#[instrument(skip(pool))]
+#[instrument(skip(repo))]
 pub async fn fetch_processed_agent_data_list(
     page: NonZeroU32,
     size: NonZeroU8,
-    pool: &PgPool,
+    include_deleted: bool,
+    repo: &dyn ProcessedAgentRepository,
 ) -> AppResult<Vec<ProcessedAgentWithId>> {
-    Ok(repo::select_processed_agent_data_list(page, size, pool).await?)
+    repo.select_list(page, size, include_deleted).await
 }
 
-#[instrument(skip(pool, subs))]
+/// Returns up to `limit` data nearest to the point and, if given, within `radius_m` meters of it
+#[instrument(skip(repo))]
+pub async fn fetch_processed_agent_data_near(
+    latitude: Latitude,
+    longitude: Longitude,
+    radius_m: Option<f64>,
+    limit: i64,
+    repo: &dyn ProcessedAgentRepository,
+) -> AppResult<Vec<NearbyProcessedAgent>> {
+    repo.select_near(latitude, longitude, radius_m, limit).await
+}
+
+#[instrument(skip(repo))]
+pub async fn fetch_processed_agent_data_after(
+    after: ProcessedAgentId,
+    since: Option<DateTime<Utc>>,
+    limit: i64,
+    repo: &dyn ProcessedAgentRepository,
+) -> AppResult<Vec<(ProcessedAgentId, ProcessedAgent)>> {
+    repo.select_after(after, since, limit).await
+}
+
+/// Replaces the data if its version is one of `expected`, or unconditionally if `expected` is `None`.
+/// The change is recorded in the audit log and published as stored. Returns the new version
+#[instrument(skip(repo, bus))]
 pub async fn update_processed_agent_data(
     id: ProcessedAgentId,
     data: ProcessedAgent,
-    pool: &PgPool,
-    subs: &Subscribers,
-) -> AppResult<bool> {
-    let updated = repo::update_processed_agent_data(id, &data, pool).await?;
-    if updated {
-        subs.broadcast(Message::Update { id, data: &data }).await?;
-    }
+    expected: Option<&[Version]>,
+    actor: &str,
+    repo: &dyn ProcessedAgentRepository,
+    bus: &EventBus,
+) -> AppResult<Version> {
+    // The stored data keeps the agent of the original sample, which the subscribers filter by
+    let (version, data) = repo.update(id, &data, expected, actor).await?;
 
-    Ok(updated)
+    bus.publish(Event::Update { id, data });
+    Ok(version)
 }
 
-#[instrument(skip(pool, subs))]
+/// Soft-deletes the data if its version is one of `expected`, or unconditionally if `expected` is `None`.
+/// The deletion is recorded in the audit log. Deleting absent data succeeds, unless a version is expected.
+/// Returns whether the data was deleted
+#[instrument(skip(repo, bus))]
 pub async fn delete_processed_agent_data(
     id: ProcessedAgentId,
-    pool: &PgPool,
-    subs: &Subscribers,
-) -> AppResult<()> {
-    let deleted = repo::delete_processed_agent_data(id, pool).await?;
+    expected: Option<&[Version]>,
+    actor: &str,
+    repo: &dyn ProcessedAgentRepository,
+    bus: &EventBus,
+) -> AppResult<bool> {
+    let deleted = repo.delete(id, expected, actor).await?;
     if deleted {
-        subs.broadcast::<ProcessedAgent>(Message::Delete { id })
-            .await?;
+        bus.publish(Event::Delete { id });
+    }
+    Ok(deleted)
+}
+
+/// Restores the soft-deleted data if its version is one of `expected`, or unconditionally
+/// if `expected` is `None`. The restoration is recorded in the audit log.
+/// Restoring data that isn't deleted does nothing. Returns the new version
+#[instrument(skip(repo, bus))]
+pub async fn restore_processed_agent_data(
+    id: ProcessedAgentId,
+    expected: Option<&[Version]>,
+    actor: &str,
+    repo: &dyn ProcessedAgentRepository,
+    bus: &EventBus,
+) -> AppResult<Version> {
+    let (version, restored) = repo.restore(id, expected, actor).await?;
+    if let Some(data) = restored {
+        bus.publish(Event::Restore { id, data });
+    }
+    Ok(version)
+}
+
+/// Permanently deletes the data soft-deleted before `cutoff`. Returns the number of purged rows
+#[instrument(skip(repo))]
+pub async fn purge_deleted_processed_agent_data(
+    cutoff: DateTime<Utc>,
+    repo: &dyn ProcessedAgentRepository,
+) -> AppResult<u64> {
+    repo.purge_deleted(cutoff).await
+}
+
+/// Rolls the raw data older than `cutoff` into hourly per-tile aggregates, then deletes it.
+/// Nothing is changed in the dry run, but the outcome is reported as if it was
+#[instrument(skip(repo))]
+pub async fn apply_retention(
+    cutoff: DateTime<Utc>,
+    tile_size_deg: f64,
+    dry_run: bool,
+    repo: &dyn ProcessedAgentRepository,
+) -> AppResult<RetentionOutcome> {
+    repo.apply_retention(cutoff, tile_size_deg, dry_run).await
+}
+
+/// Partitions the data by month, if the storage supports it
+#[instrument(skip(repo))]
+pub async fn prepare_partitions(repo: &dyn ProcessedAgentRepository) -> AppResult<()> {
+    repo.prepare_partitions().await
+}
+
+/// Returns the audit trail of the data, oldest first
+#[instrument(skip(repo))]
+pub async fn fetch_processed_agent_data_history(
+    id: ProcessedAgentId,
+    repo: &dyn ProcessedAgentRepository,
+) -> AppResult<Vec<AuditEntry>> {
+    repo.select_audit_entries(id).await
+}
+
+/// Segments the data of each agent not segmented yet into trips, storing the ended ones.
+/// The data of the trip in progress is segmented again on the next call.
+/// Returns the number of stored trips
+#[instrument(skip_all)]
+pub async fn segment_trips(
+    config: &Trips,
+    now: DateTime<Utc>,
+    repo: &dyn ProcessedAgentRepository,
+) -> AppResult<u64> {
+    let mut stored = 0;
+    for agent_id in repo.select_unsegmented_agents().await? {
+        let mut segmenter = Segmenter::new(config, agent_id.clone());
+        let mut after = None;
+        loop {
+            let data = repo
+                .select_unsegmented(agent_id.as_ref(), after, SEGMENTATION_BATCH_SIZE)
+                .await?;
+            let exhausted = data.len() < SEGMENTATION_BATCH_SIZE as usize;
+            for (id, data) in data {
+                after = Some((data.agent_data().timestamp(), id));
+                segmenter.push(id, data);
+            }
+            if exhausted {
+                break;
+            }
+        }
+
+        let segmented = segmenter.finish(now);
+        for (trip, data) in &segmented.trips {
+            if repo.insert_trip(trip, data).await? {
+                stored += 1;
+            }
+        }
+        if !segmented.dropped.is_empty() {
+            repo.mark_segmented(&segmented.dropped).await?;
+        }
     }
+    Ok(stored)
+}
 
-    Ok(())
+#[instrument(skip(repo))]
+pub async fn fetch_trips(
+    page: NonZeroU32,
+    size: NonZeroU8,
+    repo: &dyn ProcessedAgentRepository,
+) -> AppResult<Vec<TripWithId>> {
+    repo.select_trips(page, size).await
+}
+
+/// Returns the live data of the trip, oldest first, or `None` if the trip doesn't exist
+#[instrument(skip(repo))]
+pub async fn fetch_trip_points(
+    id: TripId,
+    repo: &dyn ProcessedAgentRepository,
+) -> AppResult<Option<Vec<ProcessedAgentWithId>>> {
+    if repo.select_trip(id).await?.is_none() {
+        return Ok(None);
+    }
+    repo.select_trip_data(id).await.map(Some)
+}
+
+/// Matches the data not matched yet to the nearest way within `max_distance_m` meters of it.
+/// Returns the number of matched data, including the data with no way near it
+#[instrument(skip(network, repo))]
+pub async fn match_to_roads(
+    network: &RoadNetwork,
+    max_distance_m: f64,
+    repo: &dyn ProcessedAgentRepository,
+) -> AppResult<u64> {
+    let mut matched = 0;
+    loop {
+        let data = repo.select_unmatched(MAP_MATCHING_BATCH_SIZE).await?;
+        let exhausted = data.len() < MAP_MATCHING_BATCH_SIZE as usize;
+        let matches: Vec<_> = data
+            .into_iter()
+            .map(|(id, version, gps)| MapMatched {
+                id,
+                version,
+                way: network.nearest(gps, max_distance_m),
+            })
+            .collect();
+        let updated = repo.update_way_matches(&matches).await?;
+        matched += updated;
+        // The data changed while being matched is matched on the next call
+        if exhausted || updated == 0 {
+            break;
+        }
+    }
+
+    Ok(matched)
+}
+
+/// Returns the road quality of the ways, the largest share of rough road first.
+/// The ways are named after the road network, if it is loaded
+#[instrument(skip(network, repo))]
+pub async fn fetch_road_quality(
+    page: NonZeroU32,
+    size: NonZeroU8,
+    network: Option<&RoadNetwork>,
+    repo: &dyn ProcessedAgentRepository,
+) -> AppResult<Vec<RoadQuality>> {
+    let ways = repo.select_road_quality(page, size).await?;
+    Ok(ways
+        .into_iter()
+        .map(|way| {
+            let name = network
+                .and_then(|network| network.name(way.way_id()))
+                .map(str::to_owned);
+            RoadQuality::new(way, name)
+        })
+        .collect())
 }
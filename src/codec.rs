@@ -0,0 +1,239 @@
+use std::{error::Error, io};
+
+use prost::Message;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    domain::{Agent, ProcessedAgent},
+    proto,
+};
+
+/// Encoding of the payloads published to a topic, and how it is told to the subscribers
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct Encoding {
+    #[serde(default)]
+    pub codec: Codec,
+    #[serde(default)]
+    pub content_type: ContentTypeSignal,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    #[default]
+    Json,
+    /// The `AgentData` and `ProcessedAgentData` messages of the gRPC API
+    Protobuf,
+    Cbor,
+    MessagePack,
+}
+
+/// How the codec of a payload is told to the subscribers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentTypeSignal {
+    /// Not told, the subscribers decode the payload by their own codec.
+    /// The only way understood by the subscribers from before the codecs were configurable
+    #[default]
+    None,
+    /// By a byte before the payload, see [`Codec::header`]
+    Header,
+    /// By the content type property of MQTT v5.
+    /// The subscribers connect by MQTT v5 to receive it if they are configured so as well
+    Property,
+}
+
+/// Data published over MQTT, with its Protobuf message
+pub trait Payload: Serialize + DeserializeOwned + Clone + Into<Self::Message> {
+    type Message: Message + Default;
+
+    fn try_from_message(message: Self::Message) -> Result<Self, Box<dyn Error + Send + Sync>>;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EncodeError {
+    #[error("Failed to encode the payload as JSON: {0}")]
+    Json(
+        #[from]
+        #[source]
+        serde_json::Error,
+    ),
+    #[error("Failed to encode the payload as CBOR: {0}")]
+    Cbor(
+        #[from]
+        #[source]
+        ciborium::ser::Error<io::Error>,
+    ),
+    #[error("Failed to encode the payload as MessagePack: {0}")]
+    MessagePack(
+        #[from]
+        #[source]
+        rmp_serde::encode::Error,
+    ),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeError {
+    #[error("Failed to decode the payload as JSON: {0}")]
+    Json(
+        #[from]
+        #[source]
+        serde_json::Error,
+    ),
+    #[error("Failed to decode the payload as Protobuf: {0}")]
+    Protobuf(
+        #[from]
+        #[source]
+        prost::DecodeError,
+    ),
+    #[error("Invalid Protobuf message: {0}")]
+    InvalidMessage(#[source] Box<dyn Error + Send + Sync>),
+    #[error("Failed to decode the payload as CBOR: {0}")]
+    Cbor(
+        #[from]
+        #[source]
+        ciborium::de::Error<io::Error>,
+    ),
+    #[error("Failed to decode the payload as MessagePack: {0}")]
+    MessagePack(
+        #[from]
+        #[source]
+        rmp_serde::decode::Error,
+    ),
+    #[error("Unknown content type: {0}")]
+    UnknownContentType(String),
+}
+
+impl Codec {
+    pub const ALL: [Self; 4] = [Self::Json, Self::Protobuf, Self::Cbor, Self::MessagePack];
+
+    /// Byte put before the payload to tell the codec.
+    /// Neither of the payloads encoded by the codecs starts with one of these bytes,
+    /// as each starts with an object, a map or the first field of a message,
+    /// so the payloads without a header are told apart
+    pub fn header(self) -> u8 {
+        match self {
+            Self::Json => 0x01,
+            Self::Protobuf => 0x02,
+            Self::Cbor => 0x03,
+            Self::MessagePack => 0x04,
+        }
+    }
+
+    pub fn from_header(header: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|codec| codec.header() == header)
+    }
+
+    /// Content type in the content type property of MQTT v5
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Protobuf => "application/x-protobuf",
+            Self::Cbor => "application/cbor",
+            Self::MessagePack => "application/msgpack",
+        }
+    }
+
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|codec| codec.content_type() == content_type)
+    }
+
+    /// Encodes the data, appending it to the buffer
+    pub fn encode_into<T: Payload>(
+        self,
+        data: &T,
+        buffer: &mut Vec<u8>,
+    ) -> Result<(), EncodeError> {
+        match self {
+            Self::Json => serde_json::to_writer(buffer, data)?,
+            Self::Protobuf => buffer.extend(data.clone().into().encode_to_vec()),
+            Self::Cbor => ciborium::into_writer(data, buffer)?,
+            // The fields are encoded by name, as the optional ones are skipped when absent
+            Self::MessagePack => {
+                data.serialize(&mut rmp_serde::Serializer::new(buffer).with_struct_map())?
+            }
+        }
+        Ok(())
+    }
+
+    pub fn encode<T: Payload>(self, data: &T) -> Result<Vec<u8>, EncodeError> {
+        let mut buffer = Vec::new();
+        self.encode_into(data, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    pub fn decode<T: Payload>(self, payload: &[u8]) -> Result<T, DecodeError> {
+        Ok(match self {
+            Self::Json => serde_json::from_slice(payload)?,
+            Self::Protobuf => T::try_from_message(T::Message::decode(payload)?)
+                .map_err(DecodeError::InvalidMessage)?,
+            Self::Cbor => ciborium::from_reader(payload)?,
+            Self::MessagePack => rmp_serde::from_slice(payload)?,
+        })
+    }
+}
+
+impl Payload for Agent {
+    type Message = proto::AgentData;
+
+    fn try_from_message(message: Self::Message) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(message.try_into()?)
+    }
+}
+
+impl Payload for ProcessedAgent {
+    type Message = proto::ProcessedAgentData;
+
+    fn try_from_message(message: Self::Message) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(message.try_into()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::domain::{Accelerometer, Gps, Motion, RoadState};
+
+    fn agent() -> Agent {
+        Agent::new(
+            Accelerometer::new(1.5, -2.25, 9800.0),
+            Gps::new(50.45.try_into().unwrap(), 30.52.try_into().unwrap()),
+            DateTime::from_timestamp_millis(1_717_243_200_123).unwrap(),
+        )
+        .with_idempotency_key(Some("agent:1".to_owned().try_into().unwrap()))
+        .with_agent_id(Some("AA1234BB".to_owned().try_into().unwrap()))
+    }
+
+    #[test]
+    fn each_codec_round_trips_the_data() {
+        let processed = ProcessedAgent::new(agent(), RoadState::Rough)
+            .with_motion(Motion::new(12.5, 90.0, 25.0))
+            .with_confidence(Some(0.75));
+        for codec in Codec::ALL {
+            let decoded: Agent = codec.decode(&codec.encode(&agent()).unwrap()).unwrap();
+            assert_eq!(decoded, agent(), "{codec:?}");
+
+            let decoded: ProcessedAgent = codec.decode(&codec.encode(&processed).unwrap()).unwrap();
+            assert_eq!(
+                serde_json::to_value(decoded).unwrap(),
+                serde_json::to_value(&processed).unwrap(),
+                "{codec:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn codecs_are_told_apart() {
+        for codec in Codec::ALL {
+            assert_eq!(Codec::from_header(codec.header()), Some(codec));
+            assert_eq!(Codec::from_content_type(codec.content_type()), Some(codec));
+            // The payloads without a header aren't mistaken for ones with it
+            let payload = codec.encode(&agent()).unwrap();
+            assert_eq!(Codec::from_header(payload[0]), None, "{codec:?}");
+        }
+    }
+}
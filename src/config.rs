@@ -11,6 +11,9 @@ use std::{
 use redis::{ConnectionInfo, IntoConnectionInfo, RedisResult};
 use serde::Deserialize;
 
+#[cfg(feature = "mqtt")]
+use crate::codec::Encoding;
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Server {
     host: Arc<str>,
@@ -23,6 +26,11 @@ pub struct Mqtt {
     #[serde(flatten)]
     server: Server,
     pub topic: Arc<str>,
+    /// Of the payloads published to the topic. The subscribers decode the payloads
+    /// by the codec told by the content type property or the header,
+    /// falling back to the configured codec, so that the publishers may switch one by one
+    #[serde(flatten)]
+    pub encoding: Encoding,
 }
 
 impl Server {
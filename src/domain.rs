@@ -38,22 +38,64 @@ pub struct Latitude(f64);
 #[cfg_attr(feature = "sqlx", derive(sqlx::Type), sqlx(transparent))]
 pub struct Longitude(f64);
 
-#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize, Constructor)]
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 #[cfg_attr(feature = "utoipa", derive(ToResponse, ToSchema))]
 pub struct Agent {
     accelerometer: Accelerometer,
     gps: Gps,
     timestamp: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(value_type = Option<String>, min_length = 1, max_length = 128))]
+    idempotency_key: Option<IdempotencyKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(value_type = Option<String>, min_length = 1, max_length = 64))]
+    agent_id: Option<AgentId>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Constructor)]
+/// Key identifying a sample, so that submitting it again doesn't create a duplicate,
+/// e.g. `<agent id>:<sequence number>` or a UUID generated by the agent
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Into)]
+#[serde(transparent)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type), sqlx(transparent))]
+pub struct IdempotencyKey(String);
+
+/// Identifier of the agent which took a sample, e.g. the registration number of the vehicle
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Into)]
+#[serde(transparent)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type), sqlx(transparent))]
+pub struct AgentId(String);
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[cfg_attr(feature = "utoipa", derive(ToResponse, ToSchema))]
 pub struct ProcessedAgent {
     #[serde(flatten)]
     agent_data: Agent,
     road_state: RoadState,
+    /// Absent for the first data of the agent, or if it doesn't follow the previous data in time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    motion: Option<Motion>,
+    /// Confidence of the classifier in the road state, from 0 to 1.
+    /// Absent if the classifier doesn't tell one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    confidence: Option<f64>,
+}
+
+/// Motion of the agent since its previous GPS fix
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize, Constructor)]
+#[cfg_attr(feature = "utoipa", derive(ToResponse, ToSchema))]
+pub struct Motion {
+    /// Average speed since the previous fix, in meters per second
+    speed_mps: f64,
+    /// Initial bearing from the previous fix, in degrees clockwise from the north, 0 to 360.
+    /// 0 if the agent didn't move
+    heading_deg: f64,
+    /// Great-circle distance from the previous fix, in meters
+    distance_m: f64,
 }
 
+/// Mean radius of the Earth used by the spherical approximations, in meters
+pub const MEAN_EARTH_RADIUS_M: f64 = 6_371_008.8;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
 #[cfg_attr(feature = "utoipa", derive(ToResponse, ToSchema))]
 #[cfg_attr(feature = "sqlx", derive(sqlx::Type), sqlx(type_name = "ROAD_STATE"))]
@@ -65,12 +107,69 @@ pub enum RoadState {
 }
 
 impl ProcessedAgent {
+    pub fn new(agent_data: Agent, road_state: RoadState) -> Self {
+        Self {
+            agent_data,
+            road_state,
+            motion: None,
+            confidence: None,
+        }
+    }
+
+    pub fn with_motion(self, motion: impl Into<Option<Motion>>) -> Self {
+        Self {
+            motion: motion.into(),
+            ..self
+        }
+    }
+
+    pub fn with_confidence(self, confidence: impl Into<Option<f64>>) -> Self {
+        Self {
+            confidence: confidence.into(),
+            ..self
+        }
+    }
+
     pub fn agent_data(&self) -> &Agent {
         &self.agent_data
     }
     pub fn road_state(&self) -> RoadState {
         self.road_state
     }
+    pub fn motion(&self) -> Option<Motion> {
+        self.motion
+    }
+    pub fn confidence(&self) -> Option<f64> {
+        self.confidence
+    }
+}
+
+impl Motion {
+    /// Motion from the `from` fix to the `to` one, `seconds` later
+    pub fn between(from: Gps, to: Gps, seconds: f64) -> Self {
+        let distance_m = from.distance_m(to);
+        Self {
+            speed_mps: distance_m / seconds,
+            heading_deg: if distance_m > 0.0 {
+                from.heading_deg(to)
+            } else {
+                0.0
+            },
+            distance_m,
+        }
+    }
+
+    pub fn speed_mps(&self) -> f64 {
+        self.speed_mps
+    }
+
+    pub fn heading_deg(&self) -> f64 {
+        self.heading_deg
+    }
+
+    pub fn distance_m(&self) -> f64 {
+        self.distance_m
+    }
 }
 
 impl Accelerometer {
@@ -95,9 +194,67 @@ impl Gps {
     pub fn latitude(&self) -> Latitude {
         self.latitude
     }
+
+    /// Great-circle distance to the position, in meters, by the haversine formula
+    pub fn distance_m(self, to: Gps) -> f64 {
+        let (lat1, lon1) = self.radians();
+        let (lat2, lon2) = to.radians();
+        let a = ((lat2 - lat1) / 2.0).sin().powi(2)
+            + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+        2.0 * MEAN_EARTH_RADIUS_M * a.sqrt().min(1.0).asin()
+    }
+
+    /// Initial bearing of the great circle to the position, in degrees clockwise
+    /// from the north, 0 to 360
+    pub fn heading_deg(self, to: Gps) -> f64 {
+        let (lat1, lon1) = self.radians();
+        let (lat2, lon2) = to.radians();
+        let y = (lon2 - lon1).sin() * lat2.cos();
+        let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * (lon2 - lon1).cos();
+        y.atan2(x).to_degrees().rem_euclid(360.0)
+    }
+
+    fn radians(self) -> (f64, f64) {
+        (
+            f64::from(self.latitude).to_radians(),
+            f64::from(self.longitude).to_radians(),
+        )
+    }
 }
 
 impl Agent {
+    pub fn new(accelerometer: Accelerometer, gps: Gps, timestamp: DateTime<Utc>) -> Self {
+        Self {
+            accelerometer,
+            gps,
+            timestamp,
+            idempotency_key: None,
+            agent_id: None,
+        }
+    }
+
+    pub fn with_idempotency_key(self, idempotency_key: impl Into<Option<IdempotencyKey>>) -> Self {
+        Self {
+            idempotency_key: idempotency_key.into(),
+            ..self
+        }
+    }
+
+    pub fn with_agent_id(self, agent_id: impl Into<Option<AgentId>>) -> Self {
+        Self {
+            agent_id: agent_id.into(),
+            ..self
+        }
+    }
+
+    /// Replaces the accelerometer sample, keeping the rest of the data
+    pub fn with_accelerometer(self, accelerometer: Accelerometer) -> Self {
+        Self {
+            accelerometer,
+            ..self
+        }
+    }
+
     pub fn accelerometer(&self) -> Accelerometer {
         self.accelerometer
     }
@@ -109,6 +266,28 @@ impl Agent {
     pub fn timestamp(&self) -> DateTime<Utc> {
         self.timestamp
     }
+
+    pub fn idempotency_key(&self) -> Option<&IdempotencyKey> {
+        self.idempotency_key.as_ref()
+    }
+
+    pub fn agent_id(&self) -> Option<&AgentId> {
+        self.agent_id.as_ref()
+    }
+}
+
+impl IdempotencyKey {
+    pub const MAX_LEN: usize = 128;
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for IdempotencyKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
 }
 
 impl<'de> Deserialize<'de> for Latitude {
@@ -120,6 +299,38 @@ impl<'de> Deserialize<'de> for Latitude {
     }
 }
 
+impl<'de> Deserialize<'de> for IdempotencyKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        try_from_deserialize::<_, _, String>(deserializer)
+    }
+}
+
+impl AgentId {
+    pub const MAX_LEN: usize = 64;
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for AgentId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for AgentId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        try_from_deserialize::<_, _, String>(deserializer)
+    }
+}
+
 impl<'de> Deserialize<'de> for Longitude {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -171,6 +382,38 @@ impl TryFrom<f64> for Longitude {
 #[error("longitude must be in range -180..180")]
 pub struct InvalidLongitudeError;
 
+impl TryFrom<String> for IdempotencyKey {
+    type Error = InvalidIdempotencyKeyError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if (1..=Self::MAX_LEN).contains(&value.len()) {
+            Ok(IdempotencyKey(value))
+        } else {
+            Err(InvalidIdempotencyKeyError)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("idempotency key must be 1 to 128 bytes long")]
+pub struct InvalidIdempotencyKeyError;
+
+impl TryFrom<String> for AgentId {
+    type Error = InvalidAgentIdError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if (1..=Self::MAX_LEN).contains(&value.len()) {
+            Ok(AgentId(value))
+        } else {
+            Err(InvalidAgentIdError)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("agent ID must be 1 to 64 bytes long")]
+pub struct InvalidAgentIdError;
+
 #[cfg(feature = "tonic")]
 impl From<proto::AccelerometerData> for Accelerometer {
     fn from(data: proto::AccelerometerData) -> Self {
@@ -234,7 +477,17 @@ impl TryFrom<proto::AgentData> for Agent {
             .timestamp
             .ok_or(InvalidAgentDataError::MissingTimestamp)?
             .try_into()?;
-        Ok(Self::new(accelerometer, gps, timestamp))
+        let idempotency_key = Some(value.idempotency_key)
+            .filter(|key| !key.is_empty())
+            .map(IdempotencyKey::try_from)
+            .transpose()?;
+        let agent_id = Some(value.agent_id)
+            .filter(|agent_id| !agent_id.is_empty())
+            .map(AgentId::try_from)
+            .transpose()?;
+        Ok(Self::new(accelerometer, gps, timestamp)
+            .with_idempotency_key(idempotency_key)
+            .with_agent_id(agent_id))
     }
 }
 
@@ -259,6 +512,18 @@ pub enum InvalidAgentDataError {
         #[source]
         InvalidGpsDataError,
     ),
+    #[error("Invalid idempotency key: {0}")]
+    InvalidIdempotencyKey(
+        #[from]
+        #[source]
+        InvalidIdempotencyKeyError,
+    ),
+    #[error("Invalid agent ID: {0}")]
+    InvalidAgentId(
+        #[from]
+        #[source]
+        InvalidAgentIdError,
+    ),
 }
 
 #[cfg(feature = "tonic")]
@@ -291,7 +556,9 @@ impl TryFrom<proto::ProcessedAgentData> for ProcessedAgent {
             .agent
             .ok_or(InvalidProcessedAgentDataError::MissingAgentData)?;
         let agent_data = Agent::try_from(agent_data)?;
-        Ok(Self::new(agent_data, road_state))
+        Ok(Self::new(agent_data, road_state)
+            .with_motion(value.motion.map(Into::into))
+            .with_confidence(value.confidence))
     }
 }
 
@@ -328,6 +595,32 @@ impl From<ProcessedAgent> for proto::ProcessedAgentData {
         Self {
             agent: Some(value.agent_data.into()),
             road_state: value.road_state as i32,
+            motion: value.motion.map(Into::into),
+            confidence: value.confidence,
+        }
+    }
+}
+
+#[cfg(feature = "tonic")]
+impl From<proto::MotionData> for Motion {
+    fn from(data: proto::MotionData) -> Self {
+        Self::new(data.speed_mps, data.heading_deg, data.distance_m)
+    }
+}
+
+#[cfg(feature = "tonic")]
+impl From<Motion> for proto::MotionData {
+    fn from(
+        Motion {
+            speed_mps,
+            heading_deg,
+            distance_m,
+        }: Motion,
+    ) -> Self {
+        Self {
+            speed_mps,
+            heading_deg,
+            distance_m,
         }
     }
 }
@@ -339,6 +632,8 @@ impl From<Agent> for proto::AgentData {
             accelerometer: Some(value.accelerometer.into()),
             gps: Some(value.gps.into()),
             timestamp: Some(value.timestamp.into()),
+            idempotency_key: value.idempotency_key.map(Into::into).unwrap_or_default(),
+            agent_id: value.agent_id.map(Into::into).unwrap_or_default(),
         }
     }
 }
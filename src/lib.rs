@@ -7,6 +7,8 @@ use tracing_subscriber::{
     EnvFilter,
 };
 
+#[cfg(feature = "codec")]
+pub mod codec;
 pub mod config;
 pub mod domain;
 #[cfg(feature = "mqtt")]
@@ -1,15 +1,26 @@
-use mqtt::ConnectOptionsBuilder;
+use mqtt::{ConnectOptionsBuilder, Message, MessageBuilder, Properties, PropertyCode};
 use tracing::instrument;
 
-use crate::{config::Mqtt, reclone};
+use crate::{
+    codec::{Codec, ContentTypeSignal, DecodeError, EncodeError, Encoding, Payload},
+    config::Mqtt,
+    reclone,
+};
 
 #[instrument]
 pub async fn connect(config: Mqtt) -> mqtt::Result<mqtt::AsyncClient> {
     let client = mqtt::AsyncClient::new(&config)?;
 
+    // The content type property is only sent and received over MQTT v5
+    let connect_options = match config.encoding.content_type {
+        ContentTypeSignal::Property => ConnectOptionsBuilder::new_v5().finalize(),
+        ContentTypeSignal::None | ContentTypeSignal::Header => {
+            ConnectOptionsBuilder::new().finalize()
+        }
+    };
     client
         .connect_with_callbacks(
-            ConnectOptionsBuilder::new().finalize(),
+            connect_options,
             {
                 reclone!(config);
                 move |_, _| {
@@ -32,3 +43,119 @@ pub async fn connect(config: Mqtt) -> mqtt::Result<mqtt::AsyncClient> {
 
     Ok(client)
 }
+
+impl Encoding {
+    /// Encodes the data into a message to the topic, telling the codec as configured
+    pub fn message<T: Payload>(&self, topic: &str, data: &T) -> Result<Message, EncodeError> {
+        let mut payload = Vec::new();
+        let mut properties = Properties::new();
+        match self.content_type {
+            ContentTypeSignal::None => {}
+            ContentTypeSignal::Header => payload.push(self.codec.header()),
+            ContentTypeSignal::Property => properties
+                .push_string(PropertyCode::ContentType, self.codec.content_type())
+                .expect("content type is a string property"),
+        }
+        self.codec.encode_into(data, &mut payload)?;
+        Ok(MessageBuilder::new()
+            .topic(topic)
+            .payload(payload)
+            .qos(mqtt::QOS_0)
+            .properties(properties)
+            .finalize())
+    }
+
+    /// Decodes the payload of the message by the codec told by its content type property,
+    /// else by its header, else by the configured codec
+    pub fn decode<T: Payload>(&self, message: &Message) -> Result<T, DecodeError> {
+        let payload = message.payload();
+        if let Some(content_type) = message.properties().get_string(PropertyCode::ContentType) {
+            return Codec::from_content_type(&content_type)
+                .ok_or(DecodeError::UnknownContentType(content_type))?
+                .decode(payload);
+        }
+        let header = payload
+            .split_first()
+            .and_then(|(&header, rest)| Some((Codec::from_header(header)?, rest)));
+        match header {
+            Some((codec, payload)) => codec.decode(payload),
+            None => self.codec.decode(payload),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::domain::{Accelerometer, Agent, Gps};
+
+    fn agent() -> Agent {
+        Agent::new(
+            Accelerometer::new(0.0, 0.0, 9800.0),
+            Gps::new(50.45.try_into().unwrap(), 30.52.try_into().unwrap()),
+            DateTime::UNIX_EPOCH,
+        )
+    }
+
+    fn encoding(codec: Codec, content_type: ContentTypeSignal) -> Encoding {
+        Encoding {
+            codec,
+            content_type,
+        }
+    }
+
+    #[test]
+    fn content_type_property_is_preferred_to_the_header() {
+        let message = encoding(Codec::Cbor, ContentTypeSignal::Property)
+            .message("topic", &agent())
+            .unwrap();
+        let decoded: Agent = encoding(Codec::Json, ContentTypeSignal::Header)
+            .decode(&message)
+            .unwrap();
+        assert_eq!(decoded, agent());
+
+        // The payload is not read for a header once the property tells the codec
+        let mut payload = vec![Codec::Json.header()];
+        payload.extend(Codec::Json.encode(&agent()).unwrap());
+        let mut properties = Properties::new();
+        properties
+            .push_string(PropertyCode::ContentType, "text/plain")
+            .unwrap();
+        let message = MessageBuilder::new()
+            .topic("topic")
+            .payload(payload)
+            .properties(properties)
+            .finalize();
+        assert!(matches!(
+            encoding(Codec::Json, ContentTypeSignal::Header).decode::<Agent>(&message),
+            Err(DecodeError::UnknownContentType(content_type)) if content_type == "text/plain"
+        ));
+    }
+
+    #[test]
+    fn header_is_preferred_to_the_configured_codec() {
+        let message = encoding(Codec::MessagePack, ContentTypeSignal::Header)
+            .message("topic", &agent())
+            .unwrap();
+        let decoded: Agent = encoding(Codec::Json, ContentTypeSignal::None)
+            .decode(&message)
+            .unwrap();
+        assert_eq!(decoded, agent());
+    }
+
+    #[test]
+    fn payload_without_a_signal_is_decoded_by_the_configured_codec() {
+        let message = encoding(Codec::Protobuf, ContentTypeSignal::None)
+            .message("topic", &agent())
+            .unwrap();
+        let decoded: Agent = encoding(Codec::Protobuf, ContentTypeSignal::None)
+            .decode(&message)
+            .unwrap();
+        assert_eq!(decoded, agent());
+        assert!(encoding(Codec::Json, ContentTypeSignal::None)
+            .decode::<Agent>(&message)
+            .is_err());
+    }
+}